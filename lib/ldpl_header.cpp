@@ -1,6 +1,8 @@
 /* -- LDPL 4.4 -- */
 
+#include <algorithm>
 #include <chrono>
+#include <errno.h>
 #include <fstream>
 #include <iostream>
 #include <limits.h>
@@ -11,6 +13,7 @@
 #include <string>
 #include <thread>
 #include <time.h>
+#include <tuple>
 #include <unordered_map>
 #include <vector>
 
@@ -118,9 +121,11 @@ class chText {
     }
 
   public:
-    size_t size() { return buffer.size(); }
+    size_t size() const { return buffer.size(); }
     bool empty() { return buffer.empty(); }
     size_t length() { return size(); }
+    // Reverse in place, by code point
+    void reverse() { std::reverse(buffer.begin(), buffer.end()); }
     string str_rep() {
         stringRep = "";
         for(size_t i = 0; i < size(); ++i) { stringRep += buffer[i]; }
@@ -467,6 +472,8 @@ chText VAR_ERRORTEXT      = "";
 chText to_ldpl_string(double x);
 chText trimCopy(chText _line);
 
+// Collections hold their elements by value, nested collections included,
+// so assigning inner_collection (COPY) is always a deep copy.
 #ifndef LDPLMAP
 #define LDPLMAP
 template <typename T> struct ldpl_map {
@@ -497,6 +504,55 @@ template <typename T> struct ldpl_list {
 };
 #endif LDPLMAP
 
+// Swap list[from, to) for the elements of `with`.
+template <typename T>
+void replace_range(ldpl_list<T>& list, ldpl_number from, ldpl_number to,
+                   ldpl_list<T>& with) {
+    from = floor(from);
+    to   = floor(to);
+    if(from < 0 || to < from || to > list.inner_collection.size()) {
+        cerr << "Runtime Error: RANGE " << from << " TO " << to
+             << " out of range [0, " << list.inner_collection.size() << "]"
+             << endl;
+        exit(1);
+    }
+    vector<T> elements = with.inner_collection; // `with` may be `list`
+    auto start = list.inner_collection.begin() + (size_t)from;
+    start      = list.inner_collection.erase(start, start + (size_t)(to - from));
+    list.inner_collection.insert(start, elements.begin(), elements.end());
+}
+
+// Put `value` before list[at]. `at` may be the list's size to append.
+template <typename T, typename V>
+void insert_at(ldpl_list<T>& list, ldpl_number at, V value) {
+    at = floor(at);
+    if(at < 0 || at > list.inner_collection.size()) {
+        cerr << "Runtime Error: INSERT index " << at << " out of range [0, "
+             << list.inner_collection.size() << "]" << endl;
+        exit(1);
+    }
+    list.inner_collection.insert(list.inner_collection.begin() + (size_t)at,
+                                 T(value));
+}
+
+template <typename T> void delete_at(ldpl_list<T>& list, ldpl_number at) {
+    at = floor(at);
+    if(at < 0 || at >= list.inner_collection.size()) {
+        cerr << "Runtime Error: DELETE index " << at << " out of range [0, "
+             << list.inner_collection.size() << ")" << endl;
+        exit(1);
+    }
+    list.inner_collection.erase(list.inner_collection.begin() + (size_t)at);
+}
+
+template <typename T> bool has_key(ldpl_map<T>& map, chText key) {
+    return map.inner_collection.count(key.str_rep()) > 0;
+}
+
+template <typename T> bool has_key(ldpl_map<T>& map, ldpl_number key) {
+    return map.inner_collection.count(to_ldpl_string(key).str_rep()) > 0;
+}
+
 template <typename T>
 void get_indices(ldpl_list<chText>& dest, ldpl_vector<T>& source) {
     dest.inner_collection.clear();
@@ -507,6 +563,36 @@ void get_indices(ldpl_list<chText>& dest, ldpl_vector<T>& source) {
     }
 }
 
+// Number map keys: numeric ones ("2", "10") come first in numeric
+// order, then the rest in byte order.
+bool key_less(const string& a, const string& b) {
+    char *end_a, *end_b;
+    double x = strtod(a.c_str(), &end_a);
+    double y = strtod(b.c_str(), &end_b);
+    bool a_num = !a.empty() && *end_a == '\0';
+    bool b_num = !b.empty() && *end_b == '\0';
+    if(a_num && b_num && x != y) return x < y;
+    if(a_num != b_num) return a_num;
+    return a < b;
+}
+
+// Keys of `source` in order: by key_less() when `numeric`, otherwise
+// in plain byte order.
+template <typename T>
+void get_sorted_indices(ldpl_list<chText>& dest, ldpl_vector<T>& source, bool numeric) {
+    vector<string> keys;
+    for(const auto& keyPair : source.inner_collection) {
+        keys.push_back(keyPair.first);
+    }
+    if(numeric) {
+        sort(keys.begin(), keys.end(), key_less);
+    } else {
+        sort(keys.begin(), keys.end());
+    }
+    dest.inner_collection.clear();
+    for(const auto& key : keys) { dest.inner_collection.push_back(key); }
+}
+
 ldpl_number input_number() {
     string s = "";
     while(true) {
@@ -538,12 +624,275 @@ ldpl_number to_number(chText& textNumber) {
     return to_number(textNumber.str_rep());
 }
 
+// Key of the biggest (or smallest) value in a number map. Ties go to the
+// smallest key so the result doesn't depend on hash order.
+void get_key_of_extreme(ldpl_map<ldpl_number>& source, chText& dest,
+                        bool want_max) {
+    if(source.inner_collection.empty()) {
+        dest          = "";
+        VAR_ERRORTEXT = "The map is empty.";
+        VAR_ERRORCODE = 1;
+        return;
+    }
+    auto best = source.inner_collection.begin();
+    for(auto it = best; it != source.inner_collection.end(); ++it) {
+        bool better = want_max ? it->second > best->second
+                               : it->second < best->second;
+        if(better || (it->second == best->second && it->first < best->first))
+            best = it;
+    }
+    dest          = best->first;
+    VAR_ERRORTEXT = "";
+    VAR_ERRORCODE = 0;
+}
+
+void get_key_of_extreme(ldpl_map<ldpl_number>& source, ldpl_number& dest,
+                        bool want_max) {
+    chText key;
+    get_key_of_extreme(source, key, want_max);
+    dest = to_number(key);
+}
+
+// Length in code points of the longest (or shortest) value in a text map.
+// An empty map gives 0.
+ldpl_number get_value_length(ldpl_map<chText>& source, bool want_max) {
+    if(source.inner_collection.empty()) return 0;
+    size_t best = source.inner_collection.begin()->second.size();
+    for(auto& kv : source.inner_collection) {
+        size_t len = kv.second.size();
+        if(want_max ? len > best : len < best) best = len;
+    }
+    return best;
+}
+
+// SERIALIZE: JSON-ish text for a number, text, or (nested) collection.
+// Map keys are sorted so the output doesn't depend on hash order.
+template <typename T> string ldpl_serialize(ldpl_list<T>& source);
+template <typename T> string ldpl_serialize(ldpl_map<T>& source);
+
+string ldpl_serialize(ldpl_number source) {
+    return to_ldpl_string(source).str_rep();
+}
+
+string ldpl_serialize(chText source) {
+    string out = "\"";
+    for(unsigned char c : source.str_rep()) {
+        switch(c) {
+            case '"': out += "\\\""; break;
+            case '\\': out += "\\\\"; break;
+            case '\n': out += "\\n"; break;
+            case '\r': out += "\\r"; break;
+            case '\t': out += "\\t"; break;
+            default:
+                if(c < 0x20) {
+                    out += "\\u00";
+                    out += "0123456789abcdef"[c >> 4];
+                    out += "0123456789abcdef"[c & 15];
+                } else {
+                    out += c;
+                }
+        }
+    }
+    return out + "\"";
+}
+
+template <typename T> string ldpl_serialize(ldpl_list<T>& source) {
+    string out = "[";
+    for(size_t i = 0; i < source.inner_collection.size(); ++i) {
+        if(i > 0) out += ", ";
+        out += ldpl_serialize(source.inner_collection[i]);
+    }
+    return out + "]";
+}
+
+template <typename T> string ldpl_serialize(ldpl_map<T>& source) {
+    vector<string> keys;
+    for(auto& kv : source.inner_collection) keys.push_back(kv.first);
+    sort(keys.begin(), keys.end());
+    string out = "{";
+    for(size_t i = 0; i < keys.size(); ++i) {
+        if(i > 0) out += ", ";
+        out += ldpl_serialize(chText(keys[i])) + ": ";
+        out += ldpl_serialize(source.inner_collection[keys[i]]);
+    }
+    return out + "}";
+}
+
+// DESERIALIZE: reads what SERIALIZE writes back into a collection. Each
+// ldpl_unserialize() parses one value at s[i], moves i past it, and
+// returns false if the text doesn't fit the destination's type.
+template <typename T> bool ldpl_unserialize(const string& s, size_t& i, ldpl_list<T>& dest);
+template <typename T> bool ldpl_unserialize(const string& s, size_t& i, ldpl_map<T>& dest);
+
+// Skips whitespace, then checks for (and eats) `c`.
+bool ldpl_unserialize_char(const string& s, size_t& i, char c) {
+    while(i < s.size() && isspace((unsigned char)s[i])) ++i;
+    if(i >= s.size() || s[i] != c) return false;
+    ++i;
+    return true;
+}
+
+bool ldpl_unserialize(const string& s, size_t& i, ldpl_number& dest) {
+    while(i < s.size() && isspace((unsigned char)s[i])) ++i;
+    size_t start = i;
+    while(i < s.size() && strchr("+-.0123456789eE", s[i])) ++i;
+    if(start == i || (s[start] != '-' && !isdigit((unsigned char)s[start])))
+        return false;
+    string num = s.substr(start, i - start);
+    char* end;
+    dest = strtod(num.c_str(), &end);
+    return *end == '\0';
+}
+
+// Appends code point `cp` to `out` as UTF-8.
+void ldpl_append_utf8(string& out, unsigned long cp) {
+    if(cp < 0x80) {
+        out += (char)cp;
+    } else if(cp < 0x800) {
+        out += (char)(0xC0 | (cp >> 6));
+        out += (char)(0x80 | (cp & 0x3F));
+    } else if(cp < 0x10000) {
+        out += (char)(0xE0 | (cp >> 12));
+        out += (char)(0x80 | ((cp >> 6) & 0x3F));
+        out += (char)(0x80 | (cp & 0x3F));
+    } else {
+        out += (char)(0xF0 | (cp >> 18));
+        out += (char)(0x80 | ((cp >> 12) & 0x3F));
+        out += (char)(0x80 | ((cp >> 6) & 0x3F));
+        out += (char)(0x80 | (cp & 0x3F));
+    }
+}
+
+// Reads the XXXX of a \uXXXX escape at s[i].
+bool ldpl_unserialize_hex4(const string& s, size_t& i, unsigned long& cp) {
+    if(i + 4 > s.size()) return false;
+    for(size_t j = 0; j < 4; ++j)
+        if(!isxdigit((unsigned char)s[i + j])) return false;
+    cp = strtoul(s.substr(i, 4).c_str(), nullptr, 16);
+    i += 4;
+    return true;
+}
+
+bool ldpl_unserialize(const string& s, size_t& i, chText& dest) {
+    if(!ldpl_unserialize_char(s, i, '"')) return false;
+    string out;
+    while(i < s.size() && s[i] != '"') {
+        char c = s[i++];
+        if(c != '\\') {
+            out += c;
+            continue;
+        }
+        if(i >= s.size()) return false;
+        unsigned long cp;
+        switch(s[i++]) {
+            case '"': out += '"'; break;
+            case '\\': out += '\\'; break;
+            case '/': out += '/'; break;
+            case 'b': out += '\b'; break;
+            case 'f': out += '\f'; break;
+            case 'n': out += '\n'; break;
+            case 'r': out += '\r'; break;
+            case 't': out += '\t'; break;
+            case 'u':
+                if(!ldpl_unserialize_hex4(s, i, cp)) return false;
+                // a surrogate pair is one code point
+                if(cp >= 0xD800 && cp < 0xDC00) {
+                    unsigned long low;
+                    if(s.compare(i, 2, "\\u") != 0) return false;
+                    i += 2;
+                    if(!ldpl_unserialize_hex4(s, i, low)) return false;
+                    if(low < 0xDC00 || low > 0xDFFF) return false;
+                    cp = 0x10000 + ((cp - 0xD800) << 10) + (low - 0xDC00);
+                }
+                ldpl_append_utf8(out, cp);
+                break;
+            default: return false;
+        }
+    }
+    if(i >= s.size()) return false;
+    ++i;
+    dest = out;
+    return true;
+}
+
+template <typename T>
+bool ldpl_unserialize(const string& s, size_t& i, ldpl_list<T>& dest) {
+    if(!ldpl_unserialize_char(s, i, '[')) return false;
+    if(ldpl_unserialize_char(s, i, ']')) return true;
+    do {
+        T value;
+        if(!ldpl_unserialize(s, i, value)) return false;
+        dest.inner_collection.push_back(value);
+    } while(ldpl_unserialize_char(s, i, ','));
+    return ldpl_unserialize_char(s, i, ']');
+}
+
+template <typename T>
+bool ldpl_unserialize(const string& s, size_t& i, ldpl_map<T>& dest) {
+    if(!ldpl_unserialize_char(s, i, '{')) return false;
+    if(ldpl_unserialize_char(s, i, '}')) return true;
+    do {
+        chText key;
+        T value;
+        if(!ldpl_unserialize(s, i, key) || !ldpl_unserialize_char(s, i, ':') ||
+           !ldpl_unserialize(s, i, value))
+            return false;
+        dest.inner_collection[key.str_rep()] = value;
+    } while(ldpl_unserialize_char(s, i, ','));
+    return ldpl_unserialize_char(s, i, '}');
+}
+
+// Sets ERRORCODE and leaves `dest` alone if `source` is malformed.
+template <typename T> void ldpl_deserialize(chText source, T& dest) {
+    string s = source.str_rep();
+    size_t i = 0;
+    T result;
+    bool ok = ldpl_unserialize(s, i, result);
+    while(ok && i < s.size() && isspace((unsigned char)s[i])) ++i;
+    if(!ok || i < s.size()) {
+        VAR_ERRORTEXT = "Can't DESERIALIZE, malformed input at character " +
+                        to_string(i + 1) + ".";
+        VAR_ERRORCODE = 1;
+        return;
+    }
+    dest          = result;
+    VAR_ERRORTEXT = "";
+    VAR_ERRORCODE = 0;
+}
+
+// Adds an argument to a MEMOIZE cache key. Parts are length-prefixed so
+// ("a", "bc") and ("ab", "c") get different keys.
+void memo_key_add(string& key, string part) {
+    key += to_string(part.size()) + ":" + part;
+}
+
 string input_string() {
     string s = "";
     getline(cin, s);
     return s;
 }
 
+// Like input_string(), but gives `fallback` on an empty line or EOF.
+chText input_string_or(chText fallback) {
+    string s = "";
+    if(!getline(cin, s) || s.empty()) return fallback;
+    return s;
+}
+
+// Like input_number(), but gives `fallback` on an empty line or EOF.
+ldpl_number input_number_or(ldpl_number fallback) {
+    string s = "";
+    while(true) {
+        if(!getline(cin, s) || s.empty()) return fallback;
+        try {
+            ldpl_number num = stod(s);
+            return num;
+        } catch(const invalid_argument& ia) {
+            cout << "Redo from start: " << flush;
+        }
+    }
+}
+
 string input_until_eof() {
     stringstream full;
     string s       = "";
@@ -596,24 +945,78 @@ ldpl_number get_char_num(chText chr) {
     return ord;
 }
 
+// Character at `pos`, counting from the end when negative (-1 is the
+// last one). Out of range gives "", like SUBSTRING past the end.
 chText charat(const chText& s, ldpl_number pos) {
-    unsigned int _pos = floor(pos);
-    return s[pos];
+    pos = floor(pos);
+    if(pos < 0) pos += s.size();
+    if(pos < 0 || pos >= s.size()) return "";
+    return s[(size_t)pos];
+}
+
+// `x` rounded to exactly `decimals` digits after the point.
+string to_fixed_string(ldpl_number x, ldpl_number decimals) {
+    ostringstream out;
+    out.precision(decimals < 0 ? 0 : (int)decimals);
+    out << fixed << x;
+    return out.str();
 }
 
 // Convert ldpl_number to LDPL string, killing trailing 0's
 // https://stackoverflow.com/questions/16605967/ &
 // https://stackoverflow.com/questions/13686482/
 chText to_ldpl_string(ldpl_number x) {
-    ostringstream out;
-    out.precision(10);
-    out << fixed << x;
-    string str = out.str();
+    string str = to_fixed_string(x, 10);
     str.erase(str.find_last_not_of('0') + 1, string::npos);
     str.erase(str.find_last_not_of('.') + 1, string::npos);
     return str;
 }
 
+// A 0..1 ratio as a percentage like "42.5%". Ratios outside 0..1 are
+// clamped. Negative `decimals` means as many as needed.
+chText format_percent(ldpl_number ratio, ldpl_number decimals) {
+    if(ratio < 0) ratio = 0;
+    if(ratio > 1) ratio = 1;
+    ldpl_number percent = ratio * 100;
+    string str = decimals < 0 ? to_ldpl_string(percent).str_rep()
+                              : to_fixed_string(percent, decimals);
+    return str + "%";
+}
+
+// Parses `text` as a whole number written in base `radix` (2 to 36),
+// like "ff" in 16 or "-101" in 2. Bad input sets ERRORCODE and gives 0.
+ldpl_number parse_radix(chText text, ldpl_number radix) {
+    string s = text.str_rep();
+    int base = floor(radix);
+    VAR_ERRORCODE = 1;
+    if(base < 2 || base > 36) {
+        VAR_ERRORTEXT = "Can't PARSE NUMBER, radix " + to_ldpl_string(radix).str_rep() +
+                        " isn't between 2 and 36.";
+        return 0;
+    }
+    if(s.empty()) {
+        VAR_ERRORTEXT = "Can't PARSE NUMBER, there are no digits.";
+        return 0;
+    }
+    errno = 0;
+    char* end;
+    long long n = strtoll(s.c_str(), &end, base);
+    if(*end != '\0' || isspace(s[0])) {
+        size_t at = isspace(s[0]) ? 0 : end - s.c_str();
+        VAR_ERRORTEXT = "Can't PARSE NUMBER, invalid digit at character " +
+                        to_ldpl_string(at + 1).str_rep() + " for radix " +
+                        to_ldpl_string(base).str_rep() + ".";
+        return 0;
+    }
+    if(errno == ERANGE) {
+        VAR_ERRORTEXT = "Can't PARSE NUMBER, " + s + " is out of range.";
+        return 0;
+    }
+    VAR_ERRORCODE = 0;
+    VAR_ERRORTEXT = "";
+    return n;
+}
+
 #include <array>
 #include <cstdio>
 #include <memory>
@@ -631,16 +1034,73 @@ chText exec(const char* cmd) {
     return result;
 }
 
+#if !defined(_WIN32)
+#include <signal.h>
+#include <sys/wait.h>
+#include <unistd.h>
+#endif
+
+// Runs `cmd` like system() and gives its exit code, but kills it (and
+// anything it started) after `ms` milliseconds and gives -1 instead.
+// Windows has no fork(), so there the command just runs to the end.
+ldpl_number exec_with_timeout(const char* cmd, ldpl_number ms) {
+#if defined(_WIN32)
+    return system(cmd);
+#else
+    fflush(stdout);
+    pid_t pid = fork();
+    if(pid < 0) return -1;
+    if(pid == 0) {
+        setpgid(0, 0);
+        execl("/bin/sh", "sh", "-c", cmd, (char*)NULL);
+        _exit(127);
+    }
+    setpgid(pid, pid);
+    auto deadline = chrono::steady_clock::now() + chrono::milliseconds((long long)ms);
+    int status;
+    while(waitpid(pid, &status, WNOHANG) == 0) {
+        if(chrono::steady_clock::now() >= deadline) {
+            kill(-pid, SIGKILL);
+            waitpid(pid, &status, 0);
+            return -1;
+        }
+        this_thread::sleep_for(chrono::milliseconds(5));
+    }
+    return WIFEXITED(status) ? WEXITSTATUS(status) : -1;
+#endif
+}
+
 #include <random>
 
+// One generator for everything random, so SEED RANDOM makes a whole
+// program repeatable.
+mt19937& ldpl_rng() {
+    static mt19937 rng(random_device{}());
+    return rng;
+}
+
 ldpl_number get_random() {
-    random_device rd;
-    mt19937 mt(rd());
     uniform_real_distribution<double> dist(0.0, 1.0);
-    ldpl_number r = (ldpl_number)dist(mt);
+    ldpl_number r = (ldpl_number)dist(ldpl_rng());
     return r;
 }
 
+// Whole number from `from` to `to`, both included. The bounds can come
+// in either order. If no whole number fits, sets ERRORCODE and returns
+// `from`.
+ldpl_number get_random(ldpl_number from, ldpl_number to) {
+    long long lo = ceil(min(from, to)), hi = floor(max(from, to));
+    if(lo > hi) {
+        VAR_ERRORTEXT = "There's no whole number between the bounds.";
+        VAR_ERRORCODE = 1;
+        return from;
+    }
+    uniform_int_distribution<long long> dist(lo, hi);
+    VAR_ERRORTEXT = "";
+    VAR_ERRORCODE = 0;
+    return dist(ldpl_rng());
+}
+
 string expandHomeDirectory(string filename) {
 #if defined(_WIN32)
     return filename;
@@ -660,6 +1120,196 @@ string expandHomeDirectory(string filename) {
 #endif
 }
 
+#if defined(__APPLE__)
+#include <mach-o/dyld.h>
+#elif defined(_WIN32)
+#define NOMINMAX
+#include <windows.h>
+#else
+#include <unistd.h>
+#endif
+
+// Directory the running binary lives in, without a trailing slash.
+// Sets ERRORCODE and gives "" where the platform can't tell us.
+chText get_program_dir() {
+    string path;
+#if defined(__APPLE__)
+    uint32_t size = 0;
+    _NSGetExecutablePath(NULL, &size);
+    vector<char> buf(size + 1);
+    if(_NSGetExecutablePath(buf.data(), &size) == 0) path = buf.data();
+#elif defined(_WIN32)
+    char buf[MAX_PATH];
+    DWORD len = GetModuleFileNameA(NULL, buf, MAX_PATH);
+    if(len > 0 && len < MAX_PATH) path = string(buf, len);
+#elif defined(__linux__)
+    char buf[PATH_MAX];
+    ssize_t len = readlink("/proc/self/exe", buf, sizeof(buf));
+    if(len > 0) path = string(buf, len);
+#endif
+    size_t slash = path.find_last_of("/\\");
+    if(slash == string::npos) {
+        VAR_ERRORTEXT = "Can't GET PROGRAM DIRECTORY on this platform.";
+        VAR_ERRORCODE = 1;
+        return "";
+    }
+    VAR_ERRORTEXT = "";
+    VAR_ERRORCODE = 0;
+    return path.substr(0, slash == 0 ? 1 : slash);
+}
+
+// Seconds, with a fractional part, since some fixed point in the past.
+// Only good for measuring time between two calls, but never jumps when
+// the wall clock is changed.
+ldpl_number get_monotonic_time() {
+    auto since = chrono::steady_clock::now().time_since_epoch();
+    return chrono::duration<double>(since).count();
+}
+
+// Bytes of physical memory. Sets ERRORCODE and gives 0 where the
+// platform can't tell us.
+ldpl_number get_total_memory() {
+    double total = 0;
+#if defined(_WIN32)
+    MEMORYSTATUSEX status;
+    status.dwLength = sizeof(status);
+    if(GlobalMemoryStatusEx(&status)) total = status.ullTotalPhys;
+#elif defined(_SC_PHYS_PAGES) && defined(_SC_PAGE_SIZE)
+    long pages = sysconf(_SC_PHYS_PAGES), size = sysconf(_SC_PAGE_SIZE);
+    if(pages > 0 && size > 0) total = (double)pages * size;
+#endif
+    if(total <= 0) {
+        VAR_ERRORTEXT = "Can't GET TOTAL MEMORY on this platform.";
+        VAR_ERRORCODE = 1;
+        return 0;
+    }
+    VAR_ERRORTEXT = "";
+    VAR_ERRORCODE = 0;
+    return total;
+}
+
+// Number of CPUs the program can run on. Sets ERRORCODE and gives 0
+// where the platform can't tell us.
+ldpl_number get_cpu_count() {
+    unsigned int count = thread::hardware_concurrency();
+    if(count == 0) {
+        VAR_ERRORTEXT = "Can't GET CPU COUNT on this platform.";
+        VAR_ERRORCODE = 1;
+        return 0;
+    }
+    VAR_ERRORTEXT = "";
+    VAR_ERRORCODE = 0;
+    return count;
+}
+
+#if !defined(_WIN32)
+#include <dlfcn.h>
+#endif
+#include <deque>
+
+// What a function called with CALL LIBRARY FUNCTION gets, one per
+// argument. It should be declared as:
+//     extern "C" void name(ldpl_ffi_arg* args, int count);
+// Numbers it changes are copied back into the variables passed in.
+extern "C" {
+typedef struct {
+    int is_text;
+    double number;
+    const char* text;
+} ldpl_ffi_arg;
+}
+typedef void (*ldpl_ffi_fn)(ldpl_ffi_arg*, int);
+
+// Loaded libraries. A handle is an index in here plus one, so 0 is
+// never a valid handle.
+vector<void*> ldpl_libraries;
+
+void ldpl_library_error(string error) {
+    VAR_ERRORTEXT = error;
+    VAR_ERRORCODE = 1;
+}
+
+ldpl_number ldpl_load_library(chText path) {
+#if defined(_WIN32)
+    ldpl_library_error("LOAD LIBRARY isn't supported on this platform.");
+    return 0;
+#else
+    void* lib = dlopen(expandHomeDirectory(path.str_rep()).c_str(), RTLD_NOW);
+    if(!lib) {
+        ldpl_library_error(dlerror());
+        return 0;
+    }
+    ldpl_libraries.push_back(lib);
+    VAR_ERRORTEXT = "";
+    VAR_ERRORCODE = 0;
+    return ldpl_libraries.size();
+#endif
+}
+
+// The library behind `handle`, or NULL with ERRORCODE set.
+void* ldpl_library(ldpl_number handle) {
+    size_t i = handle;
+    if(handle < 1 || i != handle || i > ldpl_libraries.size() || !ldpl_libraries[i - 1]) {
+        ldpl_library_error("No library loaded with handle " +
+                           to_ldpl_string(handle).str_rep() + ".");
+        return NULL;
+    }
+    return ldpl_libraries[i - 1];
+}
+
+void ldpl_unload_library(ldpl_number handle) {
+    void* lib = ldpl_library(handle);
+    if(!lib) return;
+#if !defined(_WIN32)
+    dlclose(lib);
+#endif
+    ldpl_libraries[(size_t)handle - 1] = NULL;
+    VAR_ERRORTEXT = "";
+    VAR_ERRORCODE = 0;
+}
+
+void ldpl_ffi_pack(ldpl_ffi_arg& arg, deque<string>& texts, ldpl_number& n) {
+    arg.is_text = 0;
+    arg.number  = n;
+    arg.text    = NULL;
+}
+
+void ldpl_ffi_pack(ldpl_ffi_arg& arg, deque<string>& texts, chText& t) {
+    texts.push_back(t.str_rep());
+    arg.is_text = 1;
+    arg.number  = 0;
+    arg.text    = texts.back().c_str();
+}
+
+void ldpl_ffi_unpack(ldpl_ffi_arg& arg, ldpl_number& n) { n = arg.number; }
+
+void ldpl_ffi_unpack(ldpl_ffi_arg& arg, chText& t) {}
+
+template <typename... Args>
+void ldpl_call_library(ldpl_number handle, chText name, Args&... args) {
+    void* lib = ldpl_library(handle);
+    if(!lib) return;
+#if !defined(_WIN32)
+    ldpl_ffi_fn fn = (ldpl_ffi_fn)dlsym(lib, name.str_rep().c_str());
+    if(!fn) {
+        ldpl_library_error("Can't find function " + name.str_rep() + " in library.");
+        return;
+    }
+    // braced lists run in order, so packed[i] lines up with args
+    ldpl_ffi_arg packed[sizeof...(args) + 1];
+    deque<string> texts;
+    size_t i     = 0;
+    int pack[]   = {0, (ldpl_ffi_pack(packed[i++], texts, args), 0)...};
+    fn(packed, sizeof...(args));
+    i            = 0;
+    int unpack[] = {0, (ldpl_ffi_unpack(packed[i++], args), 0)...};
+    (void)pack;
+    (void)unpack;
+    VAR_ERRORTEXT = "";
+    VAR_ERRORCODE = 0;
+#endif
+}
+
 // getLineSafe by https://gist.github.com/josephwb/df09e3a71679461fc104
 std::istream& getlineSafe(std::istream& is, std::string& t) {
     t.clear();
@@ -765,6 +1415,301 @@ chText trimCopy(chText _line) {
     return line;
 }
 
+// Splits one CSV line into its fields. Quoted fields can hold commas,
+// and "" inside them is a quote. An unclosed quote sets ERRORCODE and
+// runs to the end of the line.
+ldpl_list<chText> parse_csv_line(chText line) {
+    ldpl_list<chText> result;
+    string s = line.str_rep();
+    while(!s.empty() && (s.back() == '\n' || s.back() == '\r')) s.pop_back();
+
+    string field;
+    bool quoted = false, closed = true;
+    for(size_t i = 0; i < s.size(); ++i) {
+        char c = s[i];
+        if(quoted) {
+            if(c != '"') {
+                field += c;
+            } else if(i + 1 < s.size() && s[i + 1] == '"') {
+                field += '"';
+                ++i;
+            } else {
+                quoted = false;
+                closed = true;
+            }
+        } else if(c == ',') {
+            result.inner_collection.push_back(field);
+            field.clear();
+        } else if(c == '"' && field.empty()) {
+            quoted = true;
+            closed = false;
+        } else {
+            field += c;
+        }
+    }
+    result.inner_collection.push_back(field);
+
+    if(closed) {
+        VAR_ERRORTEXT = "";
+        VAR_ERRORCODE = 0;
+    } else {
+        VAR_ERRORTEXT = "Unclosed quote in CSV line.";
+        VAR_ERRORCODE = 1;
+    }
+    return result;
+}
+
+// Joins fields into a CSV line, quoting the ones that need it.
+chText format_csv_line(ldpl_list<chText>& fields) {
+    string line;
+    for(size_t i = 0; i < fields.inner_collection.size(); ++i) {
+        string field = fields.inner_collection[i].str_rep();
+        if(i > 0) line += ',';
+        if(field.find_first_of(",\"\r\n") == string::npos) {
+            line += field;
+            continue;
+        }
+        line += '"';
+        for(char c : field) {
+            if(c == '"') line += '"';
+            line += c;
+        }
+        line += '"';
+    }
+    return line;
+}
+
+// Joins the items of a list into one text with `sep` between them.
+chText join_list(ldpl_list<chText>& list, chText sep) {
+    string out;
+    for(size_t i = 0; i < list.inner_collection.size(); ++i) {
+        if(i > 0) out += sep.str_rep();
+        out += list.inner_collection[i].str_rep();
+    }
+    return out;
+}
+
+chText join_list(ldpl_list<ldpl_number>& list, chText sep) {
+    string out;
+    for(size_t i = 0; i < list.inner_collection.size(); ++i) {
+        if(i > 0) out += sep.str_rep();
+        out += to_ldpl_string(list.inner_collection[i]).str_rep();
+    }
+    return out;
+}
+
+// Fills `dest` from `key = value` lines. Blank lines and lines starting
+// with # or ; are skipped, and whitespace around keys and values is
+// trimmed. A line without an = sets ERRORCODE but doesn't stop parsing.
+void parse_config(chText source, ldpl_map<chText>& dest) {
+    const char* space = " \t\r\n";
+    stringstream lines(source.str_rep());
+    string line, bad;
+    size_t line_no = 0;
+    dest.inner_collection.clear();
+    while(getline(lines, line)) {
+        ++line_no;
+        size_t start = line.find_first_not_of(space);
+        if(start == string::npos || line[start] == '#' || line[start] == ';')
+            continue;
+        size_t eq = line.find('=');
+        if(eq == string::npos || eq == start) {
+            if(bad.empty()) bad = to_string(line_no);
+            continue;
+        }
+        string key   = line.substr(start, eq - start);
+        string value = line.substr(eq + 1);
+        key.erase(key.find_last_not_of(space) + 1);
+        value.erase(0, value.find_first_not_of(space));
+        value.erase(value.find_last_not_of(space) + 1);
+        dest.inner_collection[key] = value;
+    }
+    if(bad.empty()) {
+        VAR_ERRORTEXT = "";
+        VAR_ERRORCODE = 0;
+    } else {
+        VAR_ERRORTEXT = "Can't PARSE CONFIG, no key = value on line " + bad + ".";
+        VAR_ERRORCODE = 1;
+    }
+}
+
+// Replaces each {{key}} in `tmpl` with values:key, or nothing if the
+// key isn't there. Spaces around the key are ignored and an unclosed
+// {{ is left as is.
+chText expand_template(chText tmpl, ldpl_map<chText>& values) {
+    string s = tmpl.str_rep(), out;
+    size_t i = 0;
+    while(i < s.size()) {
+        size_t open  = s.find("{{", i);
+        size_t close = open == string::npos ? open : s.find("}}", open + 2);
+        if(close == string::npos) break;
+        out += s.substr(i, open - i);
+        string key = s.substr(open + 2, close - open - 2);
+        key.erase(0, key.find_first_not_of(" \t"));
+        key.erase(key.find_last_not_of(" \t") + 1);
+        auto it = values.inner_collection.find(key);
+        if(it != values.inner_collection.end()) out += it->second.str_rep();
+        i = close + 2;
+    }
+    out += s.substr(i);
+    return out;
+}
+
+// Is this code point (as UTF-8) whitespace? Covers ASCII and the
+// Unicode space characters, like no-break and ideographic spaces.
+bool utf8_is_space(const string& ch) {
+    static const char* spaces[] = {
+        " ",      "\t",     "\n",     "\v",     "\f",     "\r",
+        "\u0085", "\u00a0", "\u1680", "\u2000", "\u2001", "\u2002",
+        "\u2003", "\u2004", "\u2005", "\u2006", "\u2007", "\u2008",
+        "\u2009", "\u200a", "\u2028", "\u2029", "\u202f", "\u205f",
+        "\u3000"};
+    for(const char* space : spaces)
+        if(ch == space) return true;
+    return false;
+}
+
+// Splits text on runs of whitespace, dropping empty tokens.
+ldpl_list<chText> utf8_tokenize(chText text) {
+    ldpl_list<chText> result;
+    string token;
+    for(size_t i = 0; i < text.size(); ++i) {
+        string& ch = text[(int)i];
+        if(!utf8_is_space(ch)) {
+            token += ch;
+        } else if(!token.empty()) {
+            result.inner_collection.push_back(token);
+            token.clear();
+        }
+    }
+    if(!token.empty()) result.inner_collection.push_back(token);
+    return result;
+}
+
+// Code point of one UTF-8 encoded character.
+unsigned long utf8_code_point(const string& c) {
+    unsigned char lead = c[0];
+    if(lead < 0x80 || c.size() == 1) return lead;
+    unsigned long cp = lead & (lead >= 0xF0 ? 0x07 : lead >= 0xE0 ? 0x0F : 0x1F);
+    for(size_t i = 1; i < c.size(); ++i) cp = (cp << 6) | (c[i] & 0x3F);
+    return cp;
+}
+
+// Lowercase of one code point: ASCII, Latin-1, Latin Extended-A, Greek,
+// and Cyrillic capitals. Everything else is returned unchanged.
+unsigned long lower_code_point(unsigned long cp) {
+    if((cp >= 'A' && cp <= 'Z') || (cp >= 0xC0 && cp <= 0xDE && cp != 0xD7) ||
+       (cp >= 0x391 && cp <= 0x3AB && cp != 0x3A2) ||
+       (cp >= 0x410 && cp <= 0x42F))
+        return cp + 0x20;
+    if(cp >= 0x400 && cp <= 0x40F) return cp + 0x50;
+    if(((cp >= 0x100 && cp <= 0x137) || (cp >= 0x14A && cp <= 0x177)) && cp % 2 == 0)
+        return cp + 1;
+    if(((cp >= 0x139 && cp <= 0x148) || (cp >= 0x179 && cp <= 0x17E)) && cp % 2 == 1)
+        return cp + 1;
+    if(cp == 0x178) return 0xFF;
+    if(cp == 0x386) return 0x3AC;
+    if(cp >= 0x388 && cp <= 0x38A) return cp + 0x25;
+    if(cp == 0x38C) return 0x3CC;
+    if(cp == 0x38E || cp == 0x38F) return cp + 0x3F;
+    return cp;
+}
+
+// Uppercase of one code point, the inverse of lower_code_point().
+unsigned long upper_code_point(unsigned long cp) {
+    if((cp >= 'a' && cp <= 'z') || (cp >= 0xE0 && cp <= 0xFE && cp != 0xF7) ||
+       (cp >= 0x3B1 && cp <= 0x3CB && cp != 0x3C2) ||
+       (cp >= 0x430 && cp <= 0x44F))
+        return cp - 0x20;
+    if(cp >= 0x450 && cp <= 0x45F) return cp - 0x50;
+    if(((cp >= 0x100 && cp <= 0x137) || (cp >= 0x14A && cp <= 0x177)) && cp % 2 == 1)
+        return cp - 1;
+    if(((cp >= 0x139 && cp <= 0x148) || (cp >= 0x179 && cp <= 0x17E)) && cp % 2 == 0)
+        return cp - 1;
+    if(cp == 0xFF) return 0x178;
+    if(cp == 0x3C2) return 0x3A3;
+    if(cp == 0x3AC) return 0x386;
+    if(cp >= 0x3AD && cp <= 0x3AF) return cp - 0x25;
+    if(cp == 0x3CC) return 0x38C;
+    if(cp == 0x3CD || cp == 0x3CE) return cp - 0x3F;
+    return cp;
+}
+
+// `text` with every character passed through `convert`.
+chText utf8_convert_case(chText text, unsigned long (*convert)(unsigned long)) {
+    string out;
+    for(size_t i = 0; i < text.size(); ++i) {
+        string& c = text[(int)i];
+        unsigned long cp = utf8_code_point(c);
+        unsigned long converted = convert(cp);
+        if(converted == cp)
+            out += c;
+        else
+            ldpl_append_utf8(out, converted);
+    }
+    return out;
+}
+
+chText utf8_to_upper(chText text) {
+    return utf8_convert_case(text, upper_code_point);
+}
+
+chText utf8_to_lower(chText text) {
+    return utf8_convert_case(text, lower_code_point);
+}
+
+bool equals_ignore_case(chText a, chText b) {
+    if(a.size() != b.size()) return false;
+    for(size_t i = 0; i < a.size(); ++i) {
+        string& ac = a[(int)i];
+        string& bc = b[(int)i];
+        if(ac != bc &&
+           lower_code_point(utf8_code_point(ac)) !=
+               lower_code_point(utf8_code_point(bc)))
+            return false;
+    }
+    return true;
+}
+
+// `text` without any of the characters in `chars` at either end.
+chText trim_chars(chText text, chText chars) {
+    size_t size = text.size(), first = 0, last = size;
+    while(first < last && utf8GetIndexOf(chars, text[(int)first]) >= 0)
+        ++first;
+    while(last > first && utf8GetIndexOf(chars, text[(int)(last - 1)]) >= 0)
+        --last;
+    return text.substr(first, last - first);
+}
+
+// Characters from `from` up to but not including `to`. Indices are
+// clamped to the text, and a reversed range gives "".
+chText text_range(chText text, ldpl_number from, ldpl_number to) {
+    ldpl_number size = text.size();
+    if(from < 0) from = 0;
+    if(to > size) to = size;
+    if(to <= from) return "";
+    return text.substr((size_t)from, (size_t)(to - from));
+}
+
+// Levenshtein distance between two texts, counting code points.
+ldpl_number edit_distance(chText a, chText b) {
+    size_t n = a.size(), m = b.size();
+    vector<size_t> prev(m + 1), cur(m + 1);
+    for(size_t j = 0; j <= m; ++j) prev[j] = j;
+    for(size_t i = 1; i <= n; ++i) {
+        cur[0] = i;
+        string& ac = a[(int)(i - 1)];
+        for(size_t j = 1; j <= m; ++j) {
+            size_t cost = ac == b[(int)(j - 1)] ? 0 : 1;
+            cur[j] = min(min(prev[j] + 1, cur[j - 1] + 1), prev[j - 1] + cost);
+        }
+        swap(prev, cur);
+    }
+    return prev[m];
+}
+
+// Splits on every `needle`, keeping empty fields: ",a,,b," gives
+// "", "a", "", "b", "". An empty needle splits into characters.
 ldpl_list<chText> utf8_split_list(chText haystack, chText needle) {
     ldpl_list<chText> result;
     int lenHaystack = haystack.size();
@@ -1,71 +1,262 @@
 //! The Builder wraps your C++ compiler and builds the final program.
 
-use crate::{compiler::Compiler, LDPLResult};
-use std::{fs, path::Path, process::Command, str};
+use crate::{
+    compiler::{self, Compiler},
+    LDPLResult,
+};
+use std::{
+    env, fs,
+    path::Path,
+    process::Command,
+    str,
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::Mutex,
+    thread,
+};
 
 impl Compiler {
     /// Run the local C++ compiler and build a binary.
-    /// Returns the name of the built binary.
-    pub fn build(&self, path: &str, outfile: Option<String>) -> LDPLResult<String> {
-        // TODO better way to check errors after compiling
-        if !self.expected_defs.is_empty() {
-            for (sub, _) in &self.expected_defs {
-                return error!("The subprocedure {} is called but never declared.", sub);
-            }
-        }
-
+    /// Returns the name of the built binary. With `keep` the C++
+    /// source is saved next to it as `<binary>.cpp`. If the build
+    /// fails the source is always kept there for debugging, and the
+    /// error says where.
+    ///
+    /// With two or more extensions they're compiled to object files in
+    /// parallel first, then linked with the program. `set_jobs(1)`
+    /// turns that off.
+    pub fn build(&self, path: &str, outfile: Option<String>, keep: bool) -> LDPLResult<String> {
         let path = Path::new(&path);
-        let target = if outfile.is_none() {
+        let target = if let Some(outfile) = outfile {
+            outfile
+        } else {
             format!(
                 "{}/{}",
                 path.parent()
-                    .and_then(|d| Some(d.to_string_lossy()))
+                    .map(|d| d.to_string_lossy())
                     .unwrap_or(".".into()),
                 path.file_stem()
-                    .and_then(|f| Some(format!("{}-bin", f.to_string_lossy())))
+                    .map(|f| format!("{}-bin", f.to_string_lossy()))
                     .unwrap_or("ldpl-output-bin".into())
             )
             .trim_matches('/')
             .to_string()
-        } else {
-            outfile.unwrap().to_string()
         };
 
-        let filename = "ldpl-temp.cpp";
-        if Path::new(filename).exists() {
-            fs::remove_file(filename)?;
+        let filename = if keep {
+            format!("{}.cpp", target)
+        } else {
+            "ldpl-temp.cpp".to_string()
+        };
+        if Path::new(&filename).exists() {
+            fs::remove_file(&filename)?;
         }
-        fs::write(filename, self.to_string())?;
+        fs::write(&filename, self.to_string())?;
 
-        let mut cmd = Command::new("c++");
-        let mut cmd = cmd
-            .arg("ldpl-temp.cpp")
-            .arg("-std=gnu++11")
-            .arg("-w")
-            .arg("-o")
-            .arg(&target);
+        let cc = self.cc_name();
+        let objects = if self.exts.len() > 1 && self.jobs != 1 {
+            match self.compile_exts(&target) {
+                Ok(objects) => objects,
+                Err(e) => {
+                    let kept = keep_source(&filename, &target)?;
+                    return error!("{}\nKept C++ source as {}", e, kept);
+                }
+            }
+        } else {
+            vec![]
+        };
 
-        for flag in &self.flags {
-            cmd = cmd.arg(flag);
-        }
-        for ext in &self.exts {
-            cmd = cmd.arg(ext);
+        let mut cmd = self.cc_command(&filename);
+        let mut cmd = cmd.arg("-o").arg(&target);
+        if objects.is_empty() {
+            for ext in &self.exts {
+                cmd = cmd.arg(ext);
+            }
+        } else {
+            for obj in &objects {
+                cmd = cmd.arg(obj);
+            }
         }
 
         // run command
-        let cmd = cmd.output();
-
-        fs::remove_file(filename)?;
-
-        let output = cmd?;
+        let output = cmd.output();
+        self.remove_objects(&objects);
+        let output = match output {
+            Ok(output) => output,
+            Err(e) => {
+                let kept = keep_source(&filename, &target)?;
+                return error!(
+                    "Can't run C++ compiler `{}`: {}\nKept C++ source as {}",
+                    cc, e, kept
+                );
+            }
+        };
         if !output.stderr.is_empty() {
+            let kept = keep_source(&filename, &target)?;
             return error!(
                 "C++ Error compiling {}: \n{}",
-                filename,
+                kept,
                 str::from_utf8(&output.stderr).unwrap_or("UTF-8 Error in C++ output")
             );
         }
 
+        if !keep {
+            fs::remove_file(&filename)?;
+        }
+
         Ok(target)
     }
+
+    /// Where INCLUDE and USING look for files and how the C++ gets
+    /// built, for --print-search-paths. `file` is the main source
+    /// file, or "" when reading STDIN.
+    pub fn search_paths(&self, file: &str) -> String {
+        let dir = Path::new(file)
+            .parent()
+            .map(|d| d.to_string_lossy().to_string())
+            .filter(|d| !d.is_empty())
+            .unwrap_or_else(|| ".".into());
+        let dir = fs::canonicalize(&dir)
+            .map(|d| d.to_string_lossy().to_string())
+            .unwrap_or(dir);
+        let flags: Vec<_> = self
+            .cc_command("")
+            .get_args()
+            .skip(1)
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect();
+        let exts = if self.exts.is_empty() {
+            "(none)".to_string()
+        } else {
+            self.exts.join(" ")
+        };
+
+        format!(
+            "INCLUDE:      {} (then relative to each including file)\n\
+             USING:        {}<name>/<name>.ldpl\n\
+             C++ compiler: {}\n\
+             C++ flags:    {}\n\
+             Extensions:   {}\n",
+            dir,
+            compiler::lpm_location(),
+            self.cc_name(),
+            flags.join(" "),
+            exts
+        )
+    }
+
+    /// The C++ compiler we'll run.
+    fn cc_name(&self) -> String {
+        self.cc
+            .clone()
+            .or_else(|| env::var("CXX").ok().filter(|cxx| !cxx.is_empty()))
+            .unwrap_or_else(|| "c++".into())
+    }
+
+    /// C++ compiler command for `input` with the flags every run needs.
+    fn cc_command(&self, input: &str) -> Command {
+        let mut cmd = Command::new(self.cc_name());
+        cmd.arg(input).arg("-std=gnu++11").arg("-w");
+
+        // before the user's -f flags so they can override it
+        if let Some(level) = &self.opt_level {
+            cmd.arg(format!("-O{}", level));
+        }
+        for flag in &self.flags {
+            cmd.arg(flag);
+        }
+        cmd
+    }
+
+    /// Compile each extension to an object file next to `target`,
+    /// `self.jobs` at a time. Object files and libraries are passed
+    /// through untouched. Returns the files to link, in order.
+    fn compile_exts(&self, target: &str) -> LDPLResult<Vec<String>> {
+        let jobs = match self.jobs {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        }
+        .min(self.exts.len());
+
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(vec![None; self.exts.len()]);
+        thread::scope(|s| {
+            for _ in 0..jobs {
+                s.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    if i >= self.exts.len() {
+                        break;
+                    }
+                    let result = self.compile_ext(&self.exts[i], &format!("{}-ext{}.o", target, i));
+                    results.lock().unwrap()[i] = Some(result);
+                });
+            }
+        });
+
+        let mut objects = vec![];
+        let mut errors = vec![];
+        for result in results.into_inner().unwrap().into_iter().flatten() {
+            match result {
+                Ok(obj) => objects.push(obj),
+                Err(e) => errors.push(e),
+            }
+        }
+        if !errors.is_empty() {
+            self.remove_objects(&objects);
+            return error!(errors.join("\n"));
+        }
+        Ok(objects)
+    }
+
+    /// Clean up the object files `compile_exts()` made, leaving the
+    /// ones that were passed in.
+    fn remove_objects(&self, objects: &[String]) {
+        for obj in objects {
+            if !self.exts.contains(obj) {
+                let _ = fs::remove_file(obj);
+            }
+        }
+    }
+
+    /// Compile one extension to `obj`. Returns the file to link, or
+    /// the compiler's complaint about `ext`.
+    fn compile_ext(&self, ext: &str, obj: &str) -> Result<String, String> {
+        let ext_type = Path::new(ext).extension().and_then(|e| e.to_str());
+        if matches!(ext_type, Some("o" | "a" | "so")) {
+            return Ok(ext.to_string());
+        }
+
+        let output = match self.cc_command(ext).arg("-c").arg("-o").arg(obj).output() {
+            Ok(output) => output,
+            Err(e) => {
+                return Err(format!(
+                    "Can't run C++ compiler `{}`: {}",
+                    self.cc_name(),
+                    e
+                ))
+            }
+        };
+        // linker flags like -lm make the compiler grumble when it's
+        // not linking, so only a failed run counts as an error here
+        if !output.status.success() {
+            return Err(format!(
+                "C++ Error compiling {}: \n{}",
+                ext,
+                str::from_utf8(&output.stderr).unwrap_or("UTF-8 Error in C++ output")
+            ));
+        }
+        Ok(obj.to_string())
+    }
+}
+
+/// Move the C++ source of a failed build to `<target>.cpp` so it
+/// isn't left in the working directory as ldpl-temp.cpp. Returns
+/// where it ended up.
+fn keep_source(filename: &str, target: &str) -> LDPLResult<String> {
+    let kept = format!("{}.cpp", target);
+    if filename != kept {
+        // copy, not rename: the target may be on another filesystem
+        fs::copy(filename, &kept)?;
+        fs::remove_file(filename)?;
+    }
+    Ok(kept)
 }
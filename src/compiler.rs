@@ -2,37 +2,50 @@
 
 use crate::{
     parser::{LDPLParser, Parser, Rule},
-    LDPLResult, LDPLType, LPM_LOCATION,
+    LDPLError, LDPLResult, LDPLType, LPM_LOCATION,
+};
+use pest::{
+    iterators::{Pair, Pairs},
+    prec_climber::{Assoc, Operator, PrecClimber},
 };
-use pest::iterators::{Pair, Pairs};
 use std::{
     collections::HashMap,
     fmt,
+    path::{Path, PathBuf},
     sync::atomic::{AtomicUsize, Ordering},
 };
 
-////
+// ----
 // CONSTANTS
 
-/// Track indentation depth.
-static DEPTH: AtomicUsize = AtomicUsize::new(0);
-
 /// Include LDPL C++ internal functions in our output.
-const CPP_HEADER: &'static str = include_str!("../lib/ldpl_header.cpp");
+const CPP_HEADER: &str = include_str!("../lib/ldpl_header.cpp");
 
 /// Setup the C++ main() function
-const MAIN_HEADER: &'static str = r#"
+const MAIN_HEADER: &str = r#"
 int main(int argc, char* argv[]) {
     cout.precision(numeric_limits<ldpl_number>::digits10);
     for(int i = 1; i < argc; ++i) VAR_ARGV.inner_collection.push_back(argv[i]);
 
 "#;
-const MAIN_FOOTER: &'static str = r#"
+/// Call counters for --profile builds, printed to stderr on exit.
+const PROFILE_HEADER: &str = r#"
+#include <map>
+map<string, unsigned long> ldpl_profile_counts;
+struct ldpl_profile_dump {
+    ~ldpl_profile_dump() {
+        cerr << "-- sub-procedure calls --" << endl;
+        for (auto& kv : ldpl_profile_counts) cerr << kv.second << "\t" << kv.first << endl;
+    }
+} ldpl_profile_dumper;
+"#;
+
+const MAIN_FOOTER: &str = r#"
     return 0;
 }
 "#;
 
-////
+// ----
 // DATA
 
 /// State of our LDPL program, including variables and defined
@@ -54,11 +67,46 @@ pub struct Compiler {
     /// Add with `add_extension()`.
     pub exts: Vec<String>,
 
+    /// LDPL files pulled in with INCLUDE or USING PACKAGE.
+    pub includes: Vec<String>,
+
     /// Compiler flags to build with.
     pub flags: Vec<String>,
 
-    /// Forward function declarations.
-    forwards: Vec<String>,
+    /// C++ compiler to build with. Falls back to $CXX, then `c++`.
+    /// Set with `set_compiler()`.
+    pub cc: Option<String>,
+
+    /// Optimization level passed to the C++ compiler as -O<level>.
+    /// Set with `set_opt_level()`.
+    pub opt_level: Option<String>,
+
+    /// How many extensions to compile at once. 0 means one per CPU.
+    /// Set with `set_jobs()`.
+    pub jobs: usize,
+
+    /// What to indent generated C++ with. Four spaces if not set.
+    /// Set with `set_indent()`.
+    pub indent: Option<String>,
+
+    /// Count calls to every SUB and print the totals on exit.
+    /// Set with `set_profile()`.
+    pub profile: bool,
+
+    /// Emit `#line` directives so C++ errors point at the LDPL source.
+    /// Set with `set_debug_lines()`.
+    pub debug_lines: bool,
+
+    /// Turn warnings into errors. Set with `set_strict()`.
+    pub strict: bool,
+
+    /// Problems that don't stop compilation, like unreachable code.
+    pub warnings: Vec<LDPLError>,
+
+    /// Forward function declarations: mangled name and param types.
+    /// A SUB called before it's defined gets its call's argument types
+    /// until the definition replaces them with the real ones.
+    forwards: Vec<(String, Vec<LDPLType>)>,
 
     /// EXTERNAL variables
     extern_vars: HashMap<String, bool>,
@@ -73,83 +121,154 @@ pub struct Compiler {
     /// Sub definitions. name => params
     defs: HashMap<String, Vec<LDPLType>>,
 
+    /// EXTERNAL SUB signatures, from definitions or DECLARE EXTERNAL
+    /// SUB. CALL EXTERNAL is checked against these. name => params
+    extern_defs: HashMap<String, Vec<LDPLType>>,
+
     /// Path of the file we're currently compiling, if any.
     path: Option<String>,
 
+    /// Where relative paths resolve when we're not compiling a file.
+    /// Set by `compile_with_base()`.
+    base_dir: Option<PathBuf>,
+
     /// When a sub is called before it's defined, we stick it in this
-    /// list. When it's defined we remove it from the list. If the
-    /// list isn't empty when we're done, we have an error.
-    pub expected_defs: HashMap<String, bool>,
+    /// list with its call sites. When it's defined we check the calls'
+    /// arguments and remove it from the list. If the list isn't empty
+    /// when we're done, we have an error.
+    expected_defs: HashMap<String, Vec<CallSite>>,
+
+    /// Like `expected_defs`, but for CALL EXTERNAL. These are only
+    /// checked if the EXTERNAL SUB shows up later - it's fine if it
+    /// never does, since it can live in a C++ extension.
+    expected_externs: HashMap<String, Vec<CallSite>>,
+
+    /// How many INCLUDEs deep we are. Checks that need the whole
+    /// program only run at the top.
+    include_depth: usize,
+
+    /// Files being loaded right now, outermost first, as (canonical
+    /// path, path as given). Used to catch INCLUDE cycles.
+    loading: Vec<(String, String)>,
+
+    /// In-memory files from `compile_files()`, by normalized path.
+    /// Loading one of these paths never touches the disk.
+    virtual_files: HashMap<String, String>,
 
     /// User-defined statements created with CREATE STATEMENT.
     /// The same statement can reference multiple SUBs based on the
     /// param types, so we use a vec.
     user_stmts: HashMap<String, Vec<String>>,
 
+    /// SUBs marked with MEMOIZE SUB, which get a result cache, and
+    /// where they were marked.
+    memoized: HashMap<String, CallSite>,
+
+    /// SUBs registered with DECLARE SUB that haven't been defined yet,
+    /// and where they were declared.
+    declared: HashMap<String, CallSite>,
+
+    /// Mangled param names of the SUB being compiled.
+    param_names: Vec<String>,
+
     // in a sub-procedure? RETURN doesn't work outside of one.
     in_sub: bool,
 
     // in a loop? BREAK/CONTINUE only work in loops. Vec for nesting.
-    in_loop: Vec<bool>,
+    in_loop: Vec<Loop>,
 
     // counter for tmp variables
     tmp_id: usize,
+
+    // indentation level of the C++ being emitted. Atomic so methods
+    // that only borrow `self` can indent, and the Compiler stays Sync
+    // for building extensions in parallel.
+    depth: AtomicUsize,
+}
+
+/// Where a SUB that hasn't been defined yet was called, and with what.
+/// MEMOIZE and DECLARE use it too, with the param types they name.
+struct CallSite {
+    name: String,
+    file: Option<String>,
+    line: usize,
+    col: usize,
+    args: Vec<LDPLType>,
+}
+
+impl CallSite {
+    /// An error pointing at this site, in the file it's in.
+    fn error(&self, msg: String) -> LDPLError {
+        let mut err = LDPLError::new(msg, self.line, self.col, self.name.len());
+        err.file = self.file.clone();
+        err
+    }
+}
+
+/// A loop being compiled, and whether BREAK N or CONTINUE N need
+/// labels to jump to.
+#[derive(Default)]
+struct Loop {
+    /// Names the labels. Only assigned once one is needed.
+    id: Option<usize>,
+    break_label: bool,
+    continue_label: bool,
 }
 
-////
+// ----
 // MACROS
 
 /// Call when an unexpected Pair/Rule is encountered.
 macro_rules! unexpected {
     ($rule:expr) => {
-        return error!("Unexpected rule: {:?}", $rule);
+        return error!("Unexpected rule: {:?}", $rule)
     };
 }
 
 /// Produce a single line with indentation. Used to build multi-line responses.
 macro_rules! emit_line {
-    ($msg:expr) => {
-        format!("{}{}\n", "    ".repeat(DEPTH.load(Ordering::SeqCst)), $msg)
+    ($self:ident, $msg:expr) => {
+        format!("{}{}\n", $self.indentation().repeat($self.depth.load(Ordering::SeqCst)), $msg)
     };
-    ($fmt:expr, $($args:expr),*) => {
-        emit_line!(format!($fmt, $($args),*))
+    ($self:ident, $fmt:expr, $($args:expr),*) => {
+        emit_line!($self, format!($fmt, $($args),*))
     };
-    ($fmt:expr, $($args:expr,)*) => {
-        emit_line!(format!($fmt, $($args,)*))
+    ($self:ident, $fmt:expr, $($args:expr,)*) => {
+        emit_line!($self, format!($fmt, $($args,)*))
     };
 }
 
 /// Produce a line of code at the current indentation level with a
 /// trailing newline.
 macro_rules! emit {
-    ($msg:expr) => {
-        Ok(emit_line!($msg))
+    ($self:ident, $msg:expr) => {
+        Ok(emit_line!($self, $msg))
     };
-    ($fmt:expr, $($args:expr),*) => {
-        emit!(format!($fmt, $($args),*))
+    ($self:ident, $fmt:expr, $($args:expr),*) => {
+        emit!($self, format!($fmt, $($args),*))
     };
-    ($fmt:expr, $($args:expr,)*) => {
-        emit!(format!($fmt, $($args,)*))
+    ($self:ident, $fmt:expr, $($args:expr,)*) => {
+        emit!($self, format!($fmt, $($args,)*))
     };
 }
 
 /// Increase indentation level (depth)
 macro_rules! indent {
-    () => {
-        DEPTH.fetch_add(1, Ordering::SeqCst);
+    ($self:ident) => {
+        $self.depth.fetch_add(1, Ordering::SeqCst);
     };
 }
 
 /// Decrease indentation level
 macro_rules! dedent {
-    () => {
-        if DEPTH.load(Ordering::SeqCst) > 0 {
-            DEPTH.fetch_sub(1, Ordering::SeqCst);
+    ($self:ident) => {
+        if $self.depth.load(Ordering::SeqCst) > 0 {
+            $self.depth.fetch_sub(1, Ordering::SeqCst);
         }
     };
 }
 
-////
+// ----
 // FUNCTIONS
 
 /// Turns a string of LDPL code into C++ code.
@@ -178,14 +297,23 @@ pub fn new() -> Compiler {
     Compiler::default()
 }
 
+/// Where USING looks for LPM libraries, with ~ expanded.
+pub fn lpm_location() -> String {
+    LPM_LOCATION.replace('~', env!("HOME"))
+}
+
 /// Treating the compiler as a string produces the compiled C++.
 impl fmt::Display for Compiler {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "{}{}{}{}{}{}{}",
+            "{}{}{}{}\n{}{}{}{}",
             CPP_HEADER,
-            self.forwards.join(""),
+            if self.profile { PROFILE_HEADER } else { "" },
+            self.forwards
+                .iter()
+                .map(|(name, params)| format!("void {}({});\n", name, compile_param_types(params)))
+                .collect::<String>(),
             self.vars.join("\n"),
             self.subs.join(""),
             MAIN_HEADER,
@@ -202,32 +330,276 @@ impl Compiler {
         Ok(())
     }
 
+    /// Add every .cpp file in `dir` as an extension, sorted by path so
+    /// builds don't depend on directory order. Subdirectories are only
+    /// searched when `recursive` is set.
+    pub fn add_extension_dir(&mut self, dir: &str, recursive: bool) -> LDPLResult<()> {
+        fn find(dir: &Path, recursive: bool, found: &mut Vec<String>) -> LDPLResult<()> {
+            let entries = std::fs::read_dir(dir).map_err(|err| {
+                Err(format!(
+                    "Can't read extension directory {}: {}",
+                    dir.display(),
+                    err
+                ))
+            })?;
+            for entry in entries {
+                let path = entry?.path();
+                if path.is_dir() {
+                    if recursive {
+                        find(&path, recursive, found)?;
+                    }
+                } else if path.extension().is_some_and(|ext| ext == "cpp") {
+                    found.push(path.to_string_lossy().to_string());
+                }
+            }
+            Ok(())
+        }
+
+        let mut found = vec![];
+        find(Path::new(dir), recursive, &mut found)?;
+        found.sort();
+        for ext in found {
+            self.add_extension(ext)?;
+        }
+        Ok(())
+    }
+
     /// Add a C++ flag to include when building.
     pub fn add_flag(&mut self, flag: String) -> LDPLResult<()> {
         self.flags.push(flag);
         Ok(())
     }
 
+    /// Build with -O<level>. Accepts 0, 1, 2, 3, or s.
+    pub fn set_opt_level(&mut self, level: &str) -> LDPLResult<()> {
+        if !["0", "1", "2", "3", "s"].contains(&level) {
+            return error!(
+                "Invalid optimization level -O{} (expected 0, 1, 2, 3, or s)",
+                level
+            );
+        }
+        self.opt_level = Some(level.to_string());
+        Ok(())
+    }
+
+    /// Indent generated C++ with `indent`, like "\t" or "  ". Must
+    /// be set before compiling anything.
+    pub fn set_indent(&mut self, indent: &str) {
+        self.indent = Some(indent.to_string());
+    }
+
+    /// One level of indentation in the generated C++.
+    fn indentation(&self) -> &str {
+        self.indent.as_deref().unwrap_or("    ")
+    }
+
+    /// Compile up to `jobs` extensions at once. 1 builds everything
+    /// in a single C++ compiler run, 0 uses one job per CPU.
+    pub fn set_jobs(&mut self, jobs: usize) {
+        self.jobs = jobs;
+    }
+
+    /// Count SUB calls in the generated program. Must be set before
+    /// compiling anything.
+    pub fn set_profile(&mut self, profile: bool) {
+        self.profile = profile;
+    }
+
+    /// Tag every statement with a `#line` directive. Must be set
+    /// before compiling anything.
+    pub fn set_debug_lines(&mut self, debug_lines: bool) {
+        self.debug_lines = debug_lines;
+    }
+
+    /// Treat warnings as errors.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Use a specific C++ compiler when building.
+    pub fn set_compiler(&mut self, cc: String) -> LDPLResult<()> {
+        self.cc = Some(cc);
+        Ok(())
+    }
+
+    /// Forget everything compiled so far so this compiler can be used
+    /// for a new program. Settings made with the `set_` methods are
+    /// kept, but EXTENSIONs and FLAGs are cleared along with the code.
+    pub fn reset(&mut self) {
+        *self = Compiler {
+            cc: self.cc.take(),
+            opt_level: self.opt_level.take(),
+            jobs: self.jobs,
+            indent: self.indent.take(),
+            profile: self.profile,
+            debug_lines: self.debug_lines,
+            strict: self.strict,
+            ..Compiler::default()
+        };
+    }
+
     /// Load a file from disk, parse it, and generate C++ code.
     pub fn load_and_compile(&mut self, path: &str) -> LDPLResult<()> {
         // info!("Loading {}", path);
-        let old_path = self.path.clone();
-        self.path = Some(path.to_string());
-
-        let source =
-            std::fs::read_to_string(&path).map_err(|err| Err(format!("{}: {}", path, err)))?;
+        let source = match self.virtual_files.get(&normalize_path(path)) {
+            Some(source) => source.clone(),
+            None => {
+                std::fs::read_to_string(path).map_err(|err| Err(format!("{}: {}", path, err)))?
+            }
+        };
         // info!("Parsing {}", path);
-        let out = self.compile(&source);
+        let ast = LDPLParser::parse(Rule::program, &source).map_err(|err| err.with_path(path))?;
+
+        let old_path = self.path.replace(path.to_string());
+        self.loading.push((canonical_path(path), path.to_string()));
+        let out = self.compile_ast(ast).map_err(|mut err| {
+            // keep the innermost file when INCLUDEs nest
+            if err.file.is_none() {
+                err.file = Some(path.to_string());
+            }
+            err
+        });
+        self.loading.pop();
         self.path = old_path;
         out
     }
 
+    /// If loading `path` would INCLUDE a file that's already being
+    /// loaded, describe the cycle.
+    fn include_cycle(&self, path: &str) -> Option<String> {
+        let canonical = canonical_path(path);
+        let start = self.loading.iter().position(|(c, _)| *c == canonical)?;
+        let mut chain: Vec<_> = self.loading[start..]
+            .iter()
+            .map(|(_, p)| p.as_str())
+            .collect();
+        chain.push(path);
+        Some(format!("Include cycle: {}", chain.join(" -> ")))
+    }
+
+    /// JSON description of the compiled program: global variables,
+    /// SUBs and their params, INCLUDEs, EXTENSIONs, and statements
+    /// made with CREATE STATEMENT. Names are uppercase, like LDPL
+    /// sees them.
+    pub fn manifest(&self) -> String {
+        let mut globals: Vec<_> = self.globals.iter().collect();
+        globals.sort_by(|a, b| a.0.cmp(b.0));
+        let globals = globals
+            .iter()
+            .map(|(name, t)| format!("    {}: {}", json_str(name), json_str(&t.to_string())))
+            .collect::<Vec<_>>();
+
+        let mut subs: Vec<_> = self.defs.iter().collect();
+        subs.sort_by(|a, b| a.0.cmp(b.0));
+        let subs = subs
+            .iter()
+            .map(|(name, params)| {
+                let params = params
+                    .iter()
+                    .map(|t| json_str(&t.to_string()))
+                    .collect::<Vec<_>>();
+                format!("    {}: [{}]", json_str(name), params.join(", "))
+            })
+            .collect::<Vec<_>>();
+
+        let mut stmts: Vec<_> = self.user_stmts.iter().collect();
+        stmts.sort();
+        let stmts = stmts
+            .iter()
+            .map(|(stmt, subs)| {
+                let subs = subs.iter().map(|s| json_str(s)).collect::<Vec<_>>();
+                format!("    {}: [{}]", json_str(stmt), subs.join(", "))
+            })
+            .collect::<Vec<_>>();
+
+        let list = |items: &[String]| {
+            items
+                .iter()
+                .map(|i| format!("    {}", json_str(i)))
+                .collect::<Vec<_>>()
+        };
+
+        let sections = [
+            ("globals", globals, '{', '}'),
+            ("subs", subs, '{', '}'),
+            ("includes", list(&self.includes), '[', ']'),
+            ("extensions", list(&self.exts), '[', ']'),
+            ("statements", stmts, '{', '}'),
+        ];
+        let body = sections
+            .iter()
+            .map(|(name, items, open, close)| {
+                if items.is_empty() {
+                    format!("  \"{}\": {}{}", name, open, close)
+                } else {
+                    format!(
+                        "  \"{}\": {}\n{}\n  {}",
+                        name,
+                        open,
+                        items.join(",\n"),
+                        close
+                    )
+                }
+            })
+            .collect::<Vec<_>>();
+        format!("{{\n{}\n}}\n", body.join(",\n"))
+    }
+
     /// Turns a string of LDPL code into C++ code.
     pub fn compile(&mut self, source: &str) -> LDPLResult<()> {
-        let ast = LDPLParser::parse(Rule::program, &source)?;
+        let ast = LDPLParser::parse(Rule::program, source)?;
         self.compile_ast(ast)
     }
 
+    /// Compile several files given in one string, for editors and
+    /// tests that don't want to touch the disk. Each file starts with a
+    /// `--- <path>` line. The first file is the program; the others
+    /// are only compiled when INCLUDEd, which finds them by path before
+    /// looking on disk.
+    pub fn compile_files(&mut self, input: &str) -> LDPLResult<()> {
+        let mut files: Vec<(String, String)> = vec![];
+        for line in input.split_inclusive('\n') {
+            if let Some(name) = line.strip_prefix("--- ") {
+                let name = name.trim();
+                if name.is_empty() {
+                    return error!("File name expected after ---");
+                }
+                files.push((name.to_string(), String::new()));
+            } else if let Some((_, source)) = files.last_mut() {
+                source.push_str(line);
+            } else if !line.trim().is_empty() {
+                return error!("Expected `--- <file>` before any code");
+            }
+        }
+
+        let main = match files.first() {
+            Some((name, _)) => name.clone(),
+            None => return error!("No files found, expected `--- <file>` lines"),
+        };
+        for (name, source) in files {
+            if self
+                .virtual_files
+                .insert(normalize_path(&name), source)
+                .is_some()
+            {
+                return error!("File given twice: {}", name);
+            }
+        }
+        self.load_and_compile(&main)
+    }
+
+    /// Turns a string of LDPL code into C++ code, resolving relative
+    /// INCLUDE and EXTENSION paths against `base_dir` as if the code
+    /// lived in a file there. INCLUDEd files are read right away, but
+    /// EXTENSION files are only collected and aren't read until
+    /// `build()`.
+    pub fn compile_with_base(&mut self, source: &str, base_dir: &Path) -> LDPLResult<()> {
+        let old_base = self.base_dir.replace(base_dir.to_path_buf());
+        let out = self.compile(source);
+        self.base_dir = old_base;
+        out
+    }
+
     /// Turns parsed LDPL code into C++ code.
     pub fn compile_ast(&mut self, ast: Pairs<Rule>) -> LDPLResult<()> {
         // Predeclared vars
@@ -249,6 +621,7 @@ impl Compiler {
                 Rule::EOI => break,
 
                 Rule::procedure_section => {
+                    let mut dead_after = None;
                     for proc_stmt in pair.into_inner() {
                         match proc_stmt.as_rule() {
                             Rule::create_stmt_stmt => self.add_user_stmt(proc_stmt)?,
@@ -257,10 +630,11 @@ impl Compiler {
                                 self.subs.push(sub);
                             }
                             _ => {
-                                indent!();
+                                self.check_reachable(&mut dead_after, &proc_stmt)?;
+                                indent!(self);
                                 let stmt = self.compile_subproc_stmt(proc_stmt)?;
                                 self.main.push(stmt);
-                                dedent!();
+                                dedent!(self);
                             }
                         }
                     }
@@ -270,6 +644,56 @@ impl Compiler {
             }
         }
 
+        if self.include_depth == 0 {
+            self.check_defs()?;
+        }
+
+        Ok(())
+    }
+
+    /// Make sure every SUB that was called or MEMOIZEd got defined.
+    fn check_defs(&self) -> LDPLResult<()> {
+        if !self.expected_defs.is_empty() {
+            let mut sites: Vec<_> = self.expected_defs.values().map(|calls| &calls[0]).collect();
+            sites.sort_by(|a, b| (&a.file, a.line, a.col).cmp(&(&b.file, b.line, b.col)));
+            let list = sites
+                .iter()
+                .map(|site| format!("{} (called at {}:{})", site.name, site.line, site.col))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let first = sites[0];
+            let mut err = LDPLError::new(
+                format!("Sub-procedures called but never defined: {}", list),
+                first.line,
+                first.col,
+                first.name.len(),
+            );
+            err.file = first.file.clone();
+            return Err(err);
+        }
+
+        let earliest = |site: &&CallSite| (site.file.clone(), site.line, site.col);
+
+        if let Some(site) = self
+            .memoized
+            .iter()
+            .filter(|(sub, _)| !self.defs.contains_key(*sub))
+            .map(|(_, site)| site)
+            .min_by_key(earliest)
+        {
+            return Err(site.error(format!(
+                "MEMOIZE used with unknown sub-procedure: {}",
+                site.name
+            )));
+        }
+
+        if let Some(site) = self.declared.values().min_by_key(earliest) {
+            return Err(site.error(format!(
+                "DECLAREd sub-procedure never defined: {}",
+                site.name
+            )));
+        }
+
         Ok(())
     }
 
@@ -279,8 +703,16 @@ impl Compiler {
         let stmt = pair.into_inner().next().unwrap();
         match stmt.as_rule() {
             Rule::include_stmt => {
-                let file = stmt.into_inner().next().unwrap().as_str();
-                self.load_and_compile(&self.expand_path(unquote(file)))?;
+                let node = stmt.into_inner().next().unwrap();
+                let file = self.expand_path(unquote(node.as_str()));
+                if let Some(cycle) = self.include_cycle(&file) {
+                    return span_error!(node, cycle);
+                }
+                self.includes.push(file.clone());
+                self.include_depth += 1;
+                let out = self.load_and_compile(&file);
+                self.include_depth -= 1;
+                out?;
             }
             Rule::extension_stmt => {
                 let ext_file = unquote(stmt.into_inner().next().unwrap().as_str());
@@ -290,13 +722,94 @@ impl Compiler {
                 let flag = unquote(stmt.into_inner().next().unwrap().as_str());
                 self.add_flag(flag.into())?;
             }
+            Rule::memoize_stmt => {
+                let ident = stmt.into_inner().next().unwrap();
+                let name = ident.as_str().to_uppercase();
+                if self.defs.contains_key(&name) {
+                    return span_error!(
+                        ident,
+                        "MEMOIZE must come before SUB-PROCEDURE {} is defined",
+                        ident.as_str()
+                    );
+                }
+                let (line, col) = ident.as_span().start_pos().line_col();
+                let site = CallSite {
+                    name: ident.as_str().to_string(),
+                    file: self.path.clone(),
+                    line,
+                    col,
+                    args: vec![],
+                };
+                self.memoized.insert(name, site);
+            }
+            Rule::declare_stmt => {
+                let mut iter = stmt.into_inner().peekable();
+                if iter.next_if(|n| n.as_rule() == Rule::external).is_some() {
+                    let ident = iter.next().unwrap();
+                    let name = ident.as_str().to_uppercase();
+                    if self.extern_defs.contains_key(&name) {
+                        return span_error!(
+                            ident,
+                            "EXTERNAL SUB-PROCEDURE {} is already declared or defined",
+                            ident.as_str()
+                        );
+                    }
+                    let types = check_type_names(iter.next())?;
+                    self.set_forward(&mangle_extern(ident.as_str()), &types);
+                    self.extern_defs.insert(name, types);
+                    return Ok(());
+                }
+                let ident = iter.next().unwrap();
+                let name = ident.as_str().to_uppercase();
+                if self.defs.contains_key(&name) {
+                    return span_error!(
+                        ident,
+                        "SUB-PROCEDURE {} is already declared or defined",
+                        ident.as_str()
+                    );
+                }
+                let types = check_type_names(iter.next())?;
+                self.set_forward(&mangle_sub(ident.as_str()), &types);
+                let (line, col) = ident.as_span().start_pos().line_col();
+                let site = CallSite {
+                    name: ident.as_str().to_string(),
+                    file: self.path.clone(),
+                    line,
+                    col,
+                    args: types.clone(),
+                };
+                self.defs.insert(name.clone(), types);
+                self.declared.insert(name, site);
+            }
+            Rule::enum_stmt => {
+                let mut iter = stmt.into_inner();
+                iter.next(); // group name
+                for (i, member) in iter.enumerate() {
+                    let name = member.as_str().to_uppercase();
+                    if self.globals.contains_key(&name) {
+                        return span_error!(
+                            member,
+                            "Duplicate declaration for variable: {}",
+                            member.as_str()
+                        );
+                    }
+                    self.vars
+                        .push(format!("ldpl_number {} = {};", mangle_var(&name), i));
+                    self.globals.insert(name, LDPLType::Number);
+                }
+            }
             Rule::using_stmt => {
-                let name = stmt.into_inner().next().unwrap().as_str().to_lowercase();
-                let mut path = format!("{}{}/{}.ldpl", LPM_LOCATION, name, name);
-                if path.contains('~') {
-                    path = path.replace("~", env!("HOME"));
+                let node = stmt.into_inner().next().unwrap();
+                let name = node.as_str().to_lowercase();
+                let path = format!("{}{}/{}.ldpl", lpm_location(), name, name);
+                if let Some(cycle) = self.include_cycle(&path) {
+                    return span_error!(node, cycle);
                 }
-                self.load_and_compile(&path)?;
+                self.includes.push(path.clone());
+                self.include_depth += 1;
+                let out = self.load_and_compile(&path);
+                self.include_depth -= 1;
+                out?;
             }
             _ => unexpected!(stmt),
         }
@@ -312,8 +825,11 @@ impl Compiler {
             let is_extern = def.as_rule() == Rule::external_type_def;
 
             let mut parts = def.into_inner();
-            let ident = parts.next().unwrap().as_str();
-            let typename = parts.next().unwrap().as_str();
+            let ident_pair = parts.next().unwrap();
+            let ident = ident_pair.as_str();
+            let type_pair = parts.next().unwrap();
+            let ldpltype = check_type_name(&type_pair)?;
+            let typename = type_pair.as_str();
             let varname = ident.to_uppercase();
             let mut var: String;
 
@@ -322,28 +838,35 @@ impl Compiler {
                 var = format!("extern {} {}", compile_type(typename), mangle_extern(ident));
             } else {
                 var = format!("{} {}", compile_type(typename), mangle_var(ident));
-                if typename == "number" {
+                if ldpltype.is_number() {
                     var.push_str(" = 0");
-                } else if typename == "text" {
+                } else if ldpltype.is_text() {
                     var.push_str(r#" = """#);
                 }
             }
 
-            let ldpltype = LDPLType::from(typename);
             if local {
                 if self.locals.contains_key(&varname) {
-                    return error!("Duplicate declaration for variable: {}", ident);
+                    return span_error!(
+                        ident_pair,
+                        "Duplicate declaration for variable: {}",
+                        ident
+                    );
                 }
                 self.locals.insert(varname, ldpltype);
             } else {
                 if self.globals.contains_key(&varname) {
-                    return error!("Duplicate declaration for variable: {}", ident);
+                    return span_error!(
+                        ident_pair,
+                        "Duplicate declaration for variable: {}",
+                        ident
+                    );
                 }
                 self.globals.insert(varname, ldpltype);
             };
 
             var.push(';');
-            out.push(emit_line!(var));
+            out.push(emit_line!(self, var));
         }
 
         Ok(format!("{}\n", out.join("")))
@@ -359,10 +882,12 @@ impl Compiler {
             assert!(def.as_rule() == Rule::type_def);
             let mut parts = def.into_inner();
             let ident = parts.next().unwrap().as_str();
-            let typename = parts.next().unwrap().as_str();
-            let typetype = LDPLType::from(typename);
+            let type_pair = parts.next().unwrap();
+            let typetype = check_type_name(&type_pair)?;
+            let typename = type_pair.as_str();
             types.push(typetype.clone());
             self.locals.insert(ident.to_uppercase(), typetype);
+            self.param_names.push(mangle_var(ident));
             out.push(format!("{}& {}", compile_type(typename), mangle_var(ident)));
         }
 
@@ -374,70 +899,123 @@ impl Compiler {
         let mut iter = pair.into_inner();
         let mut params = String::new();
         let mut param_types = vec![];
+        self.param_names.clear();
         let mut vars = String::new();
         let mut body: Vec<String> = vec![];
         let mut is_extern = false;
-        let ident;
 
         self.locals.clear();
         self.in_sub = true;
-        indent!();
+        indent!(self);
 
-        let first = iter.next().unwrap();
-        if first.as_rule() == Rule::external {
+        let mut name = iter.next().unwrap();
+        if name.as_rule() == Rule::external {
             is_extern = true;
-            ident = iter.next().unwrap().as_str();
-        } else {
-            ident = first.as_str();
+            name = iter.next().unwrap();
         }
+        let ident = name.as_str();
 
         let ident_upper = ident.to_uppercase();
 
-        if self.defs.contains_key(&ident_upper) {
-            return error!("Redefining existing SUB-PROCEDURE: {}", ident);
-        }
-
-        if self.expected_defs.contains_key(&ident_upper) {
-            self.expected_defs.remove(&ident_upper);
+        if self.defs.contains_key(&ident_upper) && !self.declared.contains_key(&ident_upper) {
+            return span_error!(name, "Redefining existing SUB-PROCEDURE: {}", ident);
         }
 
-        let mut node = iter.next().unwrap();
-
-        if node.as_rule() == Rule::sub_param_section {
+        // both sections are optional and may be empty
+        let mut iter = iter.peekable();
+        if let Some(node) = iter.next_if(|n| n.as_rule() == Rule::sub_param_section) {
             let (types, string) = self.compile_params(node)?;
             params = string;
             param_types = types;
-            node = iter.next().unwrap();
         }
 
-        if node.as_rule() == Rule::sub_data_section {
-            vars = self.compile_data(node, true)?;
-            node = iter.next().unwrap();
+        if self.declared.remove(&ident_upper).is_some() {
+            let want = &self.defs[&ident_upper];
+            if *want != param_types {
+                return span_error!(
+                    name,
+                    "SUB-PROCEDURE {} doesn't match its DECLARE: declared with ({}), defined with ({})",
+                    ident,
+                    show_types(want),
+                    show_types(&param_types)
+                );
+            }
         }
 
-        // done with the header, register this SUB so we
-        // can call it recursively in the body.
-        self.defs.insert(ident.to_uppercase(), param_types);
+        if is_extern {
+            if let Some(want) = self.extern_defs.get(&ident_upper) {
+                if *want != param_types {
+                    return span_error!(
+                        name,
+                        "EXTERNAL SUB-PROCEDURE {} doesn't match its DECLARE: declared with ({}), defined with ({})",
+                        ident,
+                        show_types(want),
+                        show_types(&param_types)
+                    );
+                }
+            }
+            self.extern_defs
+                .insert(ident_upper.clone(), param_types.clone());
+        }
 
-        loop {
-            body.push(self.compile_subproc_stmt(node)?);
-            let node_opt = iter.next();
-            if node_opt.is_none() {
-                break;
-            } else {
-                node = node_opt.unwrap();
+        // now that we know the params, check the calls made before
+        // this SUB was defined
+        let mut calls = self.expected_defs.remove(&ident_upper).unwrap_or_default();
+        if is_extern {
+            calls.extend(
+                self.expected_externs
+                    .remove(&ident_upper)
+                    .unwrap_or_default(),
+            );
+        }
+        for call in calls {
+            if let Some(msg) = call_args_mismatch(&call.name, &param_types, &call.args) {
+                let mut err = LDPLError::new(msg, call.line, call.col, call.name.len());
+                err.file = call.file;
+                return Err(err);
             }
         }
-        dedent!();
-        self.in_sub = false;
+
+        if let Some(node) = iter.next_if(|n| n.as_rule() == Rule::sub_data_section) {
+            vars = self.compile_data(node, true)?;
+        }
+
+        // done with the header, register this SUB so we
+        // can call it recursively in the body.
+        self.defs.insert(ident.to_uppercase(), param_types.clone());
 
         let mangled = if is_extern {
             mangle_extern(ident)
         } else {
             mangle_sub(ident)
         };
+        // calls made before now guessed the params from their arguments
+        if self.forwards.iter().any(|(fwd, _)| *fwd == mangled) {
+            self.set_forward(&mangled, &param_types);
+        }
+
+        if self.profile {
+            body.push(emit_line!(
+                self,
+                "ldpl_profile_counts[\"{}\"]++;",
+                escape_cpp_string(ident)
+            ));
+        }
+
+        let mut dead_after = None;
+        for node in iter {
+            self.check_reachable(&mut dead_after, &node)?;
+            body.push(self.compile_subproc_stmt(node)?);
+        }
+        dedent!(self);
+        self.in_sub = false;
+
+        if self.memoized.contains_key(&ident_upper) {
+            return self.compile_memoized_sub(name, &mangled, &params, &param_types, vars, body);
+        }
 
         emit!(
+            self,
             "void {}({}) {{\n{}{}}}\n",
             mangled,
             params,
@@ -446,15 +1024,93 @@ impl Compiler {
         )
     }
 
+    /// MEMOIZE SUB: the real body gets renamed and the SUB becomes a
+    /// wrapper that caches the final value of every param, keyed on
+    /// the values they had going in (output params included). Only
+    /// correct for pure SUBs: same inputs, same outputs, no side
+    /// effects that need to happen again.
+    fn compile_memoized_sub(
+        &self,
+        name: Pair<Rule>,
+        mangled: &str,
+        params: &str,
+        types: &[LDPLType],
+        vars: String,
+        body: Vec<String>,
+    ) -> LDPLResult<String> {
+        if types.iter().any(|t| t.is_collection()) {
+            return span_error!(
+                name,
+                "MEMOIZE only works with number and text parameters: {}",
+                name.as_str()
+            );
+        }
+
+        // lowercase so it can't clash with a mangled (uppercase) name
+        let inner = format!("{}_memo_body", mangled);
+        let names = self.param_names.join(", ");
+        let cpp_types = types
+            .iter()
+            .map(|t| compile_type(&t.to_string()))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let mut out = vec![
+            emit_line!(self, "void {}({});", mangled, params),
+            emit_line!(
+                self,
+                "void {}({}) {{\n{}{}}}",
+                inner,
+                params,
+                vars,
+                body.join("")
+            ),
+            emit_line!(self, "void {}({}) {{", mangled, params),
+        ];
+        indent!(self);
+        out.push(emit_line!(
+            self,
+            "static unordered_map<string, tuple<{}>> cache;",
+            cpp_types
+        ));
+        out.push(emit_line!(self, "string key;"));
+        for (name, t) in self.param_names.iter().zip(types) {
+            if t.is_number() {
+                out.push(emit_line!(
+                    self,
+                    "memo_key_add(key, to_ldpl_string({}).str_rep());",
+                    name
+                ));
+            } else {
+                out.push(emit_line!(self, "memo_key_add(key, {}.str_rep());", name));
+            }
+        }
+        out.push(emit_line!(self, "auto hit = cache.find(key);"));
+        out.push(emit_line!(self, "if (hit != cache.end()) {"));
+        indent!(self);
+        out.push(emit_line!(self, "tie({}) = hit->second;", names));
+        out.push(emit_line!(self, "return;"));
+        dedent!(self);
+        out.push(emit_line!(self, "}"));
+        out.push(emit_line!(self, "{}({});", inner, names));
+        out.push(emit_line!(self, "cache[key] = make_tuple({});", names));
+        dedent!(self);
+        out.push(emit_line!(self, "}"));
+
+        Ok(out.join(""))
+    }
+
     /// Read CREATE STATEMENT and add mapping as a user_stmt
     /// CREATE STATEMENT <text> EXECUTING <ident>
     fn add_user_stmt(&mut self, pair: Pair<Rule>) -> LDPLResult<()> {
         let mut iter = pair.into_inner();
         let stmt = unquote(iter.next().unwrap().as_str()).to_uppercase();
-        let ident = iter.next().unwrap().as_str().to_uppercase();
+        let sub = iter.next().unwrap();
+        let ident = sub.as_str().to_uppercase();
 
         if !self.defs.contains_key(&ident) {
-            return error!(
+            return span_error!(
+                sub,
                 "CREATE STATEMENT used with unknown sub-procedure: {}",
                 ident
             );
@@ -471,6 +1127,7 @@ impl Compiler {
 
     /// Translate a user-defined STATEMENT into a SUB call.
     fn compile_user_stmt(&mut self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let span = pair.clone();
         let iter = pair.into_inner();
 
         // we can't just take pair.as_str() because that returns the
@@ -482,14 +1139,23 @@ impl Compiler {
             .join(" ")
             .to_uppercase();
 
-        let types_iter = iter.clone(); // for inferring types of stmt parts
-        let call_parts: Vec<_> = stmt.split(" ").map(|p| p.to_uppercase()).collect();
+        // for inferring types of stmt parts
+        let types_iter = iter.clone();
+        // one part per node, so text with spaces in a lookup like
+        // `ages:"Jo Ann"` stays in one piece
+        let call_parts: Vec<_> = iter.clone().map(|p| p.as_str().to_uppercase()).collect();
         let mut matched = false;
         let mut sub_name = String::new();
 
         // args is list of (index, type)
         let mut args: Vec<(usize, LDPLType)> = vec![];
 
+        // subs whose statement words matched but whose params didn't,
+        // so we can tell the user what they could have meant
+        let mut candidates: Vec<String> = vec![];
+        let mut call_types: Vec<LDPLType> = vec![];
+        let mut type_err = None;
+
         'outer: for (pattern, subs) in &self.user_stmts {
             let mut def_parts: Vec<_> = pattern.split(" ").collect();
 
@@ -498,13 +1164,22 @@ impl Compiler {
                 continue;
             }
 
+            args.clear();
             let mut types_iter = types_iter.clone(); // re-use each loop
                                                      // compare each word in the pattern
             for (i, call_part) in call_parts.iter().enumerate() {
                 let node = types_iter.next().unwrap();
                 let def_part = def_parts.remove(0); // safe - we checked size
                 if def_part == "$" {
-                    args.push((i, self.scalar_type_of_expr(node)?.clone()));
+                    // a word that isn't a variable just means this
+                    // pattern doesn't fit, unless nothing else does
+                    match self.type_of_expr(node) {
+                        Ok(t) => args.push((i, t.clone())),
+                        Err(e) => {
+                            type_err.get_or_insert(e);
+                            continue 'outer;
+                        }
+                    }
                 } else if *call_part != def_part {
                     continue 'outer;
                 }
@@ -521,13 +1196,20 @@ impl Compiler {
                         matched = true;
                         break 'outer;
                     }
+                    candidates.push(format!("{}({})", sub, show_types(sub_params)));
                 }
             }
+            call_types = call_params;
+        }
 
-            // if we're here, we didn't find a match
-            return error!(
-                "Statement arguments didn't match any sub-procedures: {}",
-                stmt
+        if !matched && !candidates.is_empty() {
+            candidates.sort();
+            return span_error!(
+                span,
+                "Statement arguments didn't match any sub-procedures: {}; got ({}); candidates: {}",
+                stmt,
+                show_types(&call_types),
+                candidates.join(", ")
             );
         }
 
@@ -540,11 +1222,15 @@ impl Compiler {
             return Ok(format!(
                 "{}\n{}",
                 prefix,
-                emit_line!("{}({});", mangle_sub(&sub_name), args)
+                emit_line!(self, "{}({});", mangle_sub(&sub_name), args)
             ));
         }
 
-        error!("Unknown statement: {}", stmt)
+        if let Some(err) = type_err {
+            return Err(err);
+        }
+
+        span_error!(span, "Unknown statement: {}", stmt)
     }
 
     /// Used in CALL and when calling user-defined statements.
@@ -558,27 +1244,33 @@ impl Compiler {
     ///   as prepared by PREFIX.
     fn compile_arg_list<'p, I: Iterator<Item = Pair<'p, Rule>>>(
         &mut self,
-        mut iter: I,
+        iter: I,
     ) -> LDPLResult<(String, String)> {
         let mut prefix = vec![];
         let mut args = vec![];
 
-        while let Some(arg) = iter.next() {
+        for arg in iter {
             match arg.as_rule() {
                 Rule::number => {
                     let var = format!("LPVAR_{}", self.tmp_id);
                     self.tmp_id += 1;
                     prefix.push(emit_line!(
+                        self,
                         "ldpl_number {} = {};",
                         var,
                         self.compile_expr(arg)?
                     ));
                     args.push(var);
                 }
-                Rule::text | Rule::linefeed => {
+                Rule::text | Rule::linefeed | Rule::crlf => {
                     let var = format!("LPVAR_{}", self.tmp_id);
                     self.tmp_id += 1;
-                    prefix.push(emit_line!("chText {} = {};", var, self.compile_expr(arg)?));
+                    prefix.push(emit_line!(
+                        self,
+                        "chText {} = {};",
+                        var,
+                        self.compile_expr(arg)?
+                    ));
                     args.push(var);
                 }
                 Rule::var => args.push(self.compile_expr(arg)?),
@@ -593,11 +1285,14 @@ impl Compiler {
     fn compile_subproc_stmt(&mut self, pair: Pair<Rule>) -> LDPLResult<String> {
         let mut out = vec![];
 
+        let (line, _) = pair.as_span().start_pos().line_col();
+
         out.push(match pair.as_rule() {
             // control flow
             Rule::call_stmt => self.compile_call_stmt(pair)?,
+            Rule::call_library_stmt => self.compile_call_library_stmt(pair)?,
             Rule::if_stmt => self.compile_if_stmt(pair)?,
-            Rule::else_stmt => return error!("unexpected ELSE statement"),
+            Rule::else_stmt => return span_error!(pair, "unexpected ELSE statement"),
             Rule::while_stmt => self.compile_while_stmt(pair)?,
             Rule::for_each_stmt => self.compile_for_each_stmt(pair)?,
             Rule::for_stmt => self.compile_for_stmt(pair)?,
@@ -609,47 +1304,100 @@ impl Compiler {
             Rule::wait_stmt => self.compile_wait_stmt(pair)?,
             Rule::store_quote_stmt => self.compile_store_quote_stmt(pair)?,
             Rule::store_stmt => self.compile_store_stmt(pair)?,
+            Rule::store_test_stmt => self.compile_store_test_stmt(pair)?,
+            Rule::sub_exists_stmt => self.compile_sub_exists_stmt(pair)?,
+            Rule::reset_stmt => self.compile_reset_stmt(pair)?,
+            Rule::swap_stmt => self.compile_swap_stmt(pair)?,
 
             // math
             Rule::solve_stmt => self.compile_solve_stmt(pair)?,
-            Rule::floor_stmt => self.compile_floor_stmt(pair)?,
+            Rule::floor_stmt => self.compile_rounding_stmt(pair, "floor")?,
+            Rule::ceil_stmt => self.compile_rounding_stmt(pair, "ceil")?,
+            Rule::round_stmt => self.compile_rounding_stmt(pair, "round")?,
+            Rule::negate_stmt => self.compile_negate_stmt(pair)?,
+            Rule::incr_stmt => self.compile_incr_stmt(pair, "+=")?,
+            Rule::decr_stmt => self.compile_incr_stmt(pair, "-=")?,
+            Rule::toggle_stmt => self.compile_toggle_stmt(pair)?,
             Rule::modulo_stmt => self.compile_modulo_stmt(pair)?,
+            Rule::random_stmt => self.compile_random_stmt(pair)?,
+            Rule::seed_rand_stmt => self.compile_seed_rand_stmt(pair)?,
 
             // text
             Rule::join_stmt => self.compile_join_stmt(pair)?,
             Rule::old_join_stmt => self.compile_old_join_stmt(pair)?,
+            Rule::join_list_stmt => self.compile_join_list_stmt(pair)?,
             Rule::replace_stmt => self.compile_replace_stmt(pair)?,
             Rule::split_stmt => self.compile_split_stmt(pair)?,
+            Rule::tokenize_stmt => self.compile_tokenize_stmt(pair)?,
+            Rule::parse_csv_stmt => self.compile_parse_csv_stmt(pair)?,
+            Rule::format_csv_stmt => self.compile_format_csv_stmt(pair)?,
+            Rule::format_percent_stmt => self.compile_format_percent_stmt(pair)?,
+            Rule::parse_config_stmt => self.compile_parse_config_stmt(pair)?,
+            Rule::parse_radix_stmt => self.compile_parse_radix_stmt(pair)?,
+            Rule::case_stmt => self.compile_case_stmt(pair)?,
+            Rule::expand_stmt => self.compile_expand_stmt(pair)?,
             Rule::get_char_stmt => self.compile_get_char_stmt(pair)?,
             Rule::get_ascii_stmt => self.compile_get_ascii_stmt(pair)?,
             Rule::get_char_code_stmt => self.compile_get_char_code_stmt(pair)?,
             Rule::get_index_stmt => self.compile_get_index_stmt(pair)?,
+            Rule::contains_stmt => self.compile_contains_stmt(pair)?,
+            Rule::edit_distance_stmt => self.compile_edit_distance_stmt(pair)?,
             Rule::count_stmt => self.compile_count_stmt(pair)?,
             Rule::substr_stmt => self.compile_substring_stmt(pair)?,
+            Rule::text_range_stmt => self.compile_text_range_stmt(pair)?,
             Rule::trim_stmt => self.compile_trim_stmt(pair)?,
 
             // list
+            Rule::push_front_stmt => self.compile_push_front_stmt(pair)?,
             Rule::push_stmt => self.compile_push_stmt(pair)?,
             Rule::delete_stmt => self.compile_delete_stmt(pair)?,
+            Rule::insert_at_stmt => self.compile_insert_at_stmt(pair)?,
+            Rule::delete_at_stmt => self.compile_delete_at_stmt(pair)?,
+            Rule::map_each_stmt => self.compile_map_each_stmt(pair)?,
+            Rule::filter_stmt => self.compile_filter_stmt(pair)?,
+            Rule::sort_stmt => self.compile_sort_stmt(pair)?,
+            Rule::reverse_stmt => self.compile_reverse_stmt(pair)?,
+            Rule::fill_range_stmt => self.compile_fill_range_stmt(pair)?,
+            Rule::shuffle_stmt => self.compile_shuffle_stmt(pair)?,
+            Rule::replace_range_stmt => self.compile_replace_range_stmt(pair)?,
+            Rule::reduce_stmt => self.compile_reduce_stmt(pair)?,
 
             // map
             Rule::get_keys_count_stmt => self.compile_get_keys_count_stmt(pair)?,
             Rule::get_keys_stmt => self.compile_get_keys_stmt(pair)?,
+            Rule::get_sorted_keys_stmt => self.compile_get_sorted_keys_stmt(pair)?,
+            Rule::get_key_of_value_stmt => self.compile_get_key_of_value_stmt(pair)?,
+            Rule::max_value_length_stmt => self.compile_max_value_length_stmt(pair)?,
+            Rule::delete_key_stmt => self.compile_delete_key_stmt(pair)?,
 
             // list + map
             Rule::clear_stmt => self.compile_clear_stmt(pair)?,
             Rule::copy_stmt => self.compile_copy_stmt(pair)?,
+            Rule::serialize_stmt => self.compile_serialize_stmt(pair)?,
+            Rule::deserialize_stmt => self.compile_deserialize_stmt(pair)?,
 
-            // list + text
+            // list + map + text
             Rule::get_length_stmt => self.compile_get_length_stmt(pair)?,
 
             // io
-            Rule::display_stmt => self.compile_display_stmt(pair)?,
+            Rule::display_stmt => self.compile_display_stmt(pair, "cout")?,
+            Rule::display_error_stmt => self.compile_display_error_stmt(pair)?,
             Rule::load_stmt => self.compile_load_stmt(pair)?,
             Rule::write_stmt => self.compile_write_stmt(pair)?,
             Rule::append_stmt => self.compile_append_stmt(pair)?,
             Rule::accept_stmt => self.compile_accept_stmt(pair)?,
+            Rule::accept_default_stmt => self.compile_accept_default_stmt(pair)?,
             Rule::execute_stmt => self.compile_execute_stmt(pair)?,
+            Rule::program_dir_stmt => self.compile_program_dir_stmt(pair)?,
+            Rule::total_memory_stmt => {
+                self.compile_system_info_stmt(pair, "GET TOTAL MEMORY", "get_total_memory")?
+            }
+            Rule::cpu_count_stmt => {
+                self.compile_system_info_stmt(pair, "GET CPU COUNT", "get_cpu_count")?
+            }
+            Rule::monotonic_time_stmt => self.compile_monotonic_time_stmt(pair)?,
+            Rule::load_library_stmt => self.compile_load_library_stmt(pair)?,
+            Rule::unload_library_stmt => self.compile_unload_library_stmt(pair)?,
 
             // user-defined statement (made via CREATE STATEMENT)
             Rule::user_stmt => self.compile_user_stmt(pair)?,
@@ -657,9 +1405,36 @@ impl Compiler {
             _ => unexpected!(pair),
         });
 
+        if self.debug_lines {
+            return Ok(self.tag_lines(line, &out.join("")));
+        }
         Ok(out.join(""))
     }
 
+    /// Put a `#line` directive before every line of C++ generated for
+    /// the statement on `line`. Lines from nested statements are
+    /// already tagged, so they keep their own.
+    fn tag_lines(&self, line: usize, code: &str) -> String {
+        let file = self.path.as_deref().unwrap_or("<stdin>");
+        let directive = format!("#line {} \"{}\"\n", line, escape_cpp_string(file));
+
+        let mut out = String::new();
+        let mut tagged = false;
+        for code_line in code.lines() {
+            if code_line.starts_with("#line ") {
+                tagged = true;
+            } else {
+                if !tagged {
+                    out.push_str(&directive);
+                }
+                tagged = false;
+            }
+            out.push_str(code_line);
+            out.push('\n');
+        }
+        out
+    }
+
     ////
     // CONTROL FLOW
 
@@ -671,7 +1446,7 @@ impl Compiler {
         let var = iter.next().unwrap();
         let val = self.compile_expr_for_type(expr, self.type_of_var(var.clone())?)?;
 
-        emit!("{} = {};", self.compile_var(var)?, val)
+        emit!(self, "{} = {};", self.compile_var(var)?, val)
     }
 
     /// STORE QUOTE IN _
@@ -681,36 +1456,212 @@ impl Compiler {
         let txt = iter.next().unwrap().as_str();
         // remove extra preceeding \n from txt. parser limitation.
         if !txt.is_empty() {
-            emit!(
-                r#"{} = "{}";"#,
+            emit!(self, r#"{} = "{}";"#, var, escape_cpp_string(&txt[1..]))
+        } else {
+            emit!(self, "{} = \"\";", var)
+        }
+    }
+
+    /// STORE TEST _ IN _
+    fn compile_store_test_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let test = self.compile_test_stmt(iter.next().unwrap())?;
+        let var = iter.next().unwrap();
+        if !self.type_of_var(var.clone())?.is_number() {
+            return span_error!(var, "STORE TEST needs a number variable: {}", var.as_str());
+        }
+        emit!(self, "{} = {} ? 1 : 0;", self.compile_var(var)?, test)
+    }
+
+    /// STORE SUB EXISTS _ IN _
+    /// Decided at compile time: 1 if the SUB has been defined or
+    /// DECLAREd above this statement, 0 if not.
+    fn compile_sub_exists_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let sub = iter.next().unwrap().as_str().to_uppercase();
+        let var = iter.next().unwrap();
+        if !self.type_of_var(var.clone())?.is_number() {
+            return span_error!(
                 var,
-                &txt[1..].replace("\n", "\\\n\\n").replace("\"", "\\\"")
+                "STORE SUB EXISTS needs a number variable: {}",
+                var.as_str()
+            );
+        }
+        emit!(
+            self,
+            "{} = {};",
+            self.compile_var(var)?,
+            if self.defs.contains_key(&sub) { 1 } else { 0 }
+        )
+    }
+
+    /// RESET _
+    fn compile_reset_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let var = pair.into_inner().next().unwrap();
+        let vartype = self.type_of_var(var.clone())?;
+        let var = self.compile_var(var)?;
+
+        if vartype.is_number() {
+            emit!(self, "{} = 0;", var)
+        } else if vartype.is_text() {
+            emit!(self, r#"{} = "";"#, var)
+        } else {
+            emit!(self, "{}.inner_collection.clear();", var)
+        }
+    }
+
+    /// SWAP _ WITH _
+    fn compile_swap_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let (left, right) = (iter.next().unwrap(), iter.next().unwrap());
+        let left_type = self.type_of_var(left.clone())?;
+        let right_type = self.type_of_var(right.clone())?;
+        if left_type != right_type {
+            return span_error!(
+                right,
+                "Can't SWAP {} ({}) with {} ({})",
+                left.as_str(),
+                left_type,
+                right.as_str(),
+                right_type
+            );
+        }
+
+        let (left, right) = (self.compile_var(left)?, self.compile_var(right)?);
+        if left_type.is_collection() {
+            emit!(
+                self,
+                "swap({}.inner_collection, {}.inner_collection);",
+                left,
+                right
             )
         } else {
-            emit!("{} = \"\";", var)
+            emit!(self, "swap({}, {});", left, right)
         }
     }
 
     /// RETURN
-    fn compile_return_stmt(&self, _pair: Pair<Rule>) -> LDPLResult<String> {
+    fn compile_return_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
         if !self.in_sub {
-            return error!("RETURN can't be used outside of SUB-PROCEDURE");
+            return span_error!(pair, "RETURN can't be used outside of SUB-PROCEDURE");
         }
-        emit!("return;")
+        emit!(self, "return;")
     }
 
     /// BREAK / CONTINUE
-    fn compile_loop_kw_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+    fn compile_loop_kw_stmt(&mut self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let span = pair.clone();
+        let mut iter = pair.into_inner();
+        let kw = iter.next().unwrap().as_str().to_lowercase();
         if self.in_loop.is_empty() {
-            return error!("{} can't be used without FOR/WHILE loop", pair.as_str());
+            return span_error!(
+                span,
+                "{} can't be used without FOR/WHILE loop",
+                kw.to_uppercase()
+            );
+        }
+
+        // BREAK N / CONTINUE N
+        let levels = match iter.next() {
+            None => 1,
+            Some(n) => match n.as_str().parse::<usize>() {
+                Ok(levels) if levels > 0 && levels <= self.in_loop.len() => levels,
+                _ => {
+                    return span_error!(
+                        n,
+                        "{} {} needs a whole number from 1 to {}, the number of loops it's in",
+                        kw.to_uppercase(),
+                        n.as_str(),
+                        self.in_loop.len()
+                    )
+                }
+            },
+        };
+        if levels == 1 {
+            return emit!(self, "{};", kw);
+        }
+
+        let idx = self.in_loop.len() - levels;
+        if self.in_loop[idx].id.is_none() {
+            self.in_loop[idx].id = Some(self.tmp_id);
+            self.tmp_id += 1;
+        }
+        let target = &mut self.in_loop[idx];
+        if kw == "break" {
+            target.break_label = true;
+        } else {
+            target.continue_label = true;
+        }
+        let id = target.id.unwrap();
+        emit!(self, "goto loop_{}_{};", id, kw)
+    }
+
+    /// Call with each statement of a block, in order, to catch the
+    /// first one that can't run because of a RETURN, EXIT, or GOTO
+    /// before it. That's a warning, or an error in strict mode.
+    /// LABELs and ELSEs start reachable code again.
+    fn check_reachable(
+        &mut self,
+        dead_after: &mut Option<&'static str>,
+        stmt: &Pair<Rule>,
+    ) -> LDPLResult<()> {
+        match stmt.as_rule() {
+            Rule::label_stmt | Rule::else_stmt => *dead_after = None,
+            _ => {
+                if let Some(kw) = dead_after.take() {
+                    let (line, col) = stmt.as_span().start_pos().line_col();
+                    let mut warning = LDPLError::new(
+                        format!("Unreachable statement after {}", kw),
+                        line,
+                        col,
+                        stmt.as_str().len(),
+                    );
+                    warning.file = self.path.clone();
+                    if self.strict {
+                        return Err(warning);
+                    }
+                    self.warnings.push(warning);
+                }
+                *dead_after = match stmt.as_rule() {
+                    Rule::return_stmt => Some("RETURN"),
+                    Rule::exit_stmt => Some("EXIT"),
+                    Rule::goto_stmt => Some("GOTO"),
+                    _ => None,
+                };
+            }
+        }
+        Ok(())
+    }
+
+    /// Start compiling the body of a loop.
+    fn push_loop(&mut self) {
+        self.in_loop.push(Loop::default());
+    }
+
+    /// Done with the body of a loop. Adds the label CONTINUE N jumps
+    /// to to the end of `body` and returns the one BREAK N jumps to,
+    /// for after the loop, if they're needed.
+    fn pop_loop(&mut self, body: &mut Vec<String>) -> String {
+        let lp = self.in_loop.pop().unwrap();
+        let id = lp.id.unwrap_or_default();
+        if lp.continue_label {
+            // a block, so the goto doesn't skip over any declarations
+            // in the label's scope
+            body.insert(0, emit_line!(self, "{"));
+            body.push(emit_line!(self, "}"));
+            body.push(format!("loop_{}_continue:;\n", id));
+        }
+        if lp.break_label {
+            format!("loop_{}_break:;\n", id)
+        } else {
+            String::new()
         }
-        emit!("{};", pair.as_str())
     }
 
     /// GOTO _
     fn compile_goto_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
         let label = pair.into_inner().next().unwrap();
-        emit!("goto label_{};", mangle(label.as_str()))
+        emit!(self, "goto label_{};", mangle(label.as_str()))
     }
 
     /// LABEL _
@@ -724,6 +1675,7 @@ impl Compiler {
     fn compile_wait_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
         let count = self.compile_expr(pair.into_inner().next().unwrap())?;
         emit!(
+            self,
             "std::this_thread::sleep_for(std::chrono::milliseconds((long int){}));",
             count
         )
@@ -731,7 +1683,7 @@ impl Compiler {
 
     /// EXIT
     fn compile_exit_stmt(&self, _pair: Pair<Rule>) -> LDPLResult<String> {
-        emit!("exit(0);")
+        emit!(self, "exit(0);")
     }
 
     /// CALL _ WITH _ ...
@@ -739,29 +1691,128 @@ impl Compiler {
         let call_stmt = pair.into_inner().next().unwrap();
         let is_extern = call_stmt.as_rule() == Rule::call_external_stmt;
         let mut iter = call_stmt.into_inner();
-        let ident = iter.next().unwrap().as_str();
-
-        if !is_extern && !self.defs.contains_key(&ident.to_uppercase()) {
-            self.expected_defs.insert(ident.to_uppercase(), true);
+        let name = iter.next().unwrap();
+        let ident = name.as_str();
+
+        // OUT arguments are passed like the others, but must be variables.
+        let mut call_args = vec![];
+        for arg in iter {
+            if arg.as_rule() != Rule::call_out_args {
+                call_args.push(arg);
+                continue;
+            }
+            for out in arg.into_inner() {
+                if out.as_rule() != Rule::var {
+                    return span_error!(
+                        out,
+                        "OUT arguments must be variables, but {} is a literal",
+                        out.as_str()
+                    );
+                }
+                call_args.push(out);
+            }
         }
 
-        let (prefix, params) = self.compile_arg_list(iter)?;
+        let args = call_args
+            .iter()
+            .cloned()
+            .map(|arg| self.type_of_expr(arg).cloned())
+            .collect::<LDPLResult<Vec<_>>>()?;
 
         let mangled = if is_extern {
             mangle_extern(ident)
         } else {
             mangle_sub(ident)
         };
+        if !self.forwards.iter().any(|(fwd, _)| *fwd == mangled) {
+            let params = if is_extern {
+                self.extern_defs.get(&ident.to_uppercase())
+            } else {
+                self.defs.get(&ident.to_uppercase())
+            };
+            let params = params.unwrap_or(&args).clone();
+            self.set_forward(&mangled, &params);
+        }
 
-        let fwd_decl = format!("void {}();", mangled);
-        if !self.forwards.contains(&fwd_decl) {
-            self.forwards.push(format!("void {}();\n", mangled));
+        let (defs, expected) = if is_extern {
+            (&self.extern_defs, &mut self.expected_externs)
+        } else {
+            (&self.defs, &mut self.expected_defs)
+        };
+        if let Some(params) = defs.get(&ident.to_uppercase()) {
+            if let Some(msg) = call_args_mismatch(ident, params, &args) {
+                return span_error!(name, msg);
+            }
+        } else {
+            let (line, col) = name.as_span().start_pos().line_col();
+            expected
+                .entry(ident.to_uppercase())
+                .or_default()
+                .push(CallSite {
+                    name: ident.to_string(),
+                    file: self.path.clone(),
+                    line,
+                    col,
+                    args,
+                });
         }
 
+        let (prefix, params) = self.compile_arg_list(call_args.into_iter())?;
+
         Ok(format!(
             "{}{}",
             prefix,
-            emit_line!("{}({});", mangled, params)
+            emit_line!(self, "{}({});", mangled, params)
+        ))
+    }
+
+    /// CALL LIBRARY FUNCTION _ FROM _ WITH _...
+    /// Literal arguments get temporaries like a normal CALL, so every
+    /// argument reaches the C++ helper as a reference it can write
+    /// numbers back into.
+    fn compile_call_library_stmt(&mut self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let name = iter.next().unwrap();
+        let handle = iter.next().unwrap();
+
+        if !self.type_of_expr(name.clone())?.is_text() {
+            return span_error!(
+                name,
+                "CALL LIBRARY FUNCTION expects a text name: {}",
+                name.as_str()
+            );
+        }
+        let t = self.type_of_expr(handle.clone())?;
+        if !t.is_number() {
+            return span_error!(
+                handle,
+                "CALL LIBRARY FUNCTION needs a number handle, but {} is a {}",
+                handle.as_str(),
+                t
+            );
+        }
+        let args: Vec<_> = iter.collect();
+        for arg in &args {
+            let t = self.type_of_expr(arg.clone())?;
+            if t.is_collection() {
+                return span_error!(
+                    arg,
+                    "CALL LIBRARY FUNCTION only passes numbers and text, but {} is a {}",
+                    arg.as_str(),
+                    t
+                );
+            }
+        }
+
+        let mut call = vec![self.compile_expr(handle)?, self.compile_expr(name)?];
+        let (prefix, params) = self.compile_arg_list(args.into_iter())?;
+        if !params.is_empty() {
+            call.push(params);
+        }
+        Ok(format!(
+            "{}{}",
+            prefix,
+            emit_line!(self, "ldpl_call_library({});", call.join(", "))
         ))
     }
 
@@ -786,28 +1837,134 @@ impl Compiler {
                 let right = self.compile_test_stmt(iter.next().unwrap())?;
                 out.push(format!("({} && {})", left, right));
             }
+            Rule::not_test_expr => {
+                let inner = self.compile_test_stmt(test.into_inner().next().unwrap())?;
+                out.push(format!("(!{})", inner));
+            }
             Rule::one_test_expr => out.push(self.compile_test_expr(test)?),
+            Rule::has_key_test_expr => out.push(self.compile_has_key_test(test)?),
             _ => unexpected!(test),
         }
         Ok(out.join(" "))
     }
 
     /// Single test expression. Use _stmt for expressions with OR / AND.
+    /// Chains like `a < b < c` become `((a < b) && (b < c))`. Operands
+    /// are plain vars and literals, so repeating `b` has no side effects.
     fn compile_test_expr(&self, pair: Pair<Rule>) -> LDPLResult<String> {
         let mut iter = pair.into_inner();
-        let left = self.compile_expr(iter.next().unwrap())?;
-        let mid = iter.next().unwrap();
-        let sign = match mid.as_rule() {
-            Rule::equal_expr => "==",
-            Rule::not_equal_expr => "!=",
-            Rule::gt_expr => ">",
-            Rule::lt_expr => "<",
-            Rule::gte_expr => ">=",
-            Rule::lte_expr => "<=",
-            _ => unexpected!(mid),
-        };
-        let right = self.compile_expr(iter.next().unwrap())?;
-        Ok(format!("({} {} {})", left, sign, right))
+        let mut left = iter.next().unwrap();
+        let mut tests = vec![];
+        while let Some(mid) = iter.next() {
+            if mid.as_rule() == Rule::equal_ignore_case_expr {
+                let right = iter.next().unwrap();
+                tests.push(self.compile_ignore_case_comparison(left, right.clone())?);
+                left = right;
+                continue;
+            }
+            let sign = match mid.as_rule() {
+                Rule::equal_expr => "==",
+                Rule::not_equal_expr => "!=",
+                Rule::gt_expr => ">",
+                Rule::lt_expr => "<",
+                Rule::gte_expr => ">=",
+                Rule::lte_expr => "<=",
+                _ => unexpected!(mid),
+            };
+            let right = iter.next().unwrap();
+            tests.push(self.compile_comparison(left, sign, right.clone())?);
+            left = right;
+        }
+
+        if tests.len() == 1 {
+            Ok(tests.remove(0))
+        } else {
+            Ok(format!("({})", tests.join(" && ")))
+        }
+    }
+
+    /// `map HAS KEY key`. Keys are text or numbers, like in map:key.
+    fn compile_has_key_test(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let map = iter.next().unwrap();
+        let key = iter.next().unwrap();
+
+        self.check_map_key("HAS KEY", map.clone(), key.clone())?;
+        Ok(format!(
+            "(has_key({}, {}))",
+            self.compile_var(map)?,
+            self.compile_expr(key)?
+        ))
+    }
+
+    /// `left EQUALS IGNORING CASE right`, for text only.
+    fn compile_ignore_case_comparison(
+        &self,
+        left: Pair<Rule>,
+        right: Pair<Rule>,
+    ) -> LDPLResult<String> {
+        for side in [&left, &right] {
+            let t = self.type_of_expr(side.clone())?;
+            if !t.is_text() {
+                return span_error!(
+                    side,
+                    "EQUALS IGNORING CASE compares text, but {} is a {}",
+                    side.as_str(),
+                    t
+                );
+            }
+        }
+        Ok(format!(
+            "(equals_ignore_case({}, {}))",
+            self.compile_expr(left)?,
+            self.compile_expr(right)?
+        ))
+    }
+
+    /// One `left sign right` comparison. Lists and maps can only be
+    /// compared for (in)equality with a collection of the same type.
+    fn compile_comparison(
+        &self,
+        left: Pair<Rule>,
+        sign: &str,
+        right: Pair<Rule>,
+    ) -> LDPLResult<String> {
+        let left_type = self.type_of_expr(left.clone()).ok();
+        let right_type = self.type_of_expr(right.clone()).ok();
+        let is_collection = |t: Option<&LDPLType>| t.is_some_and(|t| t.is_collection());
+
+        if is_collection(left_type) || is_collection(right_type) {
+            if left_type != right_type {
+                return span_error!(
+                    left,
+                    "Can't compare {} with {}",
+                    left.as_str(),
+                    right.as_str()
+                );
+            }
+            if sign != "==" && sign != "!=" {
+                return span_error!(
+                    left,
+                    "Collections can only be tested for equality: {} {} {}",
+                    left.as_str(),
+                    sign,
+                    right.as_str()
+                );
+            }
+            return Ok(format!(
+                "({}.inner_collection {} {}.inner_collection)",
+                self.compile_expr(left)?,
+                sign,
+                self.compile_expr(right)?
+            ));
+        }
+
+        Ok(format!(
+            "({} {} {})",
+            self.compile_expr(left)?,
+            sign,
+            self.compile_expr(right)?
+        ))
     }
 
     /// Coerce Number -> Text and Text -> Number.
@@ -836,6 +1993,7 @@ impl Compiler {
             Rule::number => self.compile_number(pair)?,
             Rule::text => pair.as_str().to_string(),
             Rule::linefeed => "\"\\n\"".to_string(),
+            Rule::crlf => "\"\\r\\n\"".to_string(),
             Rule::ident => self.mangle_var(pair.as_str()),
             _ => return error!("UNIMPLEMENTED: {:?}", pair),
         })
@@ -873,9 +2031,16 @@ impl Compiler {
     /// (a[b[1]]) and where b is a scalar (a[b][1]).
     fn compile_lookup_from_iter(&self, mut iter: Pairs<Rule>) -> LDPLResult<String> {
         let basevar = iter.next().unwrap();
+        // type of the collection being indexed by the current part
+        let mut container = self.type_of_expr(basevar.clone()).ok().cloned();
+        // what we've looked up so far, for errors
+        let mut name = basevar.as_str().to_string();
         let mut parts = vec![self.compile_expr(basevar)?];
         let mut copy = iter.clone();
-        while let Some(part) = iter.next() {
+        for part in iter {
+            self.check_lookup_key(&container, &name, part.clone(), copy.clone())?;
+            name = format!("{}:{}", name, part.as_str());
+
             // If it's an ident AND a variable AND a
             // container, then end this lookup and nest the
             // new one
@@ -890,32 +2055,94 @@ impl Compiler {
             copy.next(); // copy should be 1 step behind iter, to
                          // capture the current variable
 
+            container = match container {
+                Some(LDPLType::List(inner)) | Some(LDPLType::Map(inner)) => Some(*inner),
+                _ => None,
+            };
+
             // otherwise just keep adding index operations
             parts.push(format!("[{}]", self.compile_expr(part)?));
         }
         Ok(parts.join(""))
     }
 
+    /// Make sure `part` is the right kind of key for `container`:
+    /// LISTs take whole numbers, MAPs take text or numbers, and TEXT
+    /// takes either for character access. `rest` starts at `part`, in
+    /// case it's the start of a nested lookup.
+    fn check_lookup_key(
+        &self,
+        container: &Option<LDPLType>,
+        name: &str,
+        part: Pair<Rule>,
+        rest: Pairs<Rule>,
+    ) -> LDPLResult<()> {
+        let key = match part.as_rule() {
+            Rule::number => LDPLType::Number,
+            Rule::text => LDPLType::Text,
+            Rule::ident => match self.type_of_var(part.clone()) {
+                Ok(t) if t.is_collection() => self.type_of_lookup(rest)?.clone(),
+                Ok(t) => t.clone(),
+                Err(_) => return Ok(()),
+            },
+            _ => return Ok(()),
+        };
+
+        match (container, key) {
+            (Some(LDPLType::List(_)), LDPLType::Text) => span_error!(
+                part,
+                "LIST index must be a number, but {} is text",
+                part.as_str()
+            ),
+            // list:2.5 is a mistake, not a key
+            (Some(LDPLType::List(_)), _)
+                if part.as_rule() == Rule::number
+                    && part.as_str().parse::<f64>().is_ok_and(|n| n.fract() != 0.0) =>
+            {
+                span_error!(
+                    part,
+                    "LIST index must be a whole number, got {}",
+                    part.as_str()
+                )
+            }
+            (Some(LDPLType::Number), _) => span_error!(
+                part,
+                "{} is a number, so it can't be indexed with {}",
+                name,
+                part.as_str()
+            ),
+            (Some(_), LDPLType::List(_)) | (Some(_), LDPLType::Map(_)) => span_error!(
+                part,
+                "{} is a collection and can't be used as a key or index",
+                part.as_str()
+            ),
+            _ => Ok(()),
+        }
+    }
+
     /// WHILE _ DO / REPEAT
     fn compile_while_stmt(&mut self, pair: Pair<Rule>) -> LDPLResult<String> {
         let mut iter = pair.into_inner();
         let test = iter.next().unwrap();
         let test = self.compile_test_stmt(test)?;
 
-        self.in_loop.push(true);
+        self.push_loop();
         let mut body = vec![];
-        indent!();
+        indent!(self);
+        let mut dead_after = None;
         for node in iter {
+            self.check_reachable(&mut dead_after, &node)?;
             body.push(self.compile_subproc_stmt(node)?);
         }
-        dedent!();
-        self.in_loop.pop();
+        let after = self.pop_loop(&mut body);
+        dedent!(self);
 
         Ok(format!(
-            "{}{}{}",
-            emit_line!("while {} {{", test),
+            "{}{}{}{}",
+            emit_line!(self, "while {} {{", test),
             body.join(""),
-            emit_line!("}")
+            emit_line!(self, "}"),
+            after
         ))
     }
 
@@ -925,42 +2152,50 @@ impl Compiler {
         let test = iter.next().unwrap();
         let test = self.compile_test_stmt(test)?;
 
-        let mut body = vec![];
-        indent!();
+        // each branch is its test (None for ELSE) and its body
+        let mut branches = vec![(Some(test), vec![])];
+        indent!(self);
+        let mut dead_after = None;
         for node in iter {
-            match node.as_rule() {
-                Rule::else_stmt => body.push(self.compile_else_stmt(node)?),
-                _ => body.push(self.compile_subproc_stmt(node)?),
+            self.check_reachable(&mut dead_after, &node)?;
+            if node.as_rule() != Rule::else_stmt {
+                let stmt = self.compile_subproc_stmt(node)?;
+                branches.last_mut().unwrap().1.push(stmt);
+                continue;
+            }
+            if branches.last().unwrap().0.is_none() {
+                dedent!(self);
+                return span_error!(
+                    node,
+                    "Nothing can come after ELSE but END IF: {}",
+                    node.as_str()
+                );
             }
+            branches.push((self.compile_else_stmt(node)?, vec![]));
         }
-        dedent!();
+        dedent!(self);
 
-        Ok(format!(
-            "{}{}{}",
-            emit_line!("if {} {{", test),
-            body.join(""),
-            emit_line!("}")
-        ))
+        let mut out = vec![];
+        for (i, (test, body)) in branches.into_iter().enumerate() {
+            out.push(match (i, test) {
+                (0, Some(test)) => emit_line!(self, "if {} {{", test),
+                (_, Some(test)) => emit_line!(self, "}} else if {} {{", test),
+                (_, None) => emit_line!(self, "} else {"),
+            });
+            out.extend(body);
+        }
+        out.push(emit_line!(self, "}"));
+        Ok(out.join(""))
     }
 
     /// ELSE IF _ THEN
-    fn compile_else_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
-        let mut iter = pair.into_inner();
-
-        let test = if let Some(test_expr) = iter.next() {
-            Some(self.compile_test_stmt(test_expr)?)
-        } else {
-            None
-        };
-
-        dedent!();
-        let out = if test.is_some() {
-            emit!("}} else if {} {{", test.unwrap())
-        } else {
-            emit!("} else {")
-        };
-        indent!();
-        out
+    /// ELSE
+    /// Returns the test for ELSE IF, None for ELSE.
+    fn compile_else_stmt(&self, pair: Pair<Rule>) -> LDPLResult<Option<String>> {
+        match pair.into_inner().next() {
+            Some(test_expr) => Ok(Some(self.compile_test_stmt(test_expr)?)),
+            None => Ok(None),
+        }
     }
 
     /// FOR _ IN _ TO _ STEP _ DO / REPEAT
@@ -971,14 +2206,16 @@ impl Compiler {
         let to = self.compile_expr(iter.next().unwrap())?;
         let step = self.compile_expr(iter.next().unwrap())?;
 
-        self.in_loop.push(true);
-        indent!();
+        self.push_loop();
+        indent!(self);
         let mut body = vec![];
+        let mut dead_after = None;
         for node in iter {
+            self.check_reachable(&mut dead_after, &node)?;
             body.push(self.compile_subproc_stmt(node)?);
         }
-        dedent!();
-        self.in_loop.pop();
+        let after = self.pop_loop(&mut body);
+        dedent!(self);
 
         let init = format!("{} = {}", var, from);
         let test = format!(
@@ -990,10 +2227,11 @@ impl Compiler {
         let incr = format!("{} += {}", var, step);
 
         Ok(format!(
-            "{}{}{}",
-            emit_line!("for({}; {}; {}) {{", init, test, incr),
+            "{}{}{}{}",
+            emit_line!(self, "for({}; {}; {}) {{", init, test, incr),
             body.join(""),
-            emit_line!("}")
+            emit_line!(self, "}"),
+            after
         ))
     }
 
@@ -1012,24 +2250,28 @@ impl Compiler {
             ""
         };
 
-        self.in_loop.push(true);
-        indent!();
-        let mut body = vec![emit_line!("{} = {}{};", ident, range_var, method)];
+        self.push_loop();
+        indent!(self);
+        let mut body = vec![emit_line!(self, "{} = {}{};", ident, range_var, method)];
+        let mut dead_after = None;
         for node in iter {
+            self.check_reachable(&mut dead_after, &node)?;
             body.push(self.compile_subproc_stmt(node)?);
         }
-        dedent!();
-        self.in_loop.pop();
+        let after = self.pop_loop(&mut body);
+        dedent!(self);
 
         Ok(format!(
-            "{}{}{}",
+            "{}{}{}{}",
             emit_line!(
+                self,
                 "for (auto& {} : {}.inner_collection) {{",
                 range_var,
                 self.compile_expr(collection)?
             ),
             body.join(""),
-            emit_line!("}")
+            emit_line!(self, "}"),
+            after
         ))
     }
 
@@ -1043,25 +2285,142 @@ impl Compiler {
         let by = self.compile_expr(iter.next().unwrap())?;
         let var = self.compile_var(iter.next().unwrap())?;
 
-        emit!("{} = modulo({}, {});", var, base, by)
+        emit!(self, "{} = modulo({}, {});", var, base, by)
+    }
+
+    /// GET RANDOM IN _
+    /// GET RANDOM NUMBER FROM _ TO _ IN _
+    fn compile_random_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut parts: Vec<_> = pair.into_inner().collect();
+        let var = parts.pop().unwrap();
+        if !self.type_of_var(var.clone())?.is_number() {
+            return span_error!(var, "GET RANDOM stores into a number: {}", var.as_str());
+        }
+
+        let mut args = vec![];
+        for part in parts {
+            if !self.type_of_expr(part.clone())?.is_number() {
+                return span_error!(
+                    part,
+                    "GET RANDOM NUMBER expects a number: {}",
+                    part.as_str()
+                );
+            }
+            args.push(self.compile_expr(part)?);
+        }
+        emit!(
+            self,
+            "{} = get_random({});",
+            self.compile_var(var)?,
+            args.join(", ")
+        )
+    }
+
+    /// SEED RANDOM WITH _
+    fn compile_seed_rand_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let seed = self.compile_expr(pair.into_inner().next().unwrap())?;
+        emit!(self, "ldpl_rng().seed((unsigned long)({}));", seed)
     }
 
     /// FLOOR _
     /// FLOOR _ IN _
-    /// TODO: only FLOOR _ in 4.4
-    fn compile_floor_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+    /// CEIL _
+    /// CEIL _ IN _
+    /// ROUND _
+    /// ROUND _ IN _
+    /// `func` is the C++ function to call: floor, ceil, or round.
+    fn compile_rounding_stmt(&self, pair: Pair<Rule>, func: &str) -> LDPLResult<String> {
         let stmt = pair.into_inner().next().unwrap();
         let rule = stmt.as_rule();
         let mut iter = stmt.into_inner();
-        let left = self.compile_expr(iter.next().unwrap())?;
-        let mut right = left.clone();
-        match rule {
-            Rule::floor_in_stmt => right = self.compile_var(iter.next().unwrap())?,
-            Rule::floor_mut_stmt => {}
+        let expr = iter.next().unwrap();
+        let var = match rule {
+            Rule::floor_in_stmt | Rule::ceil_in_stmt | Rule::round_in_stmt => iter.next().unwrap(),
+            Rule::floor_mut_stmt | Rule::ceil_mut_stmt | Rule::round_mut_stmt => expr.clone(),
+            _ => unexpected!(rule),
+        };
+
+        let name = func.to_uppercase();
+        if !self.type_of_expr(expr.clone())?.is_number() {
+            return span_error!(expr, "{} expects a number, got {}", name, expr.as_str());
+        }
+        if !self.type_of_var(var.clone())?.is_number() {
+            return span_error!(
+                var,
+                "{} can only store into a number: {}",
+                name,
+                var.as_str()
+            );
+        }
+
+        emit!(
+            self,
+            "{} = {}({});",
+            self.compile_var(var)?,
+            func,
+            self.compile_expr(expr)?
+        )
+    }
+
+    /// NEGATE _
+    /// NEGATE _ IN _
+    fn compile_negate_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let stmt = pair.into_inner().next().unwrap();
+        let rule = stmt.as_rule();
+        let mut iter = stmt.into_inner();
+        let expr = iter.next().unwrap();
+        let var = match rule {
+            Rule::negate_in_stmt => iter.next().unwrap(),
+            Rule::negate_mut_stmt => expr.clone(),
             _ => unexpected!(rule),
+        };
+
+        if !self.type_of_expr(expr.clone())?.is_number() {
+            return span_error!(expr, "NEGATE expects a number, got {}", expr.as_str());
+        }
+        if !self.type_of_var(var.clone())?.is_number() {
+            return span_error!(var, "NEGATE can only store into a number: {}", var.as_str());
+        }
+
+        // 0 - x instead of -x so zero doesn't become -0
+        emit!(
+            self,
+            "{} = 0 - ({});",
+            self.compile_var(var)?,
+            self.compile_expr(expr)?
+        )
+    }
+
+    /// INCREMENT _ (BY _)
+    /// DECREMENT _ (BY _)
+    fn compile_incr_stmt(&self, pair: Pair<Rule>, op: &str) -> LDPLResult<String> {
+        let name = if op == "+=" { "INCREMENT" } else { "DECREMENT" };
+        let mut iter = pair.into_inner();
+        let var = iter.next().unwrap();
+        if !self.type_of_var(var.clone())?.is_number() {
+            return span_error!(var, "{} expects a number variable: {}", name, var.as_str());
         }
 
-        emit!("{} = floor({});", left, right)
+        let step = if let Some(expr) = iter.next() {
+            if !self.type_of_expr(expr.clone())?.is_number() {
+                return span_error!(expr, "{} BY expects a number, got {}", name, expr.as_str());
+            }
+            self.compile_expr(expr)?
+        } else {
+            "1".to_string()
+        };
+
+        emit!(self, "{} {} {};", self.compile_var(var)?, op, step)
+    }
+
+    /// TOGGLE _
+    fn compile_toggle_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let var = pair.into_inner().next().unwrap();
+        if !self.type_of_var(var.clone())?.is_number() {
+            return span_error!(var, "TOGGLE expects a number variable: {}", var.as_str());
+        }
+        let var = self.compile_var(var)?;
+        emit!(self, "{} = {} == 0 ? 1 : 0;", var, var)
     }
 
     /// IN _ SOLVE X
@@ -1069,27 +2428,92 @@ impl Compiler {
         let mut iter = pair.into_inner();
         let ident = iter.next().unwrap();
 
+        let t = self.type_of_var(ident.clone())?;
+        if !t.is_number() {
+            return span_error!(
+                ident,
+                "SOLVE can only store into a number, but {} is a {}",
+                ident.as_str(),
+                t
+            );
+        }
+
         emit!(
+            self,
             "{} = {};",
             self.compile_var(ident)?,
             self.compile_solve_expr(iter.next().unwrap())?
         )
     }
 
-    // Math expression part of a SOLVE statement
+    /// Math expression part of a SOLVE statement. Precedence is
+    /// resolved here and every operation is parenthesized, so the C++
+    /// never relies on its own rules (where ^ is XOR, not power).
     fn compile_solve_expr(&self, pair: Pair<Rule>) -> LDPLResult<String> {
-        let mut parts = vec![];
+        let climber = PrecClimber::new(vec![
+            Operator::new(Rule::add_op, Assoc::Left) | Operator::new(Rule::sub_op, Assoc::Left),
+            Operator::new(Rule::mul_op, Assoc::Left) | Operator::new(Rule::div_op, Assoc::Left),
+            Operator::new(Rule::pow_op, Assoc::Right),
+        ]);
+
+        climber.climb(
+            pair.into_inner(),
+            |part| self.compile_solve_operand(part),
+            |left, op, right| {
+                let (left, right) = (left?, right?);
+                Ok(match op.as_rule() {
+                    Rule::pow_op => format!("pow({}, {})", left, right),
+                    _ => format!("({} {} {})", left, op.as_str(), right),
+                })
+            },
+        )
+    }
 
-        for part in pair.into_inner() {
-            match part.as_rule() {
-                Rule::var | Rule::number | Rule::text => parts.push(self.compile_expr(part)?),
-                Rule::solve_expr => parts.push(self.compile_solve_expr(part)?),
-                Rule::math_op => parts.push(part.as_str().to_string()),
-                _ => return error!("unexpected rule: {:?}", part),
+    /// A number, variable, negation, function call, or (group) in a
+    /// SOLVE expression.
+    fn compile_solve_operand(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        match pair.as_rule() {
+            Rule::var | Rule::number | Rule::text => {
+                let t = self.type_of_expr(pair.clone())?;
+                if !t.is_number() {
+                    return span_error!(
+                        pair,
+                        "SOLVE only does math on numbers, but {} is a {}",
+                        pair.as_str(),
+                        t
+                    );
+                }
+                self.compile_expr(pair)
+            }
+            Rule::solve_expr => self.compile_solve_expr(pair),
+            Rule::neg_solve_expr => Ok(format!(
+                "(-{})",
+                self.compile_solve_operand(pair.into_inner().next().unwrap())?
+            )),
+            Rule::solve_call => {
+                let mut iter = pair.into_inner();
+                let name = iter.next().unwrap();
+                let func = match name.as_str().to_lowercase().as_ref() {
+                    "abs" => "fabs",
+                    "sqrt" => "sqrt",
+                    "sin" => "sin",
+                    "cos" => "cos",
+                    "tan" => "tan",
+                    "log" => "log",
+                    "exp" => "exp",
+                    _ => {
+                        return span_error!(
+                            name,
+                            "Unknown function in SOLVE: {} (expected ABS, SQRT, SIN, COS, TAN, LOG, or EXP)",
+                            name.as_str()
+                        )
+                    }
+                };
+                let arg = self.compile_solve_operand(iter.next().unwrap())?;
+                Ok(format!("{}({})", func, arg))
             }
+            _ => unexpected!(pair),
         }
-
-        Ok(parts.join(" "))
     }
 
     ////
@@ -1101,7 +2525,196 @@ impl Compiler {
         let text = self.compile_expr(iter.next().unwrap())?;
         let splitter = self.compile_expr(iter.next().unwrap())?;
         let var = self.compile_var(iter.next().unwrap())?;
-        emit!("{} = utf8_split_list({}, {});", var, text, splitter)
+        emit!(self, "{} = utf8_split_list({}, {});", var, text, splitter)
+    }
+
+    /// TOKENIZE _ IN _
+    fn compile_tokenize_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let text = iter.next().unwrap();
+        let var = iter.next().unwrap();
+
+        if !self.type_of_expr(text.clone())?.is_text() {
+            return span_error!(text, "TOKENIZE expects text: {}", text.as_str());
+        }
+        if *self.type_of_var(var.clone())? != LDPLType::List(Box::new(LDPLType::Text)) {
+            return span_error!(var, "TOKENIZE stores into a text list: {}", var.as_str());
+        }
+
+        emit!(
+            self,
+            "{} = utf8_tokenize({});",
+            self.compile_var(var)?,
+            self.compile_expr(text)?
+        )
+    }
+
+    /// PARSE CSV LINE _ IN _
+    fn compile_parse_csv_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let line = iter.next().unwrap();
+        let var = iter.next().unwrap();
+
+        if !self.type_of_expr(line.clone())?.is_text() {
+            return span_error!(line, "PARSE CSV LINE expects text: {}", line.as_str());
+        }
+        if *self.type_of_var(var.clone())? != LDPLType::List(Box::new(LDPLType::Text)) {
+            return span_error!(
+                var,
+                "PARSE CSV LINE stores into a text list: {}",
+                var.as_str()
+            );
+        }
+
+        emit!(
+            self,
+            "{} = parse_csv_line({});",
+            self.compile_var(var)?,
+            self.compile_expr(line)?
+        )
+    }
+
+    /// PARSE NUMBER _ RADIX _ IN _
+    fn compile_parse_radix_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let text = iter.next().unwrap();
+        let radix = iter.next().unwrap();
+        let var = iter.next().unwrap();
+
+        if !self.type_of_expr(text.clone())?.is_text() {
+            return span_error!(text, "PARSE NUMBER expects text: {}", text.as_str());
+        }
+        let t = self.type_of_expr(radix.clone())?;
+        if !t.is_number() {
+            return span_error!(
+                radix,
+                "PARSE NUMBER needs a number RADIX, but {} is a {}",
+                radix.as_str(),
+                t
+            );
+        }
+        if !self.type_of_var(var.clone())?.is_number() {
+            return span_error!(var, "PARSE NUMBER stores into a number: {}", var.as_str());
+        }
+
+        emit!(
+            self,
+            "{} = parse_radix({}, {});",
+            self.compile_var(var)?,
+            self.compile_expr(text)?,
+            self.compile_expr(radix)?
+        )
+    }
+
+    /// FORMAT CSV LINE _ IN _
+    fn compile_format_csv_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let fields = iter.next().unwrap();
+        let var = iter.next().unwrap();
+
+        if *self.type_of_expr(fields.clone())? != LDPLType::List(Box::new(LDPLType::Text)) {
+            return span_error!(
+                fields,
+                "FORMAT CSV LINE expects a text list: {}",
+                fields.as_str()
+            );
+        }
+        if !self.type_of_var(var.clone())?.is_text() {
+            return span_error!(var, "FORMAT CSV LINE stores into a text: {}", var.as_str());
+        }
+
+        emit!(
+            self,
+            "{} = format_csv_line({});",
+            self.compile_var(var)?,
+            self.compile_expr(fields)?
+        )
+    }
+
+    /// FORMAT PERCENT _ [WITH _ DECIMALS] IN _
+    /// Without DECIMALS the percentage prints like any other number.
+    fn compile_format_percent_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut parts: Vec<_> = pair.into_inner().collect();
+        let var = parts.pop().unwrap();
+
+        for part in &parts {
+            let t = self.type_of_expr(part.clone())?;
+            if !t.is_number() {
+                return span_error!(
+                    part,
+                    "FORMAT PERCENT needs numbers, but {} is a {}",
+                    part.as_str(),
+                    t
+                );
+            }
+        }
+        if !self.type_of_var(var.clone())?.is_text() {
+            return span_error!(var, "FORMAT PERCENT stores into text: {}", var.as_str());
+        }
+
+        let ratio = self.compile_expr(parts[0].clone())?;
+        let decimals = match parts.get(1) {
+            Some(decimals) => self.compile_expr(decimals.clone())?,
+            None => "-1".into(),
+        };
+        emit!(
+            self,
+            "{} = format_percent({}, {});",
+            self.compile_var(var)?,
+            ratio,
+            decimals
+        )
+    }
+
+    /// PARSE CONFIG _ INTO _
+    fn compile_parse_config_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let source = iter.next().unwrap();
+        let var = iter.next().unwrap();
+
+        if !self.type_of_expr(source.clone())?.is_text() {
+            return span_error!(source, "PARSE CONFIG expects text: {}", source.as_str());
+        }
+        if *self.type_of_var(var.clone())? != LDPLType::Map(Box::new(LDPLType::Text)) {
+            return span_error!(var, "PARSE CONFIG stores into a text map: {}", var.as_str());
+        }
+
+        emit!(
+            self,
+            "parse_config({}, {});",
+            self.compile_expr(source)?,
+            self.compile_var(var)?
+        )
+    }
+
+    /// EXPAND _ WITH _ IN _
+    fn compile_expand_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let template = iter.next().unwrap();
+        let values = iter.next().unwrap();
+        let var = iter.next().unwrap();
+
+        if !self.type_of_expr(template.clone())?.is_text() {
+            return span_error!(
+                template,
+                "EXPAND expects a text template: {}",
+                template.as_str()
+            );
+        }
+        if *self.type_of_expr(values.clone())? != LDPLType::Map(Box::new(LDPLType::Text)) {
+            return span_error!(values, "EXPAND expects a text map: {}", values.as_str());
+        }
+        if !self.type_of_var(var.clone())?.is_text() {
+            return span_error!(var, "EXPAND stores into a text: {}", var.as_str());
+        }
+
+        emit!(
+            self,
+            "{} = expand_template({}, {});",
+            self.compile_var(var)?,
+            self.compile_expr(template)?,
+            self.compile_expr(values)?
+        )
     }
 
     /// REPLACE _ FROM _ WITH _ IN _
@@ -1113,7 +2726,7 @@ impl Compiler {
         let replacement = self.compile_expr(iter.next().unwrap())?;
         let var = self.compile_var(iter.next().unwrap())?;
 
-        emit!("{} = str_replace(((chText){}).str_rep(), ((chText){}).str_rep(), ((chText){}).str_rep());",
+        emit!(self, "{} = str_replace(((chText){}).str_rep(), ((chText){}).str_rep(), ((chText){}).str_rep());",
             var, text, search, replacement)
     }
 
@@ -1122,16 +2735,17 @@ impl Compiler {
         let mut iter = pair.into_inner();
         let var = self.compile_var(iter.next().unwrap())?;
 
-        let mut out = vec![emit_line!(r#"joinvar = "";"#)];
+        let mut out = vec![emit_line!(self, r#"joinvar = "";"#)];
         for expr in iter {
             out.push(emit_line!(
+                self,
                 "join(joinvar, {}, joinvar);",
                 self.compile_expr_for_type(expr, &LDPLType::Text)?
             ));
         }
-        out.push(emit_line!("{} = joinvar;", var));
+        out.push(emit_line!(self, "{} = joinvar;", var));
 
-        Ok(format!("{}", out.join("")))
+        Ok(out.join(""))
     }
 
     /// JOIN _ AND _ IN _
@@ -1141,15 +2755,100 @@ impl Compiler {
         let right = self.compile_expr_for_type(iter.next().unwrap(), &LDPLType::Text)?;
         let var = self.compile_var(iter.next().unwrap())?;
 
-        emit!("join({}, {}, {});", left, right, var)
+        emit!(self, "join({}, {}, {});", left, right, var)
+    }
+
+    /// JOIN _ WITH _ IN _
+    fn compile_join_list_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let list = iter.next().unwrap();
+        let sep = iter.next().unwrap();
+        let var = iter.next().unwrap();
+
+        let elem_type = self.element_type_of_list("JOIN", list.clone())?;
+        if elem_type.is_collection() {
+            return span_error!(
+                list,
+                "JOIN expects a number or text list, but {} is a {}",
+                list.as_str(),
+                self.type_of_expr(list.clone())?
+            );
+        }
+        if !self.type_of_var(var.clone())?.is_text() {
+            return span_error!(var, "JOIN stores into text: {}", var.as_str());
+        }
+
+        emit!(
+            self,
+            "{} = join_list({}, {});",
+            self.compile_var(var)?,
+            self.compile_expr(list)?,
+            self.compile_expr_for_type(sep, &LDPLType::Text)?
+        )
     }
 
     /// TRIM _ IN _
+    /// TRIM _ OF _ IN _ strips the given characters instead of spaces.
     fn compile_trim_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
-        let mut iter = pair.into_inner();
-        let expr = self.compile_expr(iter.next().unwrap())?;
-        let var = self.compile_var(iter.next().unwrap())?;
-        emit!("{} = trimCopy({});", var, expr)
+        let mut parts: Vec<_> = pair.into_inner().collect();
+        let var = parts.pop().unwrap();
+
+        for part in &parts {
+            if !self.type_of_expr(part.clone())?.is_text() {
+                return span_error!(part, "TRIM expects text: {}", part.as_str());
+            }
+        }
+        if !self.type_of_var(var.clone())?.is_text() {
+            return span_error!(var, "TRIM stores into text: {}", var.as_str());
+        }
+
+        let var = self.compile_var(var)?;
+        match &parts[..] {
+            [text] => emit!(
+                self,
+                "{} = trimCopy({});",
+                var,
+                self.compile_expr(text.clone())?
+            ),
+            [chars, text] => emit!(
+                self,
+                "{} = trim_chars({}, {});",
+                var,
+                self.compile_expr(text.clone())?,
+                self.compile_expr(chars.clone())?
+            ),
+            _ => unexpected!(parts),
+        }
+    }
+
+    /// TO UPPERCASE _
+    /// TO LOWERCASE _
+    /// STORE UPPERCASE OF _ IN _
+    /// STORE LOWERCASE OF _ IN _
+    fn compile_case_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut parts: Vec<_> = pair.into_inner().collect();
+        let var = parts.pop().unwrap();
+        let name = parts[0].as_str().to_uppercase();
+        let func = if name == "UPPERCASE" {
+            "utf8_to_upper"
+        } else {
+            "utf8_to_lower"
+        };
+
+        if !self.type_of_var(var.clone())?.is_text() {
+            return span_error!(var, "{} stores into text: {}", name, var.as_str());
+        }
+        let source = match parts.get(1) {
+            Some(expr) => {
+                if !self.type_of_expr(expr.clone())?.is_text() {
+                    return span_error!(expr, "{} expects text: {}", name, expr.as_str());
+                }
+                self.compile_expr(expr.clone())?
+            }
+            None => self.compile_var(var.clone())?,
+        };
+
+        emit!(self, "{} = {}({});", self.compile_var(var)?, func, source)
     }
 
     /// COUNT _ FROM _ IN _
@@ -1158,7 +2857,7 @@ impl Compiler {
         let search = self.compile_expr(iter.next().unwrap())?;
         let text = self.compile_expr(iter.next().unwrap())?;
         let var = self.compile_var(iter.next().unwrap())?;
-        emit!("{} = utf8Count({}, {});", var, text, search)
+        emit!(self, "{} = utf8Count({}, {});", var, text, search)
     }
 
     /// SUBSTRING _ FROM _ LENGTH _ IN _
@@ -1171,26 +2870,116 @@ impl Compiler {
 
         Ok(format!(
             "{}{}",
-            emit_line!("joinvar = {};", text),
-            emit_line!("{} = joinvar.substr({}, {});", var, search, length)
+            emit_line!(self, "joinvar = {};", text),
+            emit_line!(self, "{} = joinvar.substr({}, {});", var, search, length)
         ))
     }
 
-    /// GET INDEX OF _ FROM _ IN _
-    fn compile_get_index_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+    /// GET TEXT FROM _ TO _ IN _ IN _
+    fn compile_text_range_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
         let mut iter = pair.into_inner();
-        let search = self.compile_expr(iter.next().unwrap())?;
-        let text = self.compile_expr(iter.next().unwrap())?;
-        let var = self.compile_var(iter.next().unwrap())?;
-        emit!("{} = utf8GetIndexOf({}, {});", var, text, search)
-    }
+        let from = iter.next().unwrap();
+        let to = iter.next().unwrap();
+        let text = iter.next().unwrap();
+        let var = iter.next().unwrap();
 
-    /// GET CHARACTER CODE OF _ IN _
-    fn compile_get_char_code_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
-        let mut iter = pair.into_inner();
-        let expr = self.compile_expr(iter.next().unwrap())?;
+        for bound in [&from, &to] {
+            let t = self.type_of_expr(bound.clone())?;
+            if !t.is_number() {
+                return span_error!(
+                    bound,
+                    "GET TEXT FROM needs numbers, but {} is a {}",
+                    bound.as_str(),
+                    t
+                );
+            }
+        }
+        if !self.type_of_expr(text.clone())?.is_text() {
+            return span_error!(text, "GET TEXT FROM expects text: {}", text.as_str());
+        }
+        if !self.type_of_var(var.clone())?.is_text() {
+            return span_error!(var, "GET TEXT FROM stores into text: {}", var.as_str());
+        }
+
+        emit!(
+            self,
+            "{} = text_range({}, {}, {});",
+            self.compile_var(var)?,
+            self.compile_expr(text)?,
+            self.compile_expr(from)?,
+            self.compile_expr(to)?
+        )
+    }
+
+    /// GET INDEX OF _ FROM _ IN _
+    fn compile_get_index_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let search = self.compile_expr(iter.next().unwrap())?;
+        let text = self.compile_expr(iter.next().unwrap())?;
+        let var = self.compile_var(iter.next().unwrap())?;
+        emit!(self, "{} = utf8GetIndexOf({}, {});", var, text, search)
+    }
+
+    /// STORE CONTAINS _ IN _ IN _
+    fn compile_contains_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let search = iter.next().unwrap();
+        let text = iter.next().unwrap();
+        let var = iter.next().unwrap();
+
+        for expr in [&search, &text] {
+            if !self.type_of_expr(expr.clone())?.is_text() {
+                return span_error!(expr, "STORE CONTAINS expects text: {}", expr.as_str());
+            }
+        }
+        if !self.type_of_var(var.clone())?.is_number() {
+            return span_error!(var, "STORE CONTAINS stores into a number: {}", var.as_str());
+        }
+
+        emit!(
+            self,
+            "{} = utf8GetIndexOf({}, {}) >= 0;",
+            self.compile_var(var)?,
+            self.compile_expr(text)?,
+            self.compile_expr(search)?
+        )
+    }
+
+    /// GET EDIT DISTANCE OF _ AND _ IN _
+    fn compile_edit_distance_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let a = iter.next().unwrap();
+        let b = iter.next().unwrap();
+        let var = iter.next().unwrap();
+
+        for text in [&a, &b] {
+            if !self.type_of_expr(text.clone())?.is_text() {
+                return span_error!(text, "GET EDIT DISTANCE expects text: {}", text.as_str());
+            }
+        }
+        if !self.type_of_var(var.clone())?.is_number() {
+            return span_error!(
+                var,
+                "GET EDIT DISTANCE stores into a number: {}",
+                var.as_str()
+            );
+        }
+
+        emit!(
+            self,
+            "{} = edit_distance({}, {});",
+            self.compile_var(var)?,
+            self.compile_expr(a)?,
+            self.compile_expr(b)?
+        )
+    }
+
+    /// GET CHARACTER CODE OF _ IN _
+    fn compile_get_char_code_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let expr = self.compile_expr(iter.next().unwrap())?;
         let var = self.compile_var(iter.next().unwrap())?;
-        emit!("{} = get_char_num({});", var, expr)
+        emit!(self, "{} = get_char_num({});", var, expr)
     }
 
     /// GET ASCII CHARACTER _ IN _
@@ -1198,35 +2987,68 @@ impl Compiler {
         let mut iter = pair.into_inner();
         let chr = self.compile_expr(iter.next().unwrap())?;
         let var = self.compile_var(iter.next().unwrap())?;
-        emit!("{} = (char)({});", var, chr)
+        emit!(self, "{} = (char)({});", var, chr)
     }
 
     /// GET CHARACTER AT _ FROM _ IN _
+    /// Indexes count code points from 0. Negative ones count from the
+    /// end, so -1 is the last character. Anything out of range stores
+    /// "" instead of stopping the program, the same way SUBSTRING and
+    /// GET TEXT FROM clamp their ranges.
     fn compile_get_char_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
         let mut iter = pair.into_inner();
-        let at = self.compile_expr(iter.next().unwrap())?;
-        let from = self.compile_expr(iter.next().unwrap())?;
-        let var = self.compile_var(iter.next().unwrap())?;
-        emit!("{} = charat({}, {});", var, from, at)
+        let at = iter.next().unwrap();
+        let from = iter.next().unwrap();
+        let var = iter.next().unwrap();
+
+        let t = self.type_of_expr(at.clone())?;
+        if !t.is_number() {
+            return span_error!(
+                at,
+                "GET CHARACTER AT needs a number, but {} is a {}",
+                at.as_str(),
+                t
+            );
+        }
+        if !self.type_of_expr(from.clone())?.is_text() {
+            return span_error!(from, "GET CHARACTER AT expects text: {}", from.as_str());
+        }
+        if !self.type_of_var(var.clone())?.is_text() {
+            return span_error!(var, "GET CHARACTER AT stores into text: {}", var.as_str());
+        }
+
+        emit!(
+            self,
+            "{} = charat({}, {});",
+            self.compile_var(var)?,
+            self.compile_expr(from)?,
+            self.compile_expr(at)?
+        )
     }
 
     ////
-    // LIST + TEXT
+    // LIST + MAP + TEXT
 
     // GET LENGTH OF _ IN _
+    // STORE LENGTH OF _ IN _
     fn compile_get_length_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
         let mut iter = pair.into_inner();
-        let expr = iter.next().unwrap();
+        let node = iter.next().unwrap();
         let var = self.compile_var(iter.next().unwrap())?;
-        let expr_type = self.type_of_expr(expr.clone())?;
-        let expr = self.compile_expr(expr)?;
+        let expr_type = self.type_of_expr(node.clone())?;
+        let expr = self.compile_expr(node.clone())?;
 
         if expr_type.is_text() {
-            emit!("{} = ((chText){}).size();", var, expr)
-        } else if expr_type.is_list() {
-            emit!("{} = {}.inner_collection.size();", var, expr)
+            emit!(self, "{} = ((chText){}).size();", var, expr)
+        } else if expr_type.is_collection() {
+            emit!(self, "{} = {}.inner_collection.size();", var, expr)
         } else {
-            unexpected!(expr_type)
+            span_error!(
+                node,
+                "GET LENGTH expects text, a list, or a map, but {} is a {}",
+                node.as_str(),
+                expr_type
+            )
         }
     }
 
@@ -1238,28 +3060,497 @@ impl Compiler {
         let mut iter = pair.into_inner();
         let expr = self.compile_expr(iter.next().unwrap())?;
         let list = self.compile_var(iter.next().unwrap())?;
-        emit!("{}.inner_collection.push_back({});", list, expr)
+        emit!(self, "{}.inner_collection.push_back({});", list, expr)
+    }
+
+    /// PUSH _ TO FRONT OF _
+    fn compile_push_front_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let expr = iter.next().unwrap();
+        let list = iter.next().unwrap();
+
+        let elem_type = self.element_type_of_list("PUSH TO FRONT", list.clone())?;
+        let t = self.type_of_expr(expr.clone())?;
+        if *t != elem_type {
+            return span_error!(
+                expr,
+                "Can't PUSH {} to the front of {}: expected {}, got {}",
+                expr.as_str(),
+                list.as_str(),
+                elem_type,
+                t
+            );
+        }
+
+        emit!(
+            self,
+            "{0}.inner_collection.insert({0}.inner_collection.begin(), {1});",
+            self.compile_var(list)?,
+            self.compile_expr(expr)?
+        )
     }
 
     /// DELETE LAST ELEMENT OF _
     fn compile_delete_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
         let mut iter = pair.into_inner();
         let list = self.compile_var(iter.next().unwrap())?;
-        emit!(format!(
-            "if({list}.inner_collection.size() > 0) {list}.inner_collection.pop_back();",
-            list = list
-        ))
+        emit!(
+            self,
+            format!(
+                "if({list}.inner_collection.size() > 0) {list}.inner_collection.pop_back();",
+                list = list
+            )
+        )
+    }
+
+    /// INSERT _ AT _ IN _
+    fn compile_insert_at_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let expr = iter.next().unwrap();
+        let index = iter.next().unwrap();
+        let list = iter.next().unwrap();
+
+        let elem_type = self.element_type_of_list("INSERT", list.clone())?;
+        self.check_list_index("INSERT", index.clone())?;
+        let t = self.type_of_expr(expr.clone())?;
+        if *t != elem_type {
+            return span_error!(
+                expr,
+                "Can't INSERT {} into {}: expected {}, got {}",
+                expr.as_str(),
+                list.as_str(),
+                elem_type,
+                t
+            );
+        }
+
+        emit!(
+            self,
+            "insert_at({}, {}, {});",
+            self.compile_var(list)?,
+            self.compile_expr(index)?,
+            self.compile_expr(expr)?
+        )
+    }
+
+    /// DELETE INDEX _ FROM _
+    fn compile_delete_at_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let index = iter.next().unwrap();
+        let list = iter.next().unwrap();
+
+        self.element_type_of_list("DELETE INDEX", list.clone())?;
+        self.check_list_index("DELETE INDEX", index.clone())?;
+
+        emit!(
+            self,
+            "delete_at({}, {});",
+            self.compile_var(list)?,
+            self.compile_expr(index)?
+        )
+    }
+
+    /// MAP EACH _ IN _ AS _ GIVING _
+    /// Calls SUB(element, result) for every element and collects the
+    /// results. The destination may be the source list.
+    fn compile_map_each_stmt(&mut self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let ident = iter.next().unwrap();
+        let list = iter.next().unwrap();
+        let sub = iter.next().unwrap();
+        let dest = iter.next().unwrap();
+
+        let elem_type = self.element_type_of_list("MAP EACH", list.clone())?;
+        let result_type = self.element_type_of_list("MAP EACH", dest.clone())?;
+        self.check_each_var("MAP EACH", ident.clone(), &elem_type)?;
+        self.check_sub_params("MAP EACH", sub.clone(), &[elem_type, result_type.clone()])?;
+        let sub = sub.as_str();
+
+        let out_var = format!("LPVAR_{}", self.tmp_id);
+        let result_var = format!("LPVAR_{}", self.tmp_id + 1);
+        let range_var = format!("RVAR_{}", self.tmp_id + 2);
+        self.tmp_id += 3;
+
+        let cpp_type = compile_type(&result_type.to_string());
+        let ident = mangle_var(ident.as_str());
+        let mut out = vec![
+            emit_line!(self, "ldpl_list<{}> {};", cpp_type, out_var),
+            emit_line!(
+                self,
+                "for (auto& {} : {}.inner_collection) {{",
+                range_var,
+                self.compile_expr(list)?
+            ),
+        ];
+        indent!(self);
+        out.push(emit_line!(self, "{} = {};", ident, range_var));
+        out.push(emit_line!(
+            self,
+            "{} {} = {};",
+            cpp_type,
+            result_var,
+            if result_type.is_number() {
+                "0"
+            } else {
+                r#""""#
+            }
+        ));
+        out.push(emit_line!(
+            self,
+            "{}({}, {});",
+            mangle_sub(sub),
+            ident,
+            result_var
+        ));
+        out.push(emit_line!(
+            self,
+            "{}.inner_collection.push_back({});",
+            out_var,
+            result_var
+        ));
+        dedent!(self);
+        out.push(emit_line!(self, "}"));
+        out.push(emit_line!(
+            self,
+            "{}.inner_collection = {}.inner_collection;",
+            self.compile_var(dest)?,
+            out_var
+        ));
+
+        Ok(out.join(""))
+    }
+
+    /// SORT _
+    /// SORT _ IN _
+    /// The IN form sorts a copy and leaves the original alone.
+    fn compile_sort_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let list = iter.next().unwrap();
+        let elem_type = self.element_type_of_list("SORT", list.clone())?;
+        if elem_type.is_collection() {
+            return span_error!(
+                list,
+                "SORT only works on number and text lists, but {} holds {}s",
+                list.as_str(),
+                elem_type
+            );
+        }
+
+        let mut out = vec![];
+        let mut target = self.compile_expr(list.clone())?;
+        if let Some(dest) = iter.next() {
+            let dest_type = self.element_type_of_list("SORT", dest.clone())?;
+            if dest_type != elem_type {
+                return span_error!(
+                    dest,
+                    "SORT can't put {} elements into a {} list",
+                    elem_type,
+                    dest_type
+                );
+            }
+            let dest = self.compile_var(dest)?;
+            out.push(emit_line!(
+                self,
+                "{}.inner_collection = {}.inner_collection;",
+                dest,
+                target
+            ));
+            target = dest;
+        }
+
+        out.push(emit_line!(
+            self,
+            "sort({0}.inner_collection.begin(), {0}.inner_collection.end());",
+            target
+        ));
+        Ok(out.join(""))
+    }
+
+    /// SHUFFLE _
+    fn compile_shuffle_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let list = pair.into_inner().next().unwrap();
+        self.element_type_of_list("SHUFFLE", list.clone())?;
+        emit!(
+            self,
+            "shuffle({0}.inner_collection.begin(), {0}.inner_collection.end(), ldpl_rng());",
+            self.compile_expr(list)?
+        )
+    }
+
+    /// REPLACE RANGE _ TO _ IN _ WITH _
+    /// Like FOR, the range stops just before TO.
+    fn compile_replace_range_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let from = iter.next().unwrap();
+        let to = iter.next().unwrap();
+        let list = iter.next().unwrap();
+        let with = iter.next().unwrap();
+
+        for bound in [&from, &to] {
+            let t = self.type_of_expr(bound.clone())?;
+            if !t.is_number() {
+                return span_error!(
+                    bound,
+                    "REPLACE RANGE needs numbers, but {} is a {}",
+                    bound.as_str(),
+                    t
+                );
+            }
+        }
+        let elem_type = self.element_type_of_list("REPLACE RANGE", list.clone())?;
+        let with_type = self.element_type_of_list("REPLACE RANGE", with.clone())?;
+        if elem_type != with_type {
+            return span_error!(
+                with,
+                "REPLACE RANGE can't put {} elements into a {} list",
+                with_type,
+                elem_type
+            );
+        }
+
+        emit!(
+            self,
+            "replace_range({}, {}, {}, {});",
+            self.compile_var(list)?,
+            self.compile_expr(from)?,
+            self.compile_expr(to)?,
+            self.compile_expr(with)?
+        )
+    }
+
+    /// REVERSE _
+    /// Lists are reversed by element, text by code point.
+    fn compile_reverse_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let var = pair.into_inner().next().unwrap();
+        match self.type_of_var(var.clone())? {
+            LDPLType::List(..) => emit!(
+                self,
+                "reverse({0}.inner_collection.begin(), {0}.inner_collection.end());",
+                self.compile_var(var)?
+            ),
+            LDPLType::Text => emit!(self, "{}.reverse();", self.compile_var(var)?),
+            t => span_error!(
+                var,
+                "REVERSE only works on lists and text, but {} is a {}",
+                var.as_str(),
+                t
+            ),
+        }
+    }
+
+    /// FILL _ FROM _ TO _
+    /// FILL _ FROM _ TO _ STEP _
+    /// Replaces the list's contents with the values a FOR loop with
+    /// the same bounds would visit. STEP defaults to 1.
+    fn compile_fill_range_stmt(&mut self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let list = iter.next().unwrap();
+        let elem_type = self.element_type_of_list("FILL", list.clone())?;
+        if !elem_type.is_number() {
+            return span_error!(
+                list,
+                "FILL needs a number list, but {} is a {} list",
+                list.as_str(),
+                elem_type
+            );
+        }
+
+        let mut bounds = vec![];
+        for node in iter {
+            let t = self.type_of_expr(node.clone())?;
+            if !t.is_number() {
+                return span_error!(node, "FILL needs numbers, but {} is a {}", node.as_str(), t);
+            }
+            // a FOR loop would never end, but this would also eat
+            // all the memory
+            if bounds.len() == 2
+                && node.as_rule() == Rule::number
+                && node.as_str().parse::<f64>().is_ok_and(|n| n == 0.0)
+            {
+                return span_error!(node, "FILL STEP can't be 0");
+            }
+            bounds.push(self.compile_expr(node)?);
+        }
+        if bounds.len() == 2 {
+            bounds.push("1".into());
+        }
+
+        let list = self.compile_var(list)?;
+        let var = format!("LPVAR_{}", self.tmp_id);
+        self.tmp_id += 1;
+        let (from, to, step) = (&bounds[0], &bounds[1], &bounds[2]);
+
+        let init = format!("ldpl_number {} = {}", var, from);
+        let test = format!(
+            "{step} >= 0 ? {var} < {to} : {var} > {to}",
+            step = step,
+            var = var,
+            to = to
+        );
+        let incr = format!("{} += {}", var, step);
+
+        let mut out = vec![emit_line!(self, "{}.inner_collection.clear();", list)];
+        out.push(emit_line!(self, "for({}; {}; {}) {{", init, test, incr));
+        indent!(self);
+        out.push(emit_line!(
+            self,
+            "{}.inner_collection.push_back({});",
+            list,
+            var
+        ));
+        dedent!(self);
+        out.push(emit_line!(self, "}"));
+        Ok(out.join(""))
+    }
+
+    /// FILTER _ IN _ KEEPING _ GIVING _
+    /// Calls SUB(element, keep) for every element and collects the ones
+    /// where it set `keep` to anything but 0.
+    fn compile_filter_stmt(&mut self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let ident = iter.next().unwrap();
+        let list = iter.next().unwrap();
+        let sub = iter.next().unwrap();
+        let dest = iter.next().unwrap();
+
+        let elem_type = self.element_type_of_list("FILTER", list.clone())?;
+        let dest_type = self.element_type_of_list("FILTER", dest.clone())?;
+        if dest_type != elem_type {
+            return span_error!(
+                dest,
+                "FILTER can't put {} elements into a {} list",
+                elem_type,
+                dest_type
+            );
+        }
+        self.check_each_var("FILTER", ident.clone(), &elem_type)?;
+        self.check_sub_params(
+            "FILTER",
+            sub.clone(),
+            &[elem_type.clone(), LDPLType::Number],
+        )?;
+        let sub = sub.as_str();
+
+        let out_var = format!("LPVAR_{}", self.tmp_id);
+        let keep_var = format!("LPVAR_{}", self.tmp_id + 1);
+        let range_var = format!("RVAR_{}", self.tmp_id + 2);
+        self.tmp_id += 3;
+
+        let ident = mangle_var(ident.as_str());
+        let mut out = vec![
+            emit_line!(
+                self,
+                "ldpl_list<{}> {};",
+                compile_type(&elem_type.to_string()),
+                out_var
+            ),
+            emit_line!(
+                self,
+                "for (auto& {} : {}.inner_collection) {{",
+                range_var,
+                self.compile_expr(list)?
+            ),
+        ];
+        indent!(self);
+        out.push(emit_line!(self, "{} = {};", ident, range_var));
+        out.push(emit_line!(self, "ldpl_number {} = 0;", keep_var));
+        out.push(emit_line!(
+            self,
+            "{}({}, {});",
+            mangle_sub(sub),
+            ident,
+            keep_var
+        ));
+        out.push(emit_line!(
+            self,
+            "if ({}) {}.inner_collection.push_back({});",
+            keep_var,
+            out_var,
+            range_var
+        ));
+        dedent!(self);
+        out.push(emit_line!(self, "}"));
+        out.push(emit_line!(
+            self,
+            "{}.inner_collection = {}.inner_collection;",
+            self.compile_var(dest)?,
+            out_var
+        ));
+
+        Ok(out.join(""))
+    }
+
+    /// REDUCE _ IN _ WITH _ INTO _
+    /// Calls SUB(accumulator, element) for every element. The
+    /// accumulator keeps whatever value it had going in.
+    fn compile_reduce_stmt(&mut self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let ident = iter.next().unwrap();
+        let list = iter.next().unwrap();
+        let sub = iter.next().unwrap();
+        let acc = iter.next().unwrap();
+
+        let elem_type = self.element_type_of_list("REDUCE", list.clone())?;
+        let acc_type = self.type_of_var(acc.clone())?.clone();
+        self.check_each_var("REDUCE", ident.clone(), &elem_type)?;
+        self.check_sub_params("REDUCE", sub.clone(), &[acc_type, elem_type])?;
+        let sub = sub.as_str();
+
+        let range_var = format!("RVAR_{}", self.tmp_id);
+        self.tmp_id += 1;
+
+        let ident = mangle_var(ident.as_str());
+        let mut out = vec![emit_line!(
+            self,
+            "for (auto& {} : {}.inner_collection) {{",
+            range_var,
+            self.compile_expr(list)?
+        )];
+        indent!(self);
+        out.push(emit_line!(self, "{} = {};", ident, range_var));
+        out.push(emit_line!(
+            self,
+            "{}({}, {});",
+            mangle_sub(sub),
+            self.compile_var(acc)?,
+            ident
+        ));
+        dedent!(self);
+        out.push(emit_line!(self, "}"));
+
+        Ok(out.join(""))
     }
 
     ////
     // MAP
 
+    /// DELETE KEY _ FROM _
+    fn compile_delete_key_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let key = iter.next().unwrap();
+        let map = iter.next().unwrap();
+
+        // same conversions as map:key
+        let key_type = self.check_map_key("DELETE KEY", map.clone(), key.clone())?;
+        let key = if key_type.is_number() {
+            format!("to_ldpl_string({}).str_rep()", self.compile_expr(key)?)
+        } else {
+            format!("chText({}).str_rep()", self.compile_expr(key)?)
+        };
+        emit!(
+            self,
+            "{}.inner_collection.erase({});",
+            self.compile_var(map)?,
+            key
+        )
+    }
+
     /// GET KEYS COUNT OF _ IN _
     fn compile_get_keys_count_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
         let mut iter = pair.into_inner();
         let map = self.compile_expr(iter.next().unwrap())?;
         let var = self.compile_var(iter.next().unwrap())?;
-        emit!("{} = {}.inner_collection.size();", var, map)
+        emit!(self, "{} = {}.inner_collection.size();", var, map)
     }
 
     /// GET KEYS OF _ IN _
@@ -1267,38 +3558,189 @@ impl Compiler {
         let mut iter = pair.into_inner();
         let map = self.compile_expr(iter.next().unwrap())?;
         let var = self.compile_var(iter.next().unwrap())?;
-        emit!("get_indices({}, {});", var, map)
+        emit!(self, "get_indices({}, {});", var, map)
+    }
+
+    /// GET SORTED KEYS OF _ IN _
+    fn compile_get_sorted_keys_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let map = iter.next().unwrap();
+        let var = iter.next().unwrap();
+
+        let numeric = match self.type_of_expr(map.clone())? {
+            LDPLType::Map(inner) => inner.is_number(),
+            t => {
+                return span_error!(
+                    map,
+                    "GET SORTED KEYS expects a map, but {} is a {}",
+                    map.as_str(),
+                    t
+                )
+            }
+        };
+        if *self.type_of_var(var.clone())? != LDPLType::List(Box::new(LDPLType::Text)) {
+            return span_error!(
+                var,
+                "GET SORTED KEYS stores into a text list: {}",
+                var.as_str()
+            );
+        }
+
+        emit!(
+            self,
+            "get_sorted_indices({}, {}, {});",
+            self.compile_var(var)?,
+            self.compile_expr(map)?,
+            numeric
+        )
+    }
+
+    /// GET KEY OF MAX VALUE IN _ IN _
+    /// GET KEY OF MIN VALUE IN _ IN _
+    fn compile_get_key_of_value_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let want_max = iter.next().unwrap().as_str().eq_ignore_ascii_case("max");
+        let map = iter.next().unwrap();
+        let var = iter.next().unwrap();
+
+        if *self.type_of_expr(map.clone())? != LDPLType::Map(Box::new(LDPLType::Number)) {
+            return span_error!(
+                map,
+                "GET KEY OF VALUE expects a number map: {}",
+                map.as_str()
+            );
+        }
+        let vartype = self.type_of_var(var.clone())?;
+        if !vartype.is_number() && !vartype.is_text() {
+            return span_error!(var, "GET KEY OF VALUE can't store a key in a {}", vartype);
+        }
+
+        emit!(
+            self,
+            "get_key_of_extreme({}, {}, {});",
+            self.compile_expr(map)?,
+            self.compile_var(var)?,
+            want_max
+        )
+    }
+
+    /// GET MAX VALUE LENGTH OF _ IN _
+    /// GET MIN VALUE LENGTH OF _ IN _
+    fn compile_max_value_length_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let want_max = iter.next().unwrap().as_str().eq_ignore_ascii_case("max");
+        let map = iter.next().unwrap();
+        let var = iter.next().unwrap();
+
+        if *self.type_of_expr(map.clone())? != LDPLType::Map(Box::new(LDPLType::Text)) {
+            return span_error!(map, "GET VALUE LENGTH expects a text map: {}", map.as_str());
+        }
+        if !self.type_of_var(var.clone())?.is_number() {
+            return span_error!(
+                var,
+                "GET VALUE LENGTH stores into a number: {}",
+                var.as_str()
+            );
+        }
+
+        emit!(
+            self,
+            "{} = get_value_length({}, {});",
+            self.compile_var(var)?,
+            self.compile_expr(map)?,
+            want_max
+        )
     }
 
     ////
     // MAP + LIST
 
     /// COPY _ TO _
+    /// Deep copy: nested collections are held by value in C++, so
+    /// changing the copy never touches the original.
     fn compile_copy_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
         let mut iter = pair.into_inner();
         let from = self.compile_expr(iter.next().unwrap())?;
         let to = self.compile_var(iter.next().unwrap())?;
-        emit!("{}.inner_collection = {}.inner_collection;", to, from)
+        emit!(self, "{}.inner_collection = {}.inner_collection;", to, from)
     }
 
     /// CLEAR _
     fn compile_clear_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
         let mut iter = pair.into_inner();
         let collection = self.compile_var(iter.next().unwrap())?;
-        emit!("{}.inner_collection.clear();", collection)
+        emit!(self, "{}.inner_collection.clear();", collection)
+    }
+
+    /// SERIALIZE _ IN _
+    /// JSON-ish text for a list or map, nested collections included.
+    fn compile_serialize_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let from = iter.next().unwrap();
+        let to = iter.next().unwrap();
+
+        if !self.type_of_expr(from.clone())?.is_collection() {
+            return span_error!(from, "SERIALIZE expects a list or map: {}", from.as_str());
+        }
+        if !self.type_of_var(to.clone())?.is_text() {
+            return span_error!(to, "SERIALIZE stores into a text: {}", to.as_str());
+        }
+
+        emit!(
+            self,
+            "{} = ldpl_serialize({});",
+            self.compile_var(to)?,
+            self.compile_expr(from)?
+        )
+    }
+
+    /// DESERIALIZE _ INTO _
+    /// Reads SERIALIZE's output back. Malformed text sets ERRORCODE.
+    fn compile_deserialize_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let from = iter.next().unwrap();
+        let to = iter.next().unwrap();
+
+        if !self.type_of_expr(from.clone())?.is_text() {
+            return span_error!(from, "DESERIALIZE expects text: {}", from.as_str());
+        }
+        if !self.type_of_var(to.clone())?.is_collection() {
+            return span_error!(to, "DESERIALIZE stores into a list or map: {}", to.as_str());
+        }
+
+        emit!(
+            self,
+            "ldpl_deserialize({}, {});",
+            self.compile_expr(from)?,
+            self.compile_var(to)?
+        )
     }
 
     ////
     // IO
 
     /// DISPLAY _...
-    fn compile_display_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
-        let mut parts = vec!["cout".to_string()];
+    fn compile_display_stmt(&self, pair: Pair<Rule>, stream: &str) -> LDPLResult<String> {
+        let mut parts = vec![stream.to_string()];
         for node in pair.into_inner() {
             parts.push(self.compile_expr(node)?);
         }
         parts.push("flush".into());
-        emit!("{};", parts.join(" << "))
+        emit!(self, "{};", parts.join(" << "))
+    }
+
+    /// DISPLAY ERROR _...
+    /// Like DISPLAY, but to stderr. With a variable named ERROR,
+    /// `display error crlf` could mean either, so we refuse to guess.
+    fn compile_display_error_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        if self.locals.contains_key("ERROR") || self.globals.contains_key("ERROR") {
+            return span_error!(
+                pair,
+                "DISPLAY ERROR writes to stderr, but ERROR is also a variable. \
+                 Rename the variable to DISPLAY it"
+            );
+        }
+        self.compile_display_stmt(pair, "cerr")
     }
 
     /// ACCEPT _
@@ -1320,7 +3762,47 @@ impl Compiler {
             unexpected!(ident);
         };
 
-        emit!("{} = {};", self.compile_var(ident)?, fun)
+        emit!(self, "{} = {};", self.compile_var(ident)?, fun)
+    }
+
+    /// ACCEPT _ OR DEFAULT _
+    fn compile_accept_default_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let var = iter.next().unwrap();
+        let default = iter.next().unwrap();
+
+        let vartype = self.type_of_var(var.clone())?;
+        let fun = if vartype.is_text() {
+            "input_string_or"
+        } else if vartype.is_number() {
+            "input_number_or"
+        } else {
+            return span_error!(
+                var,
+                "ACCEPT needs a number or text variable, but {} is a {}",
+                var.as_str(),
+                vartype
+            );
+        };
+        let t = self.type_of_expr(default.clone())?;
+        if t != vartype {
+            return span_error!(
+                default,
+                "ACCEPT {} needs a {} DEFAULT, but {} is a {}",
+                var.as_str(),
+                vartype,
+                default.as_str(),
+                t
+            );
+        }
+
+        emit!(
+            self,
+            "{} = {}({});",
+            self.compile_var(var)?,
+            fun,
+            self.compile_expr(default)?
+        )
     }
 
     /// LOAD FILE _ IN _
@@ -1328,7 +3810,7 @@ impl Compiler {
         let mut iter = pair.into_inner();
         let path = self.compile_expr(iter.next().unwrap())?;
         let var = self.compile_var(iter.next().unwrap())?;
-        emit!("load_file({}, {});", path, var)
+        emit!(self, "load_file({}, {});", path, var)
     }
 
     /// WRITE _ TO FILE _
@@ -1338,9 +3820,9 @@ impl Compiler {
         let path = self.compile_expr(iter.next().unwrap())?;
 
         Ok(format!("{}{}{}",
-            emit_line!("file_writing_stream.open(expandHomeDirectory(((chText){}).str_rep()), ios_base::out);", path),
-            emit_line!("file_writing_stream << {};", expr),
-            emit_line!("file_writing_stream.close();")
+            emit_line!(self, "file_writing_stream.open(expandHomeDirectory(((chText){}).str_rep()), ios_base::out);", path),
+            emit_line!(self, "file_writing_stream << {};", expr),
+            emit_line!(self, "file_writing_stream.close();")
         ))
     }
 
@@ -1351,65 +3833,274 @@ impl Compiler {
         let path = self.compile_expr(iter.next().unwrap())?;
 
         Ok(format!("{}{}{}",
-            emit_line!("file_writing_stream.open(expandHomeDirectory(((chText){}).str_rep()), ios_base::app);", path),
-            emit_line!("file_writing_stream << {};", expr),
-            emit_line!("file_writing_stream.close();")
+            emit_line!(self, "file_writing_stream.open(expandHomeDirectory(((chText){}).str_rep()), ios_base::app);", path),
+            emit_line!(self, "file_writing_stream << {};", expr),
+            emit_line!(self, "file_writing_stream.close();")
         ))
     }
 
     /// EXECUTE _
     /// EXECUTE _ AND STORE EXIT CODE IN _
     /// EXECUTE _ AND STORE OUTPUT IN _
+    /// EXECUTE _ WITH TIMEOUT _ AND STORE EXIT CODE IN _
     fn compile_execute_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
         let pair = pair.into_inner().next().unwrap();
         let rule = pair.as_rule();
         let mut iter = pair.into_inner();
         match rule {
             Rule::execute_expr_stmt => emit!(
+                self,
                 "system({});",
                 self.compile_c_char_array(iter.next().unwrap())?
             ),
             Rule::execute_output_stmt => {
                 let expr = self.compile_c_char_array(iter.next().unwrap())?;
                 let var = self.compile_var(iter.next().unwrap())?;
-                emit!("{} = exec({});", var, expr)
+                emit!(self, "{} = exec({});", var, expr)
             }
             Rule::execute_exit_code_stmt => {
                 let expr = self.compile_c_char_array(iter.next().unwrap())?;
                 let var = self.compile_var(iter.next().unwrap())?;
                 emit!(
+                    self,
                     "{} = (system({}) >> 8) & 0xff;", //shift wait() val and get lowest 2
                     var,
                     expr
                 )
             }
+            Rule::execute_timeout_stmt => {
+                let expr = self.compile_c_char_array(iter.next().unwrap())?;
+                let timeout = iter.next().unwrap();
+                let t = self.type_of_expr(timeout.clone())?;
+                if !t.is_number() {
+                    return span_error!(
+                        timeout,
+                        "EXECUTE needs a number of milliseconds for TIMEOUT, but {} is a {}",
+                        timeout.as_str(),
+                        t
+                    );
+                }
+                let var = iter.next().unwrap();
+                if !self.type_of_var(var.clone())?.is_number() {
+                    return span_error!(var, "EXIT CODE is stored in a number: {}", var.as_str());
+                }
+                emit!(
+                    self,
+                    "{} = exec_with_timeout({}, {});",
+                    self.compile_var(var)?,
+                    expr,
+                    self.compile_expr(timeout)?
+                )
+            }
             _ => unexpected!(rule),
         }
     }
+
+    /// GET PROGRAM DIRECTORY IN _
+    fn compile_program_dir_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let var = pair.into_inner().next().unwrap();
+        if !self.type_of_var(var.clone())?.is_text() {
+            return span_error!(
+                var,
+                "GET PROGRAM DIRECTORY stores into text: {}",
+                var.as_str()
+            );
+        }
+        emit!(self, "{} = get_program_dir();", self.compile_var(var)?)
+    }
+
+    /// LOAD LIBRARY _ AS _
+    fn compile_load_library_stmt(&mut self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let mut iter = pair.into_inner();
+        let path = iter.next().unwrap();
+        let var = iter.next().unwrap();
+
+        if !self.type_of_expr(path.clone())?.is_text() {
+            return span_error!(path, "LOAD LIBRARY expects a text path: {}", path.as_str());
+        }
+        if !self.type_of_var(var.clone())?.is_number() {
+            return span_error!(
+                var,
+                "LOAD LIBRARY stores a handle into a number: {}",
+                var.as_str()
+            );
+        }
+
+        // glibc before 2.34 keeps dlopen() in its own library
+        let ldl = "-ldl".to_string();
+        if cfg!(target_os = "linux") && !self.flags.contains(&ldl) {
+            self.add_flag(ldl)?;
+        }
+
+        emit!(
+            self,
+            "{} = ldpl_load_library({});",
+            self.compile_var(var)?,
+            self.compile_expr(path)?
+        )
+    }
+
+    /// UNLOAD LIBRARY _
+    fn compile_unload_library_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let handle = pair.into_inner().next().unwrap();
+        let t = self.type_of_expr(handle.clone())?;
+        if !t.is_number() {
+            return span_error!(
+                handle,
+                "UNLOAD LIBRARY expects a number handle, but {} is a {}",
+                handle.as_str(),
+                t
+            );
+        }
+        emit!(self, "ldpl_unload_library({});", self.compile_expr(handle)?)
+    }
+
+    /// GET MONOTONIC TIME IN _
+    fn compile_monotonic_time_stmt(&self, pair: Pair<Rule>) -> LDPLResult<String> {
+        let var = pair.into_inner().next().unwrap();
+        if !self.type_of_var(var.clone())?.is_number() {
+            return span_error!(
+                var,
+                "GET MONOTONIC TIME stores into a number: {}",
+                var.as_str()
+            );
+        }
+        emit!(self, "{} = get_monotonic_time();", self.compile_var(var)?)
+    }
+
+    /// GET TOTAL MEMORY IN _
+    /// GET CPU COUNT IN _
+    fn compile_system_info_stmt(
+        &self,
+        pair: Pair<Rule>,
+        stmt: &str,
+        fun: &str,
+    ) -> LDPLResult<String> {
+        let var = pair.into_inner().next().unwrap();
+        if !self.type_of_var(var.clone())?.is_number() {
+            return span_error!(var, "{} stores into a number: {}", stmt, var.as_str());
+        }
+        emit!(self, "{} = {}();", self.compile_var(var)?, fun)
+    }
 }
 
-////
+// ----
 // HELPERS
 
 impl Compiler {
-    /// Find the scalar type for an expression. Map(List(Text)) will
-    /// just give us Text.
-    fn scalar_type_of_expr(&self, expr: Pair<Rule>) -> LDPLResult<LDPLType> {
-        if let Ok(t) = self.type_of_expr(expr.clone()) {
-            self.scalar_type_of_collection(t.clone())
-        } else {
-            error!("Can't infer scalar type for expression: {:?}", expr)
+    /// Element type of a list used by `stmt`, or an error if `expr`
+    /// isn't a list.
+    fn element_type_of_list(&self, stmt: &str, expr: Pair<Rule>) -> LDPLResult<LDPLType> {
+        match self.type_of_expr(expr.clone())? {
+            LDPLType::List(inner) => Ok(*inner.clone()),
+            t => span_error!(
+                expr,
+                "{} expects a list, but {} is a {}",
+                stmt,
+                expr.as_str(),
+                t
+            ),
+        }
+    }
+
+    /// Make sure `stmt` got a map and a text or number key for it.
+    /// Returns the key's type.
+    fn check_map_key(&self, stmt: &str, map: Pair<Rule>, key: Pair<Rule>) -> LDPLResult<LDPLType> {
+        let t = self.type_of_var(map.clone())?;
+        if !matches!(t, LDPLType::Map(_)) {
+            return span_error!(
+                map,
+                "{} expects a map, but {} is a {}",
+                stmt,
+                map.as_str(),
+                t
+            );
+        }
+        let t = self.type_of_expr(key.clone())?;
+        if !t.is_text() && !t.is_number() {
+            return span_error!(
+                key,
+                "Map keys are text or numbers, but {} is a {}",
+                key.as_str(),
+                t
+            );
         }
+        Ok(t.clone())
     }
 
-    /// Map(List(Text)) => Text
-    fn scalar_type_of_collection<'a>(&self, t: LDPLType) -> LDPLResult<LDPLType> {
-        Ok(match t {
-            LDPLType::List(inner) | LDPLType::Map(inner) => {
-                return self.scalar_type_of_collection(*inner);
+    /// Make sure the list index used by `stmt` is a number.
+    fn check_list_index(&self, stmt: &str, index: Pair<Rule>) -> LDPLResult<()> {
+        let t = self.type_of_expr(index.clone())?;
+        if !t.is_number() {
+            return span_error!(
+                index,
+                "{} needs a number index, but {} is a {}",
+                stmt,
+                index.as_str(),
+                t
+            );
+        }
+        Ok(())
+    }
+
+    /// Make sure the loop variable of a `stmt` matches the element type.
+    fn check_each_var(&self, stmt: &str, ident: Pair<Rule>, want: &LDPLType) -> LDPLResult<()> {
+        let name = ident.as_str();
+        let t = self.type_of_var(ident.clone())?;
+        if t != want {
+            return span_error!(
+                ident,
+                "{} needs {} to be a {}, not a {}",
+                stmt,
+                name,
+                want,
+                t
+            );
+        }
+        Ok(())
+    }
+
+    /// Make sure a sub-procedure used by `stmt` takes exactly the
+    /// param types we're going to pass it. Like CALL, a SUB that isn't
+    /// defined yet gets checked when it is.
+    fn check_sub_params(
+        &mut self,
+        stmt: &str,
+        sub: Pair<Rule>,
+        want: &[LDPLType],
+    ) -> LDPLResult<()> {
+        let ident = sub.as_str();
+        let mangled = mangle_sub(ident);
+        if !self.forwards.iter().any(|(fwd, _)| *fwd == mangled) {
+            let params = self.defs.get(&ident.to_uppercase()).cloned();
+            self.set_forward(&mangled, params.as_deref().unwrap_or(want));
+        }
+
+        match self.defs.get(&ident.to_uppercase()) {
+            None => {
+                let (line, col) = sub.as_span().start_pos().line_col();
+                self.expected_defs
+                    .entry(ident.to_uppercase())
+                    .or_default()
+                    .push(CallSite {
+                        name: ident.to_string(),
+                        file: self.path.clone(),
+                        line,
+                        col,
+                        args: want.to_vec(),
+                    });
+                Ok(())
             }
-            _ => t,
-        })
+            Some(params) if params != want => span_error!(
+                sub,
+                "{} needs sub-procedure {} to take ({}), not ({})",
+                stmt,
+                ident,
+                show_types(want),
+                show_types(params)
+            ),
+            _ => Ok(()),
+        }
     }
 
     /// Find the type for an expression.
@@ -1418,7 +4109,7 @@ impl Compiler {
             Rule::var => self.type_of_var(expr),
             Rule::ident => self.type_of_var(expr),
             Rule::number => Ok(&LDPLType::Number),
-            Rule::text | Rule::linefeed => Ok(&LDPLType::Text),
+            Rule::text | Rule::linefeed | Rule::crlf => Ok(&LDPLType::Text),
             _ => unexpected!(expr),
         }
     }
@@ -1433,19 +4124,45 @@ impl Compiler {
                 } else if let Some(t) = self.globals.get(&var.as_str().to_uppercase()) {
                     Ok(t)
                 } else {
-                    error!("No type found for {}", var.as_str())
+                    span_error!(var, "No type found for {}", var.as_str())
                 }
             }
-            Rule::lookup => {
-                let mut iter = var.into_inner();
-                let base = iter.next().unwrap();
-                let out = self.type_of_var(base);
-                out
-            }
+            Rule::lookup => self.type_of_lookup(var.into_inner()),
             _ => unexpected!(var),
         }
     }
 
+    /// Find the LDPLType for the parts of a lookup, like a:b:1.
+    fn type_of_lookup(&self, mut iter: Pairs<Rule>) -> LDPLResult<&LDPLType> {
+        // each index peels off one level: list:0 is an element
+        let mut t = self.type_of_var(iter.next().unwrap())?;
+        for part in iter {
+            t = match t {
+                LDPLType::List(inner) | LDPLType::Map(inner) => inner,
+                _ => break,
+            };
+            // a:b:1 where b is a container is a[b[1]], so the
+            // rest of the parts belong to b
+            if part.as_rule() == Rule::ident {
+                if let Ok(t) = self.type_of_var(part) {
+                    if t.is_collection() {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(t)
+    }
+
+    /// Forward declare a function, replacing its params if it already
+    /// is.
+    fn set_forward(&mut self, mangled: &str, params: &[LDPLType]) {
+        match self.forwards.iter_mut().find(|(fwd, _)| fwd == mangled) {
+            Some((_, old)) => *old = params.to_vec(),
+            None => self.forwards.push((mangled.to_string(), params.to_vec())),
+        }
+    }
+
     /// Expand a relative file path into a full one, based on the
     /// current file we're compiling.
     fn expand_path(&self, file: &str) -> String {
@@ -1456,6 +4173,8 @@ impl Compiler {
                     return format!("{}/{}", root, file);
                 }
             }
+        } else if let Some(base) = &self.base_dir {
+            return base.join(file).to_string_lossy().to_string();
         }
 
         file.to_string()
@@ -1484,16 +4203,108 @@ impl Compiler {
 }
 
 /// LDPL Type => C++ Type
-fn compile_type(ldpl_type: &str) -> &str {
-    match ldpl_type.to_lowercase().as_ref() {
-        "number" => "ldpl_number",
-        "number list" => "ldpl_list<ldpl_number>",
-        "number map" | "number vector" => "ldpl_map<ldpl_number>",
-        "text" => "chText",
-        "text list" => "ldpl_list<chText>",
-        "text map" | "text vector" => "ldpl_map<chText>",
-        _ => "UNKNOWN_TYPE",
+fn compile_type(ldpl_type: &str) -> String {
+    match LDPLType::parse(ldpl_type) {
+        Some(t) => compile_ldpl_type(&t),
+        None => "UNKNOWN_TYPE".into(),
+    }
+}
+
+/// LDPLType => C++ Type, nesting containers as deep as they go.
+fn compile_ldpl_type(ldpl_type: &LDPLType) -> String {
+    match ldpl_type {
+        LDPLType::Number => "ldpl_number".into(),
+        LDPLType::Text => "chText".into(),
+        LDPLType::List(inner) => format!("ldpl_list<{}>", compile_ldpl_type(inner)),
+        LDPLType::Map(inner) => format!("ldpl_map<{}>", compile_ldpl_type(inner)),
+    }
+}
+
+/// Param types for a C++ function declaration: `ldpl_number&, chText&`
+fn compile_param_types(params: &[LDPLType]) -> String {
+    params
+        .iter()
+        .map(|t| format!("{}&", compile_ldpl_type(t)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Type declared by a `type_name` in DATA:, LOCAL DATA:, or PARAMETERS:.
+fn check_type_name(pair: &Pair<Rule>) -> LDPLResult<LDPLType> {
+    match LDPLType::parse(pair.as_str()) {
+        Some(t) => Ok(t),
+        None => unknown_type(pair, pair.as_str()),
+    }
+}
+
+/// Param types after DECLARE SUB _ WITH, all in one `type_name` like
+/// `number text list list`. A new type starts at each NUMBER or TEXT.
+fn check_type_names(pair: Option<Pair<Rule>>) -> LDPLResult<Vec<LDPLType>> {
+    let pair = match pair {
+        Some(pair) => pair,
+        None => return Ok(vec![]),
+    };
+    let mut names: Vec<Vec<&str>> = vec![];
+    for word in pair.as_str().split_whitespace() {
+        let scalar = word.eq_ignore_ascii_case("number") || word.eq_ignore_ascii_case("text");
+        match names.last_mut() {
+            Some(name) if !scalar => name.push(word),
+            _ => names.push(vec![word]),
+        }
     }
+    names
+        .iter()
+        .map(|name| {
+            let name = name.join(" ");
+            match LDPLType::parse(&name) {
+                Some(t) => Ok(t),
+                None => unknown_type(&pair, &name),
+            }
+        })
+        .collect()
+}
+
+fn unknown_type<T>(pair: &Pair<Rule>, name: &str) -> LDPLResult<T> {
+    span_error!(
+        pair,
+        "Unknown type '{}'; expected number or text followed by any number of list or map",
+        name
+    )
+}
+
+/// Describe how a CALL's argument types don't fit a SUB's params, if
+/// they don't.
+fn call_args_mismatch(name: &str, params: &[LDPLType], args: &[LDPLType]) -> Option<String> {
+    if params.len() != args.len() {
+        return Some(format!(
+            "SUB {} expects {} arguments, got {}",
+            name,
+            params.len(),
+            args.len()
+        ));
+    }
+    params
+        .iter()
+        .zip(args)
+        .position(|(param, arg)| param != arg)
+        .map(|i| {
+            format!(
+                "argument {} to {}: expected {}, got {}",
+                i + 1,
+                name,
+                params[i],
+                args[i]
+            )
+        })
+}
+
+/// Types joined for error messages, like `number, text list`.
+fn show_types(types: &[LDPLType]) -> String {
+    types
+        .iter()
+        .map(|t| t.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
 /// Mangle a variable name for C++.
@@ -1540,6 +4351,69 @@ fn mangle_extern(ident: &str) -> String {
     mangled.to_uppercase()
 }
 
+/// `path` with symlinks and ..s resolved, so the same file always
+/// gets the same name. Falls back to `path` if it doesn't exist.
+fn canonical_path(path: &str) -> String {
+    std::fs::canonicalize(path)
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|_| normalize_path(path))
+}
+
+/// Clean up `path` without asking the filesystem: `a/./b/../c.ldpl`
+/// becomes `a/c.ldpl`. Used for files that may only exist in memory.
+fn normalize_path(path: &str) -> String {
+    let mut parts: Vec<&str> = vec![];
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." if parts.last().is_some_and(|p| *p != "..") => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    let joined = parts.join("/");
+    if path.starts_with('/') {
+        format!("/{}", joined)
+    } else {
+        joined
+    }
+}
+
+/// Quote and escape a string for JSON.
+fn json_str(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Escape `s` to go between the quotes of a C++ string literal.
+fn escape_cpp_string(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
 /// Remove "quotes" from a literal text string.
 fn unquote(text: &str) -> &str {
     &text[1..text.len() - 1]
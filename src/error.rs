@@ -8,6 +8,8 @@ pub struct LDPLError {
     pub line: usize,
     pub col: usize,
     pub len: usize,
+    /// Source file the error came from, if we know it.
+    pub file: Option<String>,
 }
 
 impl LDPLError {
@@ -17,8 +19,23 @@ impl LDPLError {
             line,
             col,
             len,
+            file: None,
         }
     }
+
+    /// Where the error happened, like `file.ldpl:3:5`. None if we
+    /// don't have a line number.
+    pub fn location(&self) -> Option<String> {
+        if self.line == 0 {
+            return None;
+        }
+        Some(format!(
+            "{}:{}:{}",
+            self.file.as_deref().unwrap_or("<stdin>"),
+            self.line,
+            self.col
+        ))
+    }
 }
 
 impl error::Error for LDPLError {
@@ -36,21 +53,25 @@ impl fmt::Display for LDPLError {
 impl From<Result<String, String>> for LDPLError {
     fn from(error: Result<String, String>) -> Self {
         LDPLError {
-            details: format!("{}", error.unwrap_err()),
+            details: error.unwrap_err(),
             line: 0,
             col: 0,
             len: 1,
+            file: None,
         }
     }
 }
 
 impl From<pest::error::Error<Rule>> for LDPLError {
+    /// Parse errors already point at their location, so line and
+    /// col are left at 0.
     fn from(error: pest::error::Error<Rule>) -> Self {
         LDPLError {
             details: format!("{}", error),
             line: 0,
             col: 0,
             len: 1,
+            file: None,
         }
     }
 }
@@ -62,13 +83,14 @@ impl From<io::Error> for LDPLError {
             line: 0,
             col: 0,
             len: 1,
+            file: None,
         }
     }
 }
 
 impl From<LDPLError> for io::Error {
     fn from(error: LDPLError) -> Self {
-        io::Error::new(io::ErrorKind::Other, error.details)
+        io::Error::other(error.details)
     }
 }
 
@@ -79,7 +101,7 @@ macro_rules! parse_error {
         Err(LDPLError::new(format!("expected {}, got {:?}", $want, $got.kind), $got.line, $got.col, $got.len))
     }};
     ($got:expr, $want:expr, $($args:expr),+) => {
-        parse_error!($got, format!($want, $($args),*));
+        parse_error!($got, format!($want, $($args),*))
     };
 }
 
@@ -90,7 +112,21 @@ macro_rules! line_error {
         Err(LDPLError::new($msg.into(), $line, $col, 1))
     }};
     ($line:expr, $col:expr, $msg:expr, $($args:expr),+) => {
-        line_error!($line, $col, format!($msg, $($args),*));
+        line_error!($line, $col, format!($msg, $($args),*))
+    };
+}
+
+/// Create an error pointing at a pest Pair, using its line, col, and
+/// length.
+macro_rules! span_error {
+    ($pair:expr, $msg:expr) => {{
+        use crate::LDPLError;
+        let span = $pair.as_span();
+        let (line, col) = span.start_pos().line_col();
+        Err(LDPLError::new($msg.into(), line, col, span.end() - span.start()))
+    }};
+    ($pair:expr, $msg:expr, $($args:expr),+) => {
+        span_error!($pair, format!($msg, $($args),*))
     };
 }
 
@@ -100,6 +136,6 @@ macro_rules! error {
         line_error!(0, 0, $msg)
     };
     ($msg:expr, $($args:expr),*) => {
-        error!(format!($msg, $($args),*));
+        error!(format!($msg, $($args),*))
     };
 }
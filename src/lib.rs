@@ -6,6 +6,7 @@ pub mod error;
 pub mod builder;
 pub mod compiler;
 pub mod parser;
+pub mod runner;
 mod types;
 
 pub use error::LDPLError;
@@ -1,7 +1,9 @@
-use ldpl::{compiler, LDPLResult};
+use ldpl::{compiler, runner::Runner, LDPLError, LDPLResult};
 use std::{
+    fs,
     io::{self, Read},
-    process::{Command, Stdio},
+    thread,
+    time::{Duration, SystemTime},
 };
 
 const DEFAULT_COMMAND: &str = "build";
@@ -13,17 +15,40 @@ macro_rules! error {
             std::process::exit(1);
         }};
         ($fmt:expr, $($args:expr),*) => {
-            error!(format!($fmt, $($args),*));
+            error!(format!($fmt, $($args),*))
         };
     }
 
+/// Print error message to the console without exiting, for --watch.
+fn print_error(error: &LDPLError) {
+    let msg = match error.location() {
+        Some(location) => format!("{}: {}", location, error.details),
+        None => error.details.clone(),
+    };
+    eprintln!("\x1b[91;1mLDPL Error: \x1b[0m{}", msg);
+}
+
+/// Print warning message to the console.
+fn print_warning(warning: &LDPLError) {
+    let msg = match warning.location() {
+        Some(location) => format!("{}: {}", location, warning.details),
+        None => warning.details.clone(),
+    };
+    eprintln!("\x1b[93;1mLDPL Warning: \x1b[0m{}", msg);
+}
+
 fn main() {
     if let Err(e) = run() {
-        error!(e);
+        match e.location() {
+            Some(location) => error!("{}: {}", location, e.details),
+            None => error!(e),
+        }
     }
 }
 
 fn run() -> LDPLResult<()> {
+    // declared early so the info!() macro below can see it
+    #[allow(clippy::needless_late_init)]
     let quiet: bool;
     let args = std::env::args().skip(1).collect::<Vec<String>>();
 
@@ -57,8 +82,19 @@ fn run() -> LDPLResult<()> {
     let mut command = DEFAULT_COMMAND;
     let mut file = String::new();
     let mut outfile = None;
+    let mut keep_cpp = false;
+    let mut cc = None;
+    let mut opt_level = None;
+    let mut profile = false;
+    let mut debug_lines = false;
+    let mut manifest = None;
+    let mut strict = false;
+    let mut jobs = None;
+    let mut watch = false;
     let mut includes = vec![];
     let mut ext_includes = vec![];
+    let mut ext_dirs = vec![];
+    let mut recursive = false;
     let mut ext_flags = vec![];
     let mut stdin = String::new();
 
@@ -104,80 +140,235 @@ fn run() -> LDPLResult<()> {
                     ext_includes.push(file);
                 }
             }
+            "--ext-dir" => {
+                if args.is_empty() {
+                    error!("extension directory expected.");
+                }
+                ext_dirs.push(args.remove(0));
+            }
+            "--recursive" => recursive = true,
             "-f" => {
                 if args.is_empty() {
                     error!("flag expected.");
                 }
                 ext_flags.push(args.remove(0));
             }
+            "-k" | "--keep-cpp" => keep_cpp = true,
+            "--profile" => profile = true,
+            "--debug-lines" => debug_lines = true,
+            "--strict" => strict = true,
+            "-w" | "--watch" => watch = true,
+            "-j" | "--jobs" => {
+                if args.is_empty() {
+                    error!("number of jobs expected.");
+                }
+                match args.remove(0).parse::<usize>() {
+                    Ok(n) if n > 0 => jobs = Some(n),
+                    _ => error!("--jobs expects a number greater than 0."),
+                }
+            }
+            "--manifest" => {
+                if args.is_empty() {
+                    error!("manifest file name expected.");
+                }
+                manifest = Some(args.remove(0));
+            }
+            "-C" | "--cc" => {
+                if args.is_empty() {
+                    error!("C++ compiler expected.");
+                }
+                cc = Some(args.remove(0));
+            }
             "-c" => {
                 if let Err(error) = io::stdin().read_to_string(&mut stdin) {
                     error!("Error reading STDIN: {}", error);
                 }
             }
+            "-O" => {
+                if args.is_empty() {
+                    error!("optimization level expected.");
+                }
+                opt_level = Some(args.remove(0));
+            }
+            _ if arg.starts_with("-O") => opt_level = Some(arg[2..].to_string()),
             "build" => command = "build",
-            "run" => command = "run",
+            "run" | "--run" => command = "run",
+            "deps" | "--emit-deps" => command = "deps",
+            "--print-search-paths" => command = "search-paths",
             _ if arg.starts_with('-') => error!("Unknown flag {}", arg),
             _ => file = arg,
         }
     }
 
-    quiet = command != "build";
+    if watch && command != "build" && command != "run" {
+        error!("--watch only works with build and run.");
+    }
+    if watch && !stdin.is_empty() {
+        error!("--watch needs a source file, not -c.");
+    }
+    quiet = command != "build" && !watch;
 
     if stdin.is_empty() {
         if file.is_empty() && !args.is_empty() {
             file = args.remove(0);
-        } else if file.is_empty() {
+        } else if file.is_empty() && command != "search-paths" {
             error!("filename expected.");
         }
     }
 
-    info!("Compiling {}", file);
-    let mut compiler = compiler::new();
-    if !includes.is_empty() {
-        for file in includes {
-            compiler.load_and_compile(&file)?;
+    // a Compiler with every option set but nothing loaded yet
+    let configure = || -> LDPLResult<compiler::Compiler> {
+        let mut compiler = compiler::new();
+        compiler.set_profile(profile);
+        compiler.set_debug_lines(debug_lines);
+        compiler.set_strict(strict);
+        for flag in &ext_flags {
+            compiler.add_flag(flag.clone())?;
         }
+        for ext in &ext_includes {
+            compiler.add_extension(ext.clone())?;
+        }
+        for dir in &ext_dirs {
+            compiler.add_extension_dir(dir, recursive)?;
+        }
+        if let Some(cc) = &cc {
+            compiler.set_compiler(cc.clone())?;
+        }
+        if let Some(level) = &opt_level {
+            compiler.set_opt_level(level)?;
+        }
+        if let Some(jobs) = jobs {
+            compiler.set_jobs(jobs);
+        }
+        Ok(compiler)
+    };
+
+    if command == "search-paths" {
+        print!("{}", configure()?.search_paths(&file));
+        return Ok(());
     }
-    for flag in ext_flags {
-        compiler.add_flag(flag)?;
-    }
-    for ext in ext_includes {
-        compiler.add_extension(ext)?;
-    }
-    if stdin.is_empty() {
-        compiler.load_and_compile(&file)?;
-    } else {
-        compiler.compile(&stdin)?;
+
+    // everything from source to Compiler, so --watch can do it again
+    let compile = || -> LDPLResult<compiler::Compiler> {
+        info!("Compiling {}", file);
+        let mut compiler = configure()?;
+        for file in &includes {
+            compiler.load_and_compile(file)?;
+        }
+        if stdin.is_empty() {
+            compiler.load_and_compile(&file)?;
+        } else if stdin.starts_with("--- ") {
+            compiler.compile_files(&stdin)?;
+        } else {
+            compiler.compile(&stdin)?;
+        }
+
+        for warning in &compiler.warnings {
+            print_warning(warning);
+        }
+
+        if let Some(path) = &manifest {
+            fs::write(path, compiler.manifest())?;
+            info!("Wrote manifest to {}", path);
+        }
+        Ok(compiler)
+    };
+
+    // rebuild whenever a loaded file changes. a failed build leaves
+    // the last good binary running.
+    if watch {
+        let mut runner: Option<Runner> = None;
+        let mut files = vec![file.clone()];
+        loop {
+            let built = compile().and_then(|compiler| {
+                info!("Building {}", file);
+                let bin = compiler.build(&file, outfile.clone(), keep_cpp)?;
+                Ok((compiler, bin))
+            });
+            match built {
+                Ok((compiler, bin)) => {
+                    success!("Built {}", bin);
+                    files = loaded_files(&includes, &file, &compiler);
+                    if command == "run" {
+                        info!("Running {}", bin);
+                        runner.get_or_insert_with(|| Runner::new(&bin)).restart()?;
+                    }
+                }
+                Err(e) => print_error(&e),
+            }
+            info!("Watching {} file(s) for changes...", files.len());
+            wait_for_change(&files);
+        }
     }
 
+    let compiler = compile()?;
+
     if command == "print" {
         println!("{}", compiler);
         return Ok(());
     }
 
+    if command == "deps" {
+        for path in loaded_files(&includes, &file, &compiler) {
+            println!("{}", path);
+        }
+        return Ok(());
+    }
+
     info!("Building {}", file);
-    let bin = compiler.build(&file, outfile)?;
+    let bin = compiler.build(&file, outfile, keep_cpp)?;
     info!("Saved as {}", bin);
+    if keep_cpp {
+        info!("Kept C++ source as {}.cpp", bin);
+    }
     success!("File(s) compiled successfully.");
 
     if command == "run" {
         info!("Running {}", bin);
-        let bin = if bin.starts_with('/') || bin.starts_with('.') {
-            bin
-        } else {
-            format!("./{}", bin)
-        };
-        let mut cmd = Command::new(bin)
-            .stdin(Stdio::inherit())
-            .stdout(Stdio::inherit())
-            .spawn()?;
-        cmd.wait()?;
+        let mut runner = Runner::new(&bin);
+        runner.start()?;
+        runner.wait()?;
     }
 
     Ok(())
 }
 
+/// Every .ldpl file the program loaded: -i includes, the main file
+/// (empty when reading STDIN), and its INCLUDEs. Canonical paths,
+/// no repeats.
+fn loaded_files(includes: &[String], file: &str, compiler: &compiler::Compiler) -> Vec<String> {
+    let mut files = includes.to_vec();
+    if !file.is_empty() {
+        files.push(file.to_string());
+    }
+    files.extend(compiler.includes.iter().cloned());
+    let mut seen = std::collections::HashSet::new();
+    let mut loaded = vec![];
+    for path in files {
+        let path = fs::canonicalize(&path)
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or(path);
+        if seen.insert(path.clone()) {
+            loaded.push(path);
+        }
+    }
+    loaded
+}
+
+/// Block until one of `files` is modified, created, or deleted.
+fn wait_for_change(files: &[String]) {
+    let stamp = |files: &[String]| -> Vec<Option<SystemTime>> {
+        files
+            .iter()
+            .map(|f| fs::metadata(f).and_then(|m| m.modified()).ok())
+            .collect()
+    };
+    let before = stamp(files);
+    while stamp(files) == before {
+        thread::sleep(Duration::from_millis(250));
+    }
+}
+
 fn print_version() {
     println!(
         "ldpl-rs v{version} ({built})",
@@ -192,7 +383,10 @@ fn print_usage() {
         r#"
     ldpl-rs [options] <command> <file.ldpl>
     ldpl-rs [-i='<included file>']... <source file>|-c
-            [-o='<output name>'|-r] [-f='<c++ flag>']... [-n]
+            [-o='<output name>'|-r] [-f='<c++ flag>']... [-C='<c++ compiler>'] [-O<level>] [-k] [-n]
+            [--profile] [--debug-lines] [--manifest='<json file>']
+            [--strict] [--jobs='<n>'] [--watch] [--ext-dir='<dir>' [--recursive]]
+            [--print-search-paths]
     ldpl-rs [-v|-h]
 "#
     );
@@ -202,6 +396,7 @@ fn print_usage() {
     print       Print compiled C++ code. (same as -r)
     build       Compile binary. (default)
     run         Run binary after building.
+    deps        Print every .ldpl file the program loads. (same as --emit-deps)
 "#
     );
     print!("\x1b[95;1mOptions:\x1b[0m");
@@ -213,7 +408,20 @@ fn print_usage() {
     -o=<name>                Set output file for compiled binary
     -i=<file>                Include file in current compilation
     -f=<flag>                Pass a flag to the C++ compiler
-    -c                       Compile from standard input
+    -c                       Compile from standard input. Split it into
+                             files with `--- <file>` lines, first is main
+    -k --keep-cpp            Keep the generated C++ next to the binary
+    -C --cc=<path>           C++ compiler to use (default: $CXX or c++)
+    -O<level>                Optimize the build: 0, 1, 2, 3, or s
+    --profile                Count sub-procedure calls, print them on exit
+    --debug-lines            Point C++ compiler errors at LDPL source lines
+    --manifest=<file>        Write a JSON description of the program
+    --strict                 Treat warnings, like unreachable code, as errors
+    -j --jobs=<n>            Compile up to n C++ extensions at once
+    --ext-dir=<dir>          Include every .cpp file in dir as an extension
+    --recursive              Search --ext-dir subdirectories too
+    --print-search-paths     Show where INCLUDE, USING, and the C++ build look
+    -w --watch               Rebuild when a source file changes (and rerun with run)
 "#,
     );
     println!(
@@ -0,0 +1,79 @@
+//! The Runner keeps one copy of a built program running, so `--watch`
+//! can swap in a fresh binary after every rebuild.
+
+use crate::LDPLResult;
+use std::process::{Child, Command, Stdio};
+
+pub struct Runner {
+    bin: String,
+    child: Option<Child>,
+}
+
+impl Runner {
+    /// Runner for the binary at `bin`. Nothing starts until `start()`.
+    pub fn new(bin: &str) -> Runner {
+        let bin = if bin.starts_with('/') || bin.starts_with('.') {
+            bin.to_string()
+        } else {
+            format!("./{}", bin)
+        };
+        Runner { bin, child: None }
+    }
+
+    /// Start the program unless it's already running.
+    pub fn start(&mut self) -> LDPLResult<()> {
+        if self.is_running() {
+            return Ok(());
+        }
+        let child = Command::new(&self.bin)
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .spawn()?;
+        self.child = Some(child);
+        Ok(())
+    }
+
+    /// Kill the program if it's still running.
+    pub fn stop(&mut self) -> LDPLResult<()> {
+        if let Some(mut child) = self.child.take() {
+            if child.try_wait()?.is_none() {
+                child.kill()?;
+            }
+            child.wait()?;
+        }
+        Ok(())
+    }
+
+    /// Stop the running program, if any, and start it again.
+    pub fn restart(&mut self) -> LDPLResult<()> {
+        self.stop()?;
+        self.start()
+    }
+
+    /// Block until the program exits on its own.
+    pub fn wait(&mut self) -> LDPLResult<()> {
+        if let Some(mut child) = self.child.take() {
+            child.wait()?;
+        }
+        Ok(())
+    }
+
+    /// Whether the program we started is still going.
+    pub fn is_running(&mut self) -> bool {
+        match &mut self.child {
+            Some(child) => matches!(child.try_wait(), Ok(None)),
+            None => false,
+        }
+    }
+
+    /// Process ID of the running program.
+    pub fn id(&self) -> Option<u32> {
+        self.child.as_ref().map(|child| child.id())
+    }
+}
+
+impl Drop for Runner {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
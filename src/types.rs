@@ -1,5 +1,7 @@
 //! Type in the LDPL Language.
 
+use std::fmt;
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum LDPLType {
     Number,
@@ -10,15 +12,34 @@ pub enum LDPLType {
 
 impl LDPLType {
     /// Create an LDPLType from an ident like `NUMBER` or `text list`.
+    /// Panics if it isn't a type; use `parse()` for user input.
     pub fn from(name: &str) -> Self {
-        match name.to_lowercase().as_ref() {
-            "number" => LDPLType::Number,
-            "number list" => LDPLType::List(Box::new(LDPLType::Number)),
-            "number map" | "number vector" => LDPLType::Map(Box::new(LDPLType::Number)),
-            "text" => LDPLType::Text,
-            "text list" => LDPLType::List(Box::new(LDPLType::Text)),
-            "text map" | "text vector" => LDPLType::Map(Box::new(LDPLType::Text)),
-            _ => unimplemented!(),
+        match LDPLType::parse(name) {
+            Some(t) => t,
+            None => panic!("Unknown type '{}'", name),
+        }
+    }
+
+    /// Like `from()`, but `None` for names that aren't a type.
+    /// Containers nest, so `number list list` is a list of number lists.
+    pub fn parse(name: &str) -> Option<Self> {
+        let name = normalize(name);
+        Some(match name.rsplit_once(' ') {
+            Some((inner, "list")) => LDPLType::List(Box::new(LDPLType::parse(inner)?)),
+            Some((inner, "map")) | Some((inner, "vector")) => {
+                LDPLType::Map(Box::new(LDPLType::parse(inner)?))
+            }
+            None if name == "number" => LDPLType::Number,
+            None if name == "text" => LDPLType::Text,
+            _ => return None,
+        })
+    }
+
+    /// The scalar at the bottom of a container, or the type itself.
+    pub fn innermost(&self) -> &LDPLType {
+        match self {
+            LDPLType::List(inner) | LDPLType::Map(inner) => inner.innermost(),
+            _ => self,
         }
     }
 
@@ -31,19 +52,11 @@ impl LDPLType {
     }
 
     pub fn is_list(&self) -> bool {
-        if let LDPLType::List(..) = self {
-            true
-        } else {
-            false
-        }
+        matches!(self, LDPLType::List(..))
     }
 
     pub fn is_map(&self) -> bool {
-        if let LDPLType::Map(..) = self {
-            true
-        } else {
-            false
-        }
+        matches!(self, LDPLType::Map(..))
     }
 
     pub fn is_collection(&self) -> bool {
@@ -51,22 +64,31 @@ impl LDPLType {
     }
 
     pub fn is_text_collection(&self) -> bool {
-        if let LDPLType::List(inner) = self {
-            **inner == LDPLType::Text
-        } else if let LDPLType::Map(inner) = self {
-            **inner == LDPLType::Text
-        } else {
-            false
-        }
+        self.is_collection() && self.innermost().is_text()
     }
 
     pub fn is_number_collection(&self) -> bool {
-        if let LDPLType::List(inner) = self {
-            **inner == LDPLType::Number
-        } else if let LDPLType::Map(inner) = self {
-            **inner == LDPLType::Number
-        } else {
-            false
+        self.is_collection() && self.innermost().is_number()
+    }
+}
+
+impl fmt::Display for LDPLType {
+    /// Prints the type the way it's declared: `number list`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LDPLType::Number => write!(f, "number"),
+            LDPLType::Text => write!(f, "text"),
+            LDPLType::List(inner) => write!(f, "{} list", inner),
+            LDPLType::Map(inner) => write!(f, "{} map", inner),
         }
     }
 }
+
+/// Lowercase a type name and collapse its whitespace, so `Number  LIST`
+/// becomes `number list`.
+pub fn normalize(name: &str) -> String {
+    name.split_whitespace()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
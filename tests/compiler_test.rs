@@ -0,0 +1,4090 @@
+#![allow(unused_macros)]
+use ldpl::{compiler, LDPLType};
+use std::{env, fs, process::Command};
+
+// compile a program into C++
+macro_rules! compile {
+    ($e:expr) => {
+        compiler::compile(&$e).unwrap().to_string()
+    };
+}
+
+// expect a compile error
+macro_rules! compile_err {
+    ($e:expr) => {
+        match compiler::compile(&$e) {
+            Ok(_) => panic!("expected a compile error"),
+            Err(e) => e.to_string(),
+        }
+    };
+}
+
+// build a program with the C++ compiler, run it, and return its output.
+// $name keeps the temp files of tests running in parallel apart.
+// $input, if given, is fed to the program's stdin.
+macro_rules! run {
+    ($name:expr, $e:expr) => {
+        run!($name, $e, "")
+    };
+    ($name:expr, $e:expr, $input:expr) => {{
+        let dir = env::temp_dir().join(format!("ldpl-test-{}-{}", std::process::id(), $name));
+        fs::create_dir_all(&dir).unwrap();
+        let (cpp, bin) = (dir.join("main.cpp"), dir.join("main-bin"));
+        fs::write(&cpp, compile!($e)).unwrap();
+        let status = Command::new("c++")
+            .arg(&cpp)
+            .args(["-std=gnu++11", "-w", "-o"])
+            .arg(&bin)
+            .status()
+            .unwrap();
+        assert!(status.success(), "C++ build failed");
+        let mut child = Command::new(&bin)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        {
+            use std::io::Write;
+            let input: &str = $input;
+            child
+                .stdin
+                .take()
+                .unwrap()
+                .write_all(input.as_bytes())
+                .unwrap();
+        }
+        let out = child.wait_with_output().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        String::from_utf8(out.stdout).unwrap()
+    }};
+}
+
+// assert the C++ contains a line, ignoring indentation
+macro_rules! assert_emits {
+    ($cpp:expr, $line:expr) => {
+        assert!(
+            $cpp.lines().any(|l| l.trim() == $line),
+            "expected `{}` in:\n{}",
+            $line,
+            $cpp
+        );
+    };
+}
+
+#[test]
+fn test_type_case() {
+    let cpp = compile!(
+        "data:
+a is NUMBER
+b is Text
+c is Number List
+d is text   MAP
+e is TEXT vector
+procedure:
+"
+    );
+    assert_emits!(cpp, "ldpl_number VAR_A = 0;");
+    assert_emits!(cpp, r#"chText VAR_B = "";"#);
+    assert_emits!(cpp, "ldpl_list<ldpl_number> VAR_C;");
+    assert_emits!(cpp, "ldpl_map<chText> VAR_D;");
+    assert_emits!(cpp, "ldpl_map<chText> VAR_E;");
+}
+
+#[test]
+fn test_nested_types() {
+    let list = |t| LDPLType::List(Box::new(t));
+    let map = |t| LDPLType::Map(Box::new(t));
+    assert_eq!(
+        list(list(LDPLType::Number)),
+        LDPLType::from("number list list")
+    );
+    assert_eq!(
+        map(map(list(LDPLType::Text))),
+        LDPLType::from("Text LIST map  map")
+    );
+    assert!(LDPLType::from("text list map").is_text_collection());
+    assert!(LDPLType::from("number map list list").is_number_collection());
+    assert_eq!(
+        "number list map list",
+        LDPLType::from("number list map list").to_string()
+    );
+    assert_eq!(None, LDPLType::parse("list list"));
+    assert_eq!(None, LDPLType::parse("number list lisst"));
+
+    let src = "data:
+row is number list
+grid is number list list
+cube is text map map map
+n is number
+procedure:
+push 1 to row
+push 2 to row
+push row to grid
+push 3 to row
+push row to grid
+store grid:1:2 in n
+display n \" \" grid:0:1 \" \"
+store \"hi\" in cube:\"a\":\"b\":\"c\"
+display cube:\"a\":\"b\":\"c\"
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "ldpl_list<ldpl_list<ldpl_number>> VAR_GRID;");
+    assert_emits!(cpp, "ldpl_map<ldpl_map<ldpl_map<chText>>> VAR_CUBE;");
+    assert_eq!("3 2 hi", run!("nested-types", src));
+}
+
+#[test]
+fn test_unknown_type() {
+    let err = match compiler::compile("data:\nn is number\nx is numbr\nprocedure:\n") {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert_eq!(
+        "Error: Unknown type 'numbr'; expected number or text followed by \
+         any number of list or map",
+        err.to_string()
+    );
+    assert_eq!((3, 6), (err.line, err.col));
+
+    let err = compile_err!("procedure:\nsub foo\nparameters:\nxs is text lisst\nend sub\n");
+    assert!(err.contains("Unknown type 'text lisst'"), "{}", err);
+    let err = compile_err!("procedure:\nsub foo\nlocal data:\nm is map\nend sub\n");
+    assert!(err.contains("Unknown type 'map'"), "{}", err);
+
+    // comments after a type aren't part of it
+    let cpp = compile!("data:\nx is number list  # some numbers\ny is external text\nprocedure:\n");
+    assert_emits!(cpp, "ldpl_list<ldpl_number> VAR_X;");
+    assert_emits!(cpp, "extern chText Y;");
+}
+
+#[test]
+fn test_empty_sub_sections() {
+    let cpp = compile!(
+        "procedure:
+sub noop
+    parameters:
+    # nothing yet
+    local data:
+    procedure:
+end sub
+sub with-param
+    parameters:
+    x is number
+end sub
+call noop
+"
+    );
+    assert_emits!(cpp, "void SUBPR_NOOP() {");
+    assert_emits!(cpp, "void SUBPR_WITHc45_PARAM(ldpl_number& VAR_X) {");
+    assert_emits!(cpp, "SUBPR_NOOP();");
+}
+
+#[test]
+fn test_reset_stmt() {
+    let cpp = compile!(
+        "data:
+n is number
+t is text
+nums is number list
+names is text map
+procedure:
+reset n
+reset t
+reset nums
+reset names
+reset nums:0
+reset names:\"bob\"
+"
+    );
+    assert_emits!(cpp, "VAR_N = 0;");
+    assert_emits!(cpp, r#"VAR_T = "";"#);
+    assert_emits!(cpp, "VAR_NUMS.inner_collection.clear();");
+    assert_emits!(cpp, "VAR_NAMES.inner_collection.clear();");
+    assert_emits!(cpp, "VAR_NUMS[0] = 0;");
+    assert_emits!(cpp, r#"VAR_NAMES["bob"] = "";"#);
+}
+
+#[test]
+fn test_swap_stmt() {
+    let src = "data:
+a is number
+b is number
+s is text
+t is text
+xs is text list
+ys is text list
+procedure:
+store 1 in a
+store 2 in b
+swap a with b
+store \"é\" in s
+store \"x\" in t
+swap s with t
+push \"one\" to xs
+swap xs with ys
+display a b s t ys:0
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "swap(VAR_A, VAR_B);");
+    assert_emits!(
+        cpp,
+        "swap(VAR_XS.inner_collection, VAR_YS.inner_collection);"
+    );
+    assert_eq!("21xéone", run!("swap", src));
+
+    let err = compile_err!("data:\nn is number\nt is text\nprocedure:\nswap n with t\n");
+    assert!(
+        err.contains("Can't SWAP n (number) with t (text)"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_display_crlf() {
+    let src = "data:
+lfs is number
+procedure:
+store 2 in lfs
+display lfs crlf \"x\" lf
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, r#"cout << VAR_LFS << "\r\n" << "x" << "\n" << flush;"#);
+    assert_eq!("2\r\nx\n", run!("display-crlf", src));
+}
+
+#[test]
+fn test_display_error() {
+    let src = "procedure:
+display \"out\"
+display error \"oops \" errorcode lf
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, r#"cerr << "oops " << VAR_ERRORCODE << "\n" << flush;"#);
+    assert_emits!(cpp, r#"cout << "out" << flush;"#);
+    // run! only captures stdout
+    assert_eq!("out", run!("display-error", src));
+    // a variable named ERROR makes DISPLAY ERROR ambiguous
+    let err = match compiler::compile("data:\nerror is text\nprocedure:\ndisplay error crlf\n") {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert!(
+        err.to_string()
+            .contains("DISPLAY ERROR writes to stderr, but ERROR is also a variable"),
+        "{}",
+        err
+    );
+    assert_eq!((4, 1), (err.line, err.col));
+    let err = compile_err!(
+        "procedure:
+sub report
+    local data:
+    error is number
+    procedure:
+    display error lf
+end sub
+"
+    );
+    assert!(err.contains("ERROR is also a variable"), "{}", err);
+}
+
+#[test]
+fn test_equals_ignoring_case() {
+    let src = "data:
+name is text
+procedure:
+store \"Hello World\" in name
+if name equals ignoring case \"hELLO wORLD\" then
+    display \"1 \"
+end if
+if name equals ignoring case \"hello\" then
+    display \"bad \"
+end if
+if name equals ignoring case \"hello worle\" then
+    display \"bad \"
+end if
+if \"ÀÉÎ ΣΑΣ ДОМ\" equals ignoring case \"àéî σασ дом\" then
+    display \"2 \"
+end if
+if not \"x\" equals ignoring case \"X\" or \"a\" equals ignoring case \"b\" then
+    display \"bad\"
+end if
+display \"done\"
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "if (equals_ignore_case(VAR_NAME, \"hELLO wORLD\")) {");
+    assert_eq!("1 2 done", run!("ignore-case", src));
+
+    let err = compile_err!(
+        "data:\nn is number\nprocedure:\nif n equals ignoring case \"1\" then\nend if\n"
+    );
+    assert!(
+        err.contains("EQUALS IGNORING CASE compares text, but n is a number"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_delete_key_stmt() {
+    let src = "data:
+ages is number map
+names is text map
+k is text
+n is number
+procedure:
+store 30 in ages:\"bob\"
+store 40 in ages:\"carol\"
+store 1 in ages:2
+store \"one\" in names:1
+store \"two\" in names:\"2\"
+store \"carol\" in k
+delete key k from ages
+delete key 2 from ages
+delete key \"nobody\" from ages
+delete key \"1\" from names
+store 2 in n
+delete key n from names
+get key count of ages in n
+display n \" \" ages:\"bob\" \" \"
+get key count of names in n
+display n
+";
+    let cpp = compile!(src);
+    assert_emits!(
+        cpp,
+        "VAR_AGES.inner_collection.erase(chText(VAR_K).str_rep());"
+    );
+    assert_emits!(
+        cpp,
+        "VAR_AGES.inner_collection.erase(to_ldpl_string(2).str_rep());"
+    );
+    assert_emits!(
+        cpp,
+        "VAR_NAMES.inner_collection.erase(to_ldpl_string(VAR_N).str_rep());"
+    );
+    assert_eq!("1 30 0", run!("delete-key", src));
+
+    let err = compile_err!("data:\nl is number list\nprocedure:\ndelete key 1 from l\n");
+    assert!(
+        err.contains("DELETE KEY expects a map, but l is a number list"),
+        "{}",
+        err
+    );
+    let err =
+        compile_err!("data:\nm is text map\nk is number list\nprocedure:\ndelete key k from m\n");
+    assert!(err.contains("Map keys are text or numbers"), "{}", err);
+}
+
+#[test]
+fn test_has_key() {
+    let src = "data:
+ages is number map
+name is text
+i is number
+procedure:
+store 30 in ages:\"bob\"
+store 1 in ages:2
+store \"bob\" in name
+if ages has key name then
+    display \"bob \"
+end if
+if ages has key \"alice\" then
+    display \"bad \"
+end if
+if ages has key 2 and not ages has key 3 then
+    display \"two \"
+end if
+if ages has key \"nobody\" or ages:\"bob\" is equal to 30 then
+    display \"or \"
+end if
+while not ages has key \"carol\" do
+    store i in ages:\"carol\"
+repeat
+display ages:\"carol\"
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "if (has_key(VAR_AGES, VAR_NAME)) {");
+    assert_eq!("bob two or 0", run!("has-key", src));
+
+    let err = compile_err!("data:\nl is number list\nprocedure:\nif l has key 1 then\nend if\n");
+    assert!(
+        err.contains("HAS KEY expects a map, but l is a number list"),
+        "{}",
+        err
+    );
+    let err = compile_err!(
+        "data:\nm is number map\nk is text list\nprocedure:\nif m has key k then\nend if\n"
+    );
+    assert!(err.contains("Map keys are text or numbers"), "{}", err);
+}
+
+#[test]
+fn test_chained_comparison() {
+    let cpp = compile!(
+        "data:
+a is number
+b is number
+c is number
+procedure:
+if a is less than b is less than or equal to c then
+    display \"ordered\"
+end if
+while a is equal to b or a is less than b is greater than c do
+repeat
+"
+    );
+    assert_emits!(cpp, "if ((VAR_A < VAR_B) && (VAR_B <= VAR_C)) {");
+    assert_emits!(
+        cpp,
+        "while ((VAR_A == VAR_B) || ((VAR_A < VAR_B) && (VAR_B > VAR_C))) {"
+    );
+}
+
+#[test]
+fn test_not_test() {
+    let cpp = compile!(
+        "data:
+a is number
+b is number
+procedure:
+if not (a is equal to b) then
+end if
+if not a is equal to 1 and b is equal to 2 then
+end if
+while not (a is equal to 1 or not b is equal to 2) do
+repeat
+"
+    );
+    assert_emits!(cpp, "if (!(VAR_A == VAR_B)) {");
+    assert_emits!(cpp, "if ((!(VAR_A == 1)) && (VAR_B == 2)) {");
+    assert_emits!(cpp, "while (!((VAR_A == 1) || (!(VAR_B == 2)))) {");
+}
+
+#[test]
+fn test_break_continue_n() {
+    let src = "data:
+i is number
+j is number
+t is text
+procedure:
+sub show
+parameters:
+n is number
+procedure:
+display n
+end sub
+for i from 0 to 3 step 1 do
+    for j from 0 to 3 step 1 do
+        if j is equal to 1 then
+            continue 2
+        end if
+        display i j \" \"
+    repeat
+    call show with 9
+repeat
+display \"/ \"
+while 1 is equal to 1 do
+    for each t in argv do
+    repeat
+    for i from 0 to 5 step 1 do
+        if i is equal to 2 then
+            break 2
+        end if
+        display i
+    repeat
+repeat
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "goto loop_0_continue;");
+    assert_emits!(cpp, "loop_0_continue:;");
+    assert_emits!(cpp, "goto loop_3_break;");
+    assert_emits!(cpp, "loop_3_break:;");
+    assert!(!cpp.contains("loop_1_"), "{}", cpp);
+    assert_eq!("00 10 20 / 01", run!("break-continue-n", src));
+
+    let err =
+        compile_err!("data:\ni is number\nprocedure:\nwhile 1 is equal to 1 do\nbreak 2\nrepeat\n");
+    assert!(
+        err.contains("BREAK 2 needs a whole number from 1 to 1"),
+        "{}",
+        err
+    );
+    let err = compile_err!("procedure:\nwhile 1 is equal to 1 do\ncontinue 0\nrepeat\n");
+    assert!(err.contains("CONTINUE 0 needs a whole number"), "{}", err);
+}
+
+#[test]
+fn test_solve_functions() {
+    let src = "data:
+x is number
+r is number
+procedure:
+store -16 in x
+in r solve sqrt of abs(x)
+display r lf
+in r solve SQRT OF 9 + 1
+display r lf
+in r solve 2 * abs(x + 6) ^ 2
+display r lf
+in r solve -exp(0)
+display r lf
+in r solve sin of 0 + cos(0) + tan(0) + log(1)
+display r lf
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_R = sqrt(fabs(VAR_X));");
+    assert_emits!(cpp, "VAR_R = (sqrt(9) + 1);");
+    assert_emits!(cpp, "VAR_R = (2 * pow(fabs((VAR_X + 6)), 2));");
+    assert_emits!(cpp, "VAR_R = (-exp(0));");
+    assert_eq!("4\n4\n200\n-1\n1\n", run!("solve-functions", src));
+
+    let err = match compiler::compile("data:\nr is number\nprocedure:\nin r solve 1 + cube(2)\n") {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert_eq!(
+        "Error: Unknown function in SOLVE: cube (expected ABS, SQRT, SIN, COS, TAN, LOG, or EXP)",
+        err.to_string()
+    );
+    assert_eq!((4, 16), (err.line, err.col));
+}
+
+#[test]
+fn test_solve_types() {
+    let err = compile_err!("data:\nt is text\nprocedure:\nin t solve 1 + 2\n");
+    assert!(
+        err.contains("SOLVE can only store into a number, but t is a text"),
+        "{}",
+        err
+    );
+
+    let err = match compiler::compile(
+        "data:\nn is number\nt is text\nprocedure:\nin n solve 1 + t * 2\n",
+    ) {
+        Ok(_) => panic!("expected a type error"),
+        Err(e) => e,
+    };
+    assert!(
+        err.to_string()
+            .contains("SOLVE only does math on numbers, but t is a text"),
+        "{}",
+        err
+    );
+    assert_eq!((5, 16), (err.line, err.col));
+
+    let err = compile_err!("data:\nn is number\nl is number list\nprocedure:\nin n solve l + 1\n");
+    assert!(err.contains("but l is a number list"), "{}", err);
+    let err = compile_err!("data:\nn is number\nprocedure:\nin n solve sqrt(\"4\")\n");
+    assert!(err.contains("SOLVE only does math on numbers"), "{}", err);
+
+    // list elements are fine
+    compile!("data:\nn is number\nl is number list\nprocedure:\npush 2 to l\nin n solve l:0 * 2\n");
+}
+
+#[test]
+fn test_solve_precedence() {
+    let cpp = compile!(
+        "data:
+x is number
+procedure:
+in x solve 2 + 3 * 4
+in x solve (2 + 3) * 4
+in x solve 2 ^ 3 ^ 2
+in x solve 10 - 4 - 3
+in x solve x * -1
+in x solve -(x + 1) / 2
+"
+    );
+    assert_emits!(cpp, "VAR_X = (2 + (3 * 4));");
+    assert_emits!(cpp, "VAR_X = ((2 + 3) * 4);");
+    assert_emits!(cpp, "VAR_X = pow(2, pow(3, 2));");
+    assert_emits!(cpp, "VAR_X = ((10 - 4) - 3);");
+    assert_emits!(cpp, "VAR_X = (VAR_X * (-1));");
+    assert_emits!(cpp, "VAR_X = ((-(VAR_X + 1)) / 2);");
+
+    let out = run!(
+        "solve",
+        "data:
+x is number
+procedure:
+in x solve 2 + 3 * 4
+display x lf
+in x solve (2 + 3) * 4
+display x lf
+in x solve 2 ^ 3 ^ 2
+display x lf
+in x solve 2 * 3 ^ 2
+display x lf
+in x solve 10 - 4 - 3
+display x lf
+in x solve 100 / 10 / 5
+display x lf
+in x solve -(2 + 3) * 2
+display x lf
+"
+    );
+    assert_eq!("14\n20\n512\n18\n3\n2\n-10\n", out);
+}
+
+#[test]
+fn test_map_each_stmt() {
+    let src = "data:
+n is number
+nums is number list
+doubled is number list
+procedure:
+sub double
+    parameters:
+    x is number
+    result is number
+    procedure:
+    in result solve x * 2
+end sub
+push 1 to nums
+push 2 to nums
+push 3 to nums
+map each n in nums as double giving doubled
+for each n in doubled do
+    display n \" \"
+repeat
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "SUBPR_DOUBLE(VAR_N, LPVAR_1);");
+    assert_emits!(
+        cpp,
+        "VAR_DOUBLED.inner_collection = LPVAR_0.inner_collection;"
+    );
+    assert_eq!("2 4 6 ", run!("map_each", src));
+
+    let err = compile_err!(
+        "data:
+n is number
+nums is number list
+names is text list
+procedure:
+sub double
+    parameters:
+    x is number
+    result is number
+end sub
+map each n in nums as double giving names
+"
+    );
+    assert!(
+        err.contains("(number, text), not (number, number)"),
+        "{}",
+        err
+    );
+
+    // like CALL, the SUB can be defined after it's used
+    let later = "data:
+n is number
+nums is number list
+doubled is number list
+procedure:
+push 4 to nums
+map each n in nums as double giving doubled
+display doubled:0
+sub double
+    parameters:
+    x is number
+    result is number
+    procedure:
+    in result solve x * 2
+end sub
+";
+    assert_eq!("8", run!("map_each_later", later));
+
+    let err = match compiler::compile(&later.replace("result is number", "result is text")) {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert!(
+        err.to_string()
+            .contains("argument 2 to double: expected text, got number"),
+        "{}",
+        err
+    );
+    assert_eq!((7, 23), (err.line, err.col));
+}
+
+#[test]
+fn test_filter_stmt() {
+    let src = "data:
+n is number
+nums is number list
+evens is number list
+procedure:
+sub even?
+    parameters:
+    x is number
+    keep is number
+    local data:
+    rem is number
+    procedure:
+    modulo x by 2 in rem
+    if rem is equal to 0 then
+        store 1 in keep
+    end if
+end sub
+for n from 1 to 9 step 1 do
+    push n to nums
+repeat
+filter n in nums keeping even? giving evens
+for each n in evens do
+    display n \" \"
+repeat
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "SUBPR_EVENc63_(VAR_N, LPVAR_1);");
+    assert_emits!(
+        cpp,
+        "if (LPVAR_1) LPVAR_0.inner_collection.push_back(RVAR_2);"
+    );
+    assert_eq!("2 4 6 8 ", run!("filter", src));
+
+    let err = compile_err!(
+        "data:
+t is text
+names is text list
+procedure:
+sub keep-all
+    parameters:
+    x is text
+    keep is text
+end sub
+filter t in names keeping keep-all giving names
+"
+    );
+    assert!(err.contains("(text, number), not (text, text)"), "{}", err);
+
+    let err = match compiler::compile(
+        "data:
+n is number
+nums is number list
+names is text list
+procedure:
+filter n in nums keeping even? giving names
+",
+    ) {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert!(
+        err.to_string()
+            .contains("FILTER can't put number elements into a text list"),
+        "{}",
+        err
+    );
+    assert_eq!((6, 39), (err.line, err.col));
+
+    let err = match compiler::compile(
+        "data:
+n is number
+nums is number list
+procedure:
+filter n in nums keeping even? giving nums
+",
+    ) {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert!(
+        err.to_string()
+            .contains("called but never defined: even? (called at 5:26)"),
+        "{}",
+        err
+    );
+    assert_eq!((5, 26), (err.line, err.col));
+    // the predicate can be defined below the FILTER
+    let later = "data:
+n is number
+nums is number list
+odds is number list
+procedure:
+for n from 1 to 6 step 1 do
+    push n to nums
+repeat
+filter n in nums keeping odd? giving odds
+for each n in odds do
+    display n \" \"
+repeat
+sub odd?
+    parameters:
+    x is number
+    keep is number
+    procedure:
+    modulo x by 2 in keep
+end sub
+";
+    assert_eq!("1 3 5 ", run!("filter_later", later));
+}
+
+#[test]
+fn test_reduce_stmt() {
+    let src = "data:
+n is number
+total is number
+nums is number list
+procedure:
+sub add
+    parameters:
+    sum is number
+    x is number
+    procedure:
+    in sum solve sum + x
+end sub
+for n from 1 to 5 step 1 do
+    push n to nums
+repeat
+store 100 in total
+reduce n in nums with add into total
+display total
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "SUBPR_ADD(VAR_TOTAL, VAR_N);");
+    assert_eq!("110", run!("reduce", src));
+
+    let err = compile_err!(
+        "data:
+n is number
+nums is number list
+procedure:
+reduce n in nums with missing into n
+"
+    );
+    assert!(
+        err.contains("called but never defined: missing (called at 5:23)"),
+        "{}",
+        err
+    );
+    // the reducer can be defined below the REDUCE
+    let later = "data:
+n is number
+longest is number
+nums is number list
+procedure:
+push 3 to nums
+push 9 to nums
+push 4 to nums
+reduce n in nums with bigger into longest
+display longest
+sub bigger
+    parameters:
+    best is number
+    x is number
+    procedure:
+    if x is greater than best then
+        store x in best
+    end if
+end sub
+";
+    assert_eq!("9", run!("reduce_later", later));
+}
+
+#[test]
+fn test_sort_stmt() {
+    let src = "data:
+nums is number list
+sorted is number list
+words is text list
+n is number
+w is text
+procedure:
+push 3 to nums
+push -1 to nums
+push 10 to nums
+push 2.5 to nums
+sort nums in sorted
+for each n in nums do
+    display n \" \"
+repeat
+display \"/ \"
+for each n in sorted do
+    display n \" \"
+repeat
+push \"pear\" to words
+push \"Apple\" to words
+push \"apple\" to words
+sort words
+for each w in words do
+    display \" \" w
+repeat
+";
+    let cpp = compile!(src);
+    assert_emits!(
+        cpp,
+        "VAR_SORTED.inner_collection = VAR_NUMS.inner_collection;"
+    );
+    assert_emits!(
+        cpp,
+        "sort(VAR_WORDS.inner_collection.begin(), VAR_WORDS.inner_collection.end());"
+    );
+    assert_eq!(
+        "3 -1 10 2.5 / -1 2.5 3 10  Apple apple pear",
+        run!("sort", src)
+    );
+
+    let err = compile_err!("data:\nn is number\nprocedure:\nsort n\n");
+    assert!(err.contains("SORT expects a list"), "{}", err);
+    let err =
+        compile_err!("data:\nns is number list\nts is text list\nprocedure:\nsort ns in ts\n");
+    assert!(
+        err.contains("SORT can't put number elements into a text list"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_reverse_stmt() {
+    let src = "data:
+nums is number list
+n is number
+t is text
+procedure:
+push 1 to nums
+push 2 to nums
+push 3 to nums
+reverse nums
+for each n in nums do
+    display n
+repeat
+store \"añb€😀\" in t
+reverse t
+display \" \" t
+";
+    let cpp = compile!(src);
+    assert_emits!(
+        cpp,
+        "reverse(VAR_NUMS.inner_collection.begin(), VAR_NUMS.inner_collection.end());"
+    );
+    assert_emits!(cpp, "VAR_T.reverse();");
+    assert_eq!("321 😀€bña", run!("reverse", src));
+
+    let err = compile_err!("data:\nn is number\nprocedure:\nreverse n\n");
+    assert!(
+        err.contains("REVERSE only works on lists and text, but n is a number"),
+        "{}",
+        err
+    );
+    let err = compile_err!("data:\nm is text map\nprocedure:\nreverse m\n");
+    assert!(err.contains("m is a text map"), "{}", err);
+}
+
+#[test]
+fn test_fill_range_stmt() {
+    let src = "data:
+nums is number list
+n is number
+procedure:
+sub show
+for each n in nums do
+    display n \" \"
+repeat
+display \"/ \"
+end sub
+fill nums from 1 to 5
+call show
+fill nums from 5 to 0 step -1
+call show
+fill nums from 0 to 9 step 2
+call show
+";
+    let cpp = compile!(src);
+    assert_emits!(
+        cpp,
+        "for(ldpl_number LPVAR_1 = 1; 1 >= 0 ? LPVAR_1 < 5 : LPVAR_1 > 5; LPVAR_1 += 1) {"
+    );
+    assert_eq!(
+        "1 2 3 4 / 5 4 3 2 1 / 0 2 4 6 8 / ",
+        run!("fill-range", src)
+    );
+
+    let err = compile_err!("data:\nts is text list\nprocedure:\nfill ts from 1 to 3\n");
+    assert!(
+        err.contains("FILL needs a number list, but ts is a text list"),
+        "{}",
+        err
+    );
+    let err = compile_err!("data:\nns is number list\nprocedure:\nfill ns from 1 to 3 step 0\n");
+    assert!(err.contains("FILL STEP can't be 0"), "{}", err);
+}
+
+#[test]
+fn test_random_stmt() {
+    let src = "data:
+r is number
+i is number
+seen is number map
+procedure:
+seed random with 7
+get random in r
+display r \" \"
+seed random with 7
+get random in r
+display r lf
+for i from 0 to 200 step 1 do
+    get random number from 6 to 1 in r
+    store 1 in seen:r
+repeat
+get key count of seen in r
+display r lf
+get random number from 3 to 3 in r
+display r \" \"
+get random number from 2.5 to 3.5 in r
+display r \" \" errorcode lf
+get random number from 1.2 to 1.8 in r
+display r \" \" errorcode \" \" errortext lf
+get random number from 1 to 2 in r
+display errorcode lf
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_R = get_random();");
+    assert_emits!(cpp, "VAR_R = get_random(6, 1);");
+
+    let out = run!("random", src);
+    let lines: Vec<_> = out.lines().collect();
+    let same: Vec<f64> = lines[0].split(' ').map(|n| n.parse().unwrap()).collect();
+    assert_eq!(same[0], same[1]);
+    assert!((0.0..1.0).contains(&same[0]), "{}", out);
+    // 1 through 6 all come up, nothing else does
+    assert_eq!("6", lines[1]);
+    // only whole numbers are picked
+    assert_eq!("3 3 0", lines[2]);
+    // and when there aren't any, you get `from` and an error
+    assert_eq!(
+        "1.2 1 There's no whole number between the bounds.",
+        lines[3]
+    );
+    assert_eq!("0", lines[4]);
+
+    let err = compile_err!("data:\nt is text\nprocedure:\nget random in t\n");
+    assert!(err.contains("stores into a number"), "{}", err);
+    let err =
+        compile_err!("data:\nn is number\nprocedure:\nget random number from \"1\" to 2 in n\n");
+    assert!(err.contains("expects a number"), "{}", err);
+}
+
+#[test]
+fn test_push_front_stmt() {
+    let src = "data:
+nums is number list
+words is text list
+front is number list
+i is number
+procedure:
+push 2 to nums
+push 1 to front of nums
+push 3 to nums
+push 0 to front of nums
+for each i in nums do
+    display i \" \"
+repeat
+push \"b\" to front of words
+push \"a\" to front of words
+display words:0 words:1 \" \"
+push 5 to front
+display front:0
+";
+    let cpp = compile!(src);
+    assert_emits!(
+        cpp,
+        "VAR_NUMS.inner_collection.insert(VAR_NUMS.inner_collection.begin(), 1);"
+    );
+    assert_eq!("0 1 2 3 ab 5", run!("push-front", src));
+
+    let err = compile_err!("data:\nl is text list\nprocedure:\npush 1 to front of l\n");
+    assert!(err.contains("expected text, got number"), "{}", err);
+    let err = compile_err!("data:\nm is number map\nprocedure:\npush 1 to front of m\n");
+    assert!(
+        err.contains("PUSH TO FRONT expects a list, but m is a number map"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_insert_delete_at_stmts() {
+    let src = "data:
+nums is number list
+i is number
+procedure:
+push 1 to nums
+push 3 to nums
+insert 2 at 1 in nums
+insert 0 at 0 in nums
+insert 4 at 4 in nums
+for each i in nums do
+    display i \" \"
+repeat
+delete index 0 from nums
+delete index 3 from nums
+delete index 1.5 from nums
+for each i in nums do
+    display i \" \"
+repeat
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "insert_at(VAR_NUMS, 1, 2);");
+    assert_emits!(cpp, "delete_at(VAR_NUMS, 0);");
+    assert_eq!("0 1 2 3 4 1 3 ", run!("insert-delete-at", src));
+
+    let err = compile_err!("data:\nl is text list\nprocedure:\ninsert 1 at 0 in l\n");
+    assert!(err.contains("expected text, got number"), "{}", err);
+    let err = compile_err!("data:\nl is number list\nprocedure:\ninsert 1 at \"0\" in l\n");
+    assert!(err.contains("INSERT needs a number index"), "{}", err);
+    let err = compile_err!("data:\nl is number list\nprocedure:\ndelete index \"0\" from l\n");
+    assert!(err.contains("DELETE INDEX needs a number index"), "{}", err);
+    let err = compile_err!("data:\nt is text\nprocedure:\ndelete index 0 from t\n");
+    assert!(err.contains("DELETE INDEX expects a list"), "{}", err);
+}
+
+#[test]
+fn test_shuffle_stmt() {
+    // std::shuffle only promises the same order for the same seed on
+    // the same C++ standard library, so compare runs against each
+    // other instead of a fixed order.
+    let src = "data:
+nums is number list
+words is text list
+n is number
+procedure:
+sub shuffled
+fill nums from 0 to 20
+shuffle nums
+for each n in nums do
+    display n \" \"
+repeat
+display lf
+end sub
+seed random with 42
+call shuffled
+seed random with 42
+call shuffled
+push \"a\" to words
+shuffle words
+";
+    let cpp = compile!(src);
+    assert_emits!(
+        cpp,
+        "shuffle(VAR_NUMS.inner_collection.begin(), VAR_NUMS.inner_collection.end(), ldpl_rng());"
+    );
+    assert_emits!(cpp, "ldpl_rng().seed((unsigned long)(42));");
+
+    let out = run!("shuffle", src);
+    let lines: Vec<_> = out.lines().collect();
+    assert_eq!(lines[0], lines[1]);
+    let mut nums: Vec<usize> = lines[0]
+        .split_whitespace()
+        .map(|n| n.parse().unwrap())
+        .collect();
+    nums.sort();
+    assert_eq!((0..20).collect::<Vec<_>>(), nums);
+
+    let err = compile_err!("data:\nm is text map\nprocedure:\nshuffle m\n");
+    assert!(err.contains("SHUFFLE expects a list"), "{}", err);
+}
+
+#[test]
+fn test_replace_range_stmt() {
+    let src = "data:
+nums is number list
+two is number list
+n is number
+procedure:
+sub show
+for each n in nums do
+    display n
+repeat
+display \" \"
+end sub
+push 7 to two
+push 8 to two
+fill nums from 0 to 5
+replace range 1 to 2 in nums with two
+call show
+fill nums from 0 to 5
+replace range 1 to 4 in nums with two
+call show
+fill nums from 0 to 5
+replace range 3 to 5 in nums with two
+call show
+replace range 0 to 5 in nums with nums
+call show
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "replace_range(VAR_NUMS, 1, 2, VAR_TWO);");
+    assert_eq!("078234 0784 01278 01278 ", run!("replace-range", src));
+
+    let err = compile_err!(
+        "data:\nns is number list\nts is text list\nprocedure:\nreplace range 0 to 1 in ns with ts\n"
+    );
+    assert!(
+        err.contains("REPLACE RANGE can't put text elements into a number list"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_get_char_stmt() {
+    let src = "data:
+word is text
+c is text
+i is number
+procedure:
+store \"ñandú\" in word
+get character at 0 from word in c
+display \"[\" c \"] \"
+get character at -1 from word in c
+display \"[\" c \"] \"
+get character at -5 from word in c
+display \"[\" c \"] \"
+get character at 1.7 from word in c
+display \"[\" c \"] \"
+store 5 in i
+get character at i from word in c
+display \"[\" c \"] \"
+get character at -6 from word in c
+display \"[\" c \"] \"
+get character at 0 from \"\" in c
+display \"[\" c \"]\"
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_C = charat(VAR_WORD, -1);");
+    assert_eq!("[ñ] [ú] [ñ] [a] [] [] []", run!("get-char", src));
+
+    let err =
+        compile_err!("data:\nc is text\nprocedure:\nget character at \"1\" from \"ab\" in c\n");
+    assert!(err.contains("needs a number"), "{}", err);
+    let err = compile_err!("data:\nn is number\nprocedure:\nget character at 1 from \"ab\" in n\n");
+    assert!(err.contains("stores into text"), "{}", err);
+}
+
+#[test]
+fn test_text_range_stmt() {
+    let src = "data:
+word is text
+out is text
+procedure:
+store \"kitten\" in word
+get text from 1 to 4 in word in out
+display \"[\" out \"] \"
+get text from 0 to 6 in word in out
+display \"[\" out \"] \"
+get text from 3 to 100 in word in out
+display \"[\" out \"] \"
+get text from -2 to 2 in word in out
+display \"[\" out \"] \"
+get text from 4 to 2 in word in out
+display \"[\" out \"] \"
+get text from 3 to 3 in word in out
+display \"[\" out \"] \"
+get text from 10 to 20 in word in out
+display \"[\" out \"] \"
+get text from 1 to 3 in \"ñandú\" in out
+display \"[\" out \"]\"
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_OUT = text_range(VAR_WORD, 1, 4);");
+    assert_eq!(
+        "[itt] [kitten] [ten] [ki] [] [] [] [an]",
+        run!("text-range", src)
+    );
+
+    let err = compile_err!("data:\nt is text\nprocedure:\nget text from \"1\" to 2 in t in t\n");
+    assert!(err.contains("needs numbers"), "{}", err);
+    let err =
+        compile_err!("data:\nn is number\nprocedure:\nget text from 1 to 2 in \"abc\" in n\n");
+    assert!(err.contains("stores into text"), "{}", err);
+}
+
+#[test]
+fn test_contains_stmt() {
+    let src = "data:
+n is number
+word is text
+procedure:
+store \"hello world\" in word
+store contains \"o w\" in word in n
+display n \" \"
+store contains \"xyz\" in word in n
+display n \" \"
+store contains \"\" in word in n
+display n \" \"
+store contains \"world!\" in word in n
+display n \" \"
+store contains \"dú\" in \"ñandú\" in n
+display n
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_N = utf8GetIndexOf(VAR_WORD, \"o w\") >= 0;");
+    assert_eq!("1 0 1 0 1", run!("contains", src));
+
+    // plain STORE still works
+    compile!("data:\nt is text\nprocedure:\nstore \"contains\" in t\n");
+
+    let err = compile_err!("data:\nt is text\nprocedure:\nstore contains \"a\" in \"b\" in t\n");
+    assert!(err.contains("stores into a number"), "{}", err);
+}
+
+#[test]
+fn test_trim_of_stmt() {
+    let src = "data:
+t is text
+procedure:
+trim \"  padded \" in t
+display \"[\" t \"] \"
+trim \"/\" of \"//usr/local/\" in t
+display \"[\" t \"] \"
+trim \"-=\" of \"=-=title-=-\" in t
+display \"[\" t \"] \"
+trim \"x\" of \"xxxx\" in t
+display \"[\" t \"] \"
+trim \"¡!\" of \"¡hola!\" in t
+display \"[\" t \"] \"
+trim \"\" of \" as is \" in t
+display \"[\" t \"]\"
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_T = trimCopy(\"  padded \");");
+    assert_emits!(cpp, "VAR_T = trim_chars(\"//usr/local/\", \"/\");");
+    assert_eq!(
+        "[padded] [usr/local] [title] [] [hola] [ as is ]",
+        run!("trim-of", src)
+    );
+
+    let err = compile_err!("data:\nt is text\nprocedure:\ntrim 1 of \"a\" in t\n");
+    assert!(err.contains("TRIM expects text: 1"), "{}", err);
+    let err = compile_err!("data:\nn is number\nprocedure:\ntrim \"a\" in n\n");
+    assert!(err.contains("TRIM stores into text"), "{}", err);
+}
+
+#[test]
+fn test_case_stmt() {
+    let src = "data:
+t is text
+procedure:
+store \"ñandú Éclair straße\" in t
+to uppercase t
+display t \" \"
+to lowercase t
+display t \" \"
+store uppercase of \"ΣΑΣ σας дом łódź ÿ\" in t
+display t \" \"
+store lowercase of \"ΣΑΣ ДОМ ŁÓDŹ ÀÉÎ Ÿ\" in t
+display t
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_T = utf8_to_upper(VAR_T);");
+    assert_emits!(cpp, "VAR_T = utf8_to_lower(\"ΣΑΣ ДОМ ŁÓDŹ ÀÉÎ Ÿ\");");
+    assert_eq!(
+        "ÑANDÚ ÉCLAIR STRAßE ñandú éclair straße ΣΑΣ ΣΑΣ ДОМ ŁÓDŹ Ÿ σασ дом łódź àéî ÿ",
+        run!("case", src)
+    );
+
+    let err = compile_err!("data:\nn is number\nprocedure:\nto uppercase n\n");
+    assert!(err.contains("UPPERCASE stores into text: n"), "{}", err);
+    let err = compile_err!("data:\nt is text\nprocedure:\nstore lowercase of 1 in t\n");
+    assert!(err.contains("LOWERCASE expects text: 1"), "{}", err);
+}
+
+#[test]
+fn test_format_percent_stmt() {
+    let src = "data:
+r is number
+t is text
+procedure:
+format percent 0.42 in t
+display t \" \"
+format percent 1 in t
+display t \" \"
+format percent 0.425 in t
+display t \" \"
+store 0.12345 in r
+format percent r with 2 decimals in t
+display t \" \"
+format percent 0.5 with 1 decimals in t
+display t \" \"
+format percent 0.005 with 0 decimals in t
+display t \" \"
+format percent 1.5 in t
+display t \" \"
+format percent -0.25 with 1 decimals in t
+display t
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_T = format_percent(0.42, -1);");
+    assert_emits!(cpp, "VAR_T = format_percent(VAR_R, 2);");
+    assert_eq!(
+        "42% 100% 42.5% 12.35% 50.0% 0% 100% 0.0%",
+        run!("format-percent", src)
+    );
+
+    let err = compile_err!("data:\nt is text\nprocedure:\nformat percent \"0.5\" in t\n");
+    assert!(err.contains("FORMAT PERCENT needs numbers"), "{}", err);
+    let err = compile_err!("data:\nn is number\nprocedure:\nformat percent 0.5 in n\n");
+    assert!(err.contains("stores into text"), "{}", err);
+}
+
+#[test]
+fn test_edit_distance_stmt() {
+    let src = "data:
+n is number
+word is text
+procedure:
+store \"kitten\" in word
+get edit distance of word and \"kitten\" in n
+display n \" \"
+get edit distance of word and \"sitten\" in n
+display n \" \"
+get edit distance of word and \"kittn\" in n
+display n \" \"
+get edit distance of word and \"kittens\" in n
+display n \" \"
+get edit distance of word and \"sitting\" in n
+display n \" \"
+get edit distance of \"abc\" and \"xyz\" in n
+display n \" \"
+get edit distance of \"\" and \"ñandú\" in n
+display n \" \"
+get edit distance of \"ñandu\" and \"ñandú\" in n
+display n
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_N = edit_distance(VAR_WORD, \"kitten\");");
+    assert_eq!("0 1 1 1 3 3 5 1", run!("edit-distance", src));
+
+    let err =
+        compile_err!("data:\nn is number\nprocedure:\nget edit distance of 1 and \"a\" in n\n");
+    assert!(err.contains("expects text: 1"), "{}", err);
+    let err =
+        compile_err!("data:\nt is text\nprocedure:\nget edit distance of \"a\" and \"b\" in t\n");
+    assert!(err.contains("stores into a number"), "{}", err);
+}
+
+#[test]
+fn test_tokenize_stmt() {
+    let src = "data:
+tokens is text list
+token is text
+text is text
+n is number
+procedure:
+in text join \"  one   two\\tthree\\n\\nfour\\r\\n\" \"ñandú\u{3000}five\u{a0}six  \"
+tokenize text in tokens
+for each token in tokens do
+    display \"[\" token \"]\"
+repeat
+display lf
+tokenize \" \\t\\n \" in tokens
+get length of tokens in n
+display n lf
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_TOKENS = utf8_tokenize(VAR_TEXT);");
+    assert_eq!(
+        "[one][two][three][four][ñandú][five][six]\n0\n",
+        run!("tokenize", src)
+    );
+
+    let err = compile_err!("data:\nl is number list\nprocedure:\ntokenize \"a b\" in l\n");
+    assert!(err.contains("stores into a text list"), "{}", err);
+}
+
+#[test]
+fn test_parse_csv_stmt() {
+    let src = "data:
+lines is text list
+fields is text list
+line is text
+field is text
+procedure:
+push \"a,b,,c\" to lines
+push \"\\\"Smith, Jo\\\",42,\\\"said \\\"\\\"hi\\\"\\\"\\\"\" to lines
+push \"\\\"\\\",x\\\"y\\\",ñ\" to lines
+push \"\" to lines
+push \"one,\\\"open, quote\" to lines
+for each line in lines do
+    parse csv line line in fields
+    for each field in fields do
+        display \"[\" field \"]\"
+    repeat
+    display \" \" errorcode lf
+repeat
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_FIELDS = parse_csv_line(VAR_LINE);");
+    assert_eq!(
+        "[a][b][][c] 0
+[Smith, Jo][42][said \"hi\"] 0
+[][x\"y\"][ñ] 0
+[] 0
+[one][open, quote] 1
+",
+        run!("parse-csv", src)
+    );
+
+    let err = compile_err!("data:\nl is number list\nprocedure:\nparse csv line \"1,2\" in l\n");
+    assert!(err.contains("stores into a text list"), "{}", err);
+    let err = compile_err!("data:\nl is text list\nprocedure:\nparse csv line 12 in l\n");
+    assert!(err.contains("expects text"), "{}", err);
+}
+
+#[test]
+fn test_parse_radix_stmt() {
+    let src = "data:
+n is number
+procedure:
+parse number \"1011\" radix 2 in n
+display n \" \"
+parse number \"ff\" radix 16 in n
+display n \" \"
+parse number \"-7F\" radix 16 in n
+display n \" \"
+parse number \"zz\" radix 36 in n
+display n \" \" errorcode \" \"
+parse number \"102\" radix 2 in n
+display n \" \" errorcode \" \" errortext \" \"
+parse number \"12\" radix 37 in n
+display errorcode \" \" errortext \" \"
+parse number \"\" radix 10 in n
+display errorcode \" \" errortext \" \"
+parse number \"42\" radix 10 in n
+display n \" \" errorcode
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_N = parse_radix(\"1011\", 2);");
+    assert_eq!(
+        "11 255 -127 1295 0 0 1 Can't PARSE NUMBER, invalid digit at character 3 for radix 2. \
+         1 Can't PARSE NUMBER, radix 37 isn't between 2 and 36. \
+         1 Can't PARSE NUMBER, there are no digits. 42 0",
+        run!("parse-radix", src)
+    );
+
+    let err = compile_err!("data:\nt is text\nprocedure:\nparse number \"1\" radix 2 in t\n");
+    assert!(err.contains("stores into a number"), "{}", err);
+    let err = compile_err!("data:\nn is number\nprocedure:\nparse number \"1\" radix \"2\" in n\n");
+    assert!(err.contains("needs a number RADIX"), "{}", err);
+}
+
+#[test]
+fn test_format_csv_stmt() {
+    let src = "data:
+fields is text list
+back is text list
+line is text
+field is text
+procedure:
+push \"plain\" to fields
+push \"\" to fields
+push \"Smith, Jo\" to fields
+push \"said \\\"hi\\\"\" to fields
+push \"two\\nlines\" to fields
+push \"ñ\" to fields
+format csv line fields in line
+display line lf
+parse csv line line in back
+for each field in back do
+    display \"[\" field \"]\"
+repeat
+display lf
+clear fields
+format csv line fields in line
+display \"(\" line \")\" lf
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_LINE = format_csv_line(VAR_FIELDS);");
+    assert_eq!(
+        "plain,,\"Smith, Jo\",\"said \"\"hi\"\"\",\"two\nlines\",ñ
+[plain][][Smith, Jo][said \"hi\"][two\nlines][ñ]
+()
+",
+        run!("format-csv", src)
+    );
+
+    let err =
+        compile_err!("data:\nl is number list\nt is text\nprocedure:\nformat csv line l in t\n");
+    assert!(err.contains("expects a text list"), "{}", err);
+    let err =
+        compile_err!("data:\nl is text list\nn is number\nprocedure:\nformat csv line l in n\n");
+    assert!(err.contains("stores into a text"), "{}", err);
+}
+
+#[test]
+fn test_parse_config_stmt() {
+    let src = "data:
+config is text map
+keys is text list
+key is text
+text is text
+procedure:
+store 1 in config:\"stale\"
+in text join \"# comment\\n\" \"name = Jo Ann \\r\\n\" \"\\n\" \"  ; also a comment\\n\" \"\\tport=8080\\n\" \"empty =\\n\" \"url = a=b#c\"
+parse config text into config
+get sorted keys of config in keys
+for each key in keys do
+    display key \"=[\" config:key \"]\" lf
+repeat
+display errorcode lf
+parse config \"a = 1\\njunk\\n= 2\\nb = 2\" into config
+get sorted keys of config in keys
+for each key in keys do
+    display key \"=\" config:key \" \"
+repeat
+display errorcode \" \" errortext lf
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "parse_config(VAR_TEXT, VAR_CONFIG);");
+    assert_eq!(
+        "empty=[]
+name=[Jo Ann]
+port=[8080]
+url=[a=b#c]
+0
+a=1 b=2 1 Can't PARSE CONFIG, no key = value on line 2.
+",
+        run!("parse-config", src)
+    );
+
+    let err = compile_err!("data:\nm is number map\nprocedure:\nparse config \"a=1\" into m\n");
+    assert!(err.contains("stores into a text map"), "{}", err);
+    let err = compile_err!("data:\nm is text map\nprocedure:\nparse config 1 into m\n");
+    assert!(err.contains("expects text"), "{}", err);
+}
+
+#[test]
+fn test_expand_stmt() {
+    let src = "data:
+vars is text map
+out is text
+n is number
+procedure:
+store \"Jo\" in vars:\"name\"
+store \"ñ\" in vars:\"x\"
+expand \"Hi {{name}}, {{ name }}! [{{missing}}] {{x}}{{x}}\" with vars in out
+display out lf
+expand \"{{name}} {{ unclosed\" with vars in out
+display out lf
+get key count of vars in n
+display n lf
+";
+    let cpp = compile!(src);
+    assert_emits!(
+        cpp,
+        "VAR_OUT = expand_template(\"Hi {{name}}, {{ name }}! [{{missing}}] {{x}}{{x}}\", VAR_VARS);"
+    );
+    // missing keys don't get added to the map
+    assert_eq!("Hi Jo, Jo! [] ññ\nJo {{ unclosed\n2\n", run!("expand", src));
+
+    let err =
+        compile_err!("data:\nm is number map\nt is text\nprocedure:\nexpand \"\" with m in t\n");
+    assert!(err.contains("expects a text map"), "{}", err);
+    let err =
+        compile_err!("data:\nm is text map\nn is number\nprocedure:\nexpand \"\" with m in n\n");
+    assert!(err.contains("stores into a text"), "{}", err);
+}
+
+#[test]
+fn test_serialize_stmt() {
+    let src = "data:
+nums is number list
+names is text map
+empty is number map
+out is text
+procedure:
+push 1 to nums
+push 2.5 to nums
+push -3 to nums
+serialize nums in out
+display out lf
+store \"Bo \\\"the\\\" cat\" in names:\"b\"
+store \"a\\\\b\" in names:\"a\"
+store \"ñ\" in names:1
+serialize names in out
+display out lf
+serialize empty in out
+display out lf
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_OUT = ldpl_serialize(VAR_NUMS);");
+    assert_eq!(
+        "[1, 2.5, -3]\n{\"1\": \"ñ\", \"a\": \"a\\\\b\", \"b\": \"Bo \\\"the\\\" cat\"}\n{}\n",
+        run!("serialize", src)
+    );
+
+    let err = compile_err!("data:\nn is number\nout is text\nprocedure:\nserialize n in out\n");
+    assert!(err.contains("expects a list or map"), "{}", err);
+    let err = compile_err!("data:\nl is text list\nn is number\nprocedure:\nserialize l in n\n");
+    assert!(err.contains("stores into a text"), "{}", err);
+
+    // nested collections can't be declared yet, so drive the header
+    // helper directly
+    let dir = env::temp_dir().join(format!("ldpl-test-{}-serialize-nested", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (cpp_path, bin) = (dir.join("main.cpp"), dir.join("main-bin"));
+    fs::write(
+        &cpp_path,
+        format!(
+            "#include \"{}/lib/ldpl_header.cpp\"
+int main() {{
+    ldpl_map<ldpl_list<chText>> m;
+    m[\"xs\"].inner_collection.push_back(\"tab\\there\");
+    m[\"xs\"].inner_collection.push_back(\"\\x01\");
+    m[\"ys\"];
+    cout << ldpl_serialize(m) << endl;
+    ldpl_map<ldpl_list<chText>> back;
+    ldpl_deserialize(ldpl_serialize(m), back);
+    cout << ldpl_serialize(back) << VAR_ERRORCODE << endl;
+}}
+",
+            env!("CARGO_MANIFEST_DIR")
+        ),
+    )
+    .unwrap();
+    let status = Command::new("c++")
+        .arg(&cpp_path)
+        .args(["-std=gnu++11", "-w", "-o"])
+        .arg(&bin)
+        .status()
+        .unwrap();
+    assert!(status.success(), "C++ build failed");
+    let out = Command::new(&bin).output().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(
+        "{\"xs\": [\"tab\\there\", \"\\u0001\"], \"ys\": []}\n\
+         {\"xs\": [\"tab\\there\", \"\\u0001\"], \"ys\": []}0\n",
+        String::from_utf8(out.stdout).unwrap()
+    );
+}
+
+#[test]
+fn test_deserialize_stmt() {
+    let src = "data:
+nums is number list
+back is number list
+names is text map
+again is text map
+text is text
+bad is text list
+i is number
+procedure:
+push 1 to nums
+push -2.5 to nums
+push 1000 to nums
+serialize nums in text
+deserialize text into back
+serialize back in text
+display text \" \" errorcode lf
+store \"Bo \\\"the\\\" cat\\\\\" in names:\"b\"
+store \"ñ\\ttab\" in names:1
+serialize names in text
+deserialize text into again
+serialize again in text
+display text lf
+deserialize \" { \\\"a\\\" : \\\"\\\\u00f1\\\\ud83d\\\\ude00\\\" } \" into again
+display again:\"a\" \" \" errorcode lf
+push \"keep\" to bad
+store \"[\\\"a\\\",\" in names:0
+store \"[\\\"a\\\"] x\" in names:1
+store \"[1]\" in names:2
+store \"[\\\"\\\\x\\\"]\" in names:3
+store \"\" in names:4
+store \"{\\\"a\\\" 1}\" in names:5
+for i from 0 to 6 step 1 do
+    deserialize names:i into bad
+    display errorcode \" \"
+repeat
+display errortext lf
+get length of bad in i
+display i \" \" bad:0 lf
+deserialize \"[\\\"1\\\"]\" into back
+display errorcode lf
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "ldpl_deserialize(VAR_TEXT, VAR_BACK);");
+    assert_eq!(
+        "[1, -2.5, 1000] 0
+{\"1\": \"ñ\\ttab\", \"b\": \"Bo \\\"the\\\" cat\\\\\"}
+ñ😀 0
+1 1 1 1 1 1 Can't DESERIALIZE, malformed input at character 1.
+1 keep
+1
+",
+        run!("deserialize", src)
+    );
+
+    let err =
+        compile_err!("data:\nn is number\nl is text list\nprocedure:\ndeserialize n into l\n");
+    assert!(err.contains("expects text"), "{}", err);
+    let err = compile_err!("data:\nn is number\nprocedure:\ndeserialize \"[]\" into n\n");
+    assert!(err.contains("stores into a list or map"), "{}", err);
+}
+
+#[test]
+fn test_get_length_stmt() {
+    let src = "data:
+ages is number map
+names is text list
+n is number
+procedure:
+store 1 in ages:\"a\"
+store 2 in ages:\"b\"
+push \"ñandú\" to names
+get length of ages in n
+display n \" \"
+store length of names in n
+display n \" \"
+store length of names:0 in n
+display n lf
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_N = VAR_AGES.inner_collection.size();");
+    assert_emits!(cpp, "VAR_N = VAR_NAMES.inner_collection.size();");
+    assert_emits!(cpp, "VAR_N = ((chText)VAR_NAMES[0]).size();");
+    assert_eq!("2 1 5\n", run!("get-length", src));
+
+    let err = compile_err!("data:\nn is number\nprocedure:\nget length of n in n\n");
+    assert!(
+        err.contains("GET LENGTH expects text, a list, or a map, but n is a number"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_max_value_length_stmt() {
+    let src = "data:
+names is text map
+nothing is text map
+n is number
+procedure:
+store \"bob\" in names:1
+store \"ñandú\" in names:2
+store \"jo\" in names:3
+get max value length of names in n
+display n lf
+get MIN value length of names in n
+display n lf
+get max value length of nothing in n
+display n lf
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_N = get_value_length(VAR_NAMES, true);");
+    assert_emits!(cpp, "VAR_N = get_value_length(VAR_NAMES, false);");
+    assert_eq!("5\n2\n0\n", run!("max_value_length", src));
+
+    let err = compile_err!(
+        "data:
+nums is number map
+n is number
+procedure:
+get max value length of nums in n
+"
+    );
+    assert!(err.contains("expects a text map"), "{}", err);
+
+    let err = compile_err!(
+        "data:
+names is text map
+t is text
+procedure:
+get max value length of names in t
+"
+    );
+    assert!(err.contains("stores into a number"), "{}", err);
+}
+
+#[test]
+fn test_get_key_of_value_stmt() {
+    let src = "data:
+scores is number map
+nothing is number map
+name is text
+n is number
+procedure:
+store 3 in scores:\"bob\"
+store 9 in scores:\"ann\"
+store 1 in scores:\"cid\"
+store 9 in scores:\"zed\"
+get key of max value in scores in name
+display name \" \" errorcode lf
+get key of min value in scores in name
+display name \" \" errorcode lf
+store 5 in nothing:10
+store 2 in nothing:20
+get key of MAX value in nothing in n
+display n lf
+clear nothing
+get key of min value in nothing in name
+display \"[\" name \"] \" errorcode lf
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "get_key_of_extreme(VAR_SCORES, VAR_NAME, true);");
+    assert_emits!(cpp, "get_key_of_extreme(VAR_SCORES, VAR_NAME, false);");
+    assert_eq!("ann 0\ncid 0\n10\n[] 1\n", run!("key_of_value", src));
+
+    let err = compile_err!(
+        "data:
+names is text map
+name is text
+procedure:
+get key of max value in names in name
+"
+    );
+    assert!(err.contains("expects a number map"), "{}", err);
+
+    let err = match compiler::compile(
+        "data:
+scores is number map
+names is text list
+procedure:
+get key of max value in scores in names
+",
+    ) {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert!(
+        err.to_string()
+            .contains("GET KEY OF VALUE can't store a key in a text list"),
+        "{}",
+        err
+    );
+    assert_eq!((5, 35), (err.line, err.col));
+}
+
+#[test]
+fn test_fractional_list_index() {
+    let result = compiler::compile(
+        "data:
+nums is number list
+ages is number map
+n is number
+procedure:
+store 1 in ages:2.5
+store nums:2.0 in n
+display nums:2.5
+",
+    );
+    let err = match result {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert_eq!(
+        "Error: LIST index must be a whole number, got 2.5",
+        err.to_string()
+    );
+    assert_eq!((8, 14), (err.line, err.col));
+}
+
+#[test]
+fn test_lookup_key_types() {
+    let data = "data:
+nums is number list
+ages is number map
+idx is number list
+key is text
+n is number
+t is text
+procedure:
+";
+    // text and numeric map keys, char access, nested index
+    let cpp = compile!(format!(
+        "{}store ages:key in n\nstore ages:1 in n\ndisplay t:0\nstore nums:idx:0 in n\n",
+        data
+    ));
+    assert_emits!(cpp, "VAR_N = VAR_AGES[VAR_KEY];");
+    assert_emits!(cpp, "VAR_N = VAR_NUMS[VAR_IDX[0]];");
+
+    let err = match compiler::compile(&format!("{}store nums:key in n\n", data)) {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert_eq!(
+        "Error: LIST index must be a number, but key is text",
+        err.to_string()
+    );
+    assert_eq!((9, 12), (err.line, err.col));
+
+    let err = compile_err!(format!("{}store nums:\"j\" in n\n", data));
+    assert!(err.contains("LIST index must be a number"), "{}", err);
+    let err = compile_err!(format!("{}store nums:0:1 in n\n", data));
+    assert!(
+        err.contains("nums:0 is a number, so it can't be indexed with 1"),
+        "{}",
+        err
+    );
+    let err = compile_err!(format!("{}store ages:idx in n\n", data));
+    assert!(
+        err.contains("idx is a collection and can't be used as a key"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_negate_stmt() {
+    let src = "data:
+a is number
+b is number
+procedure:
+store 5 in a
+negate a
+display a \" \"
+negate a
+display a \" \"
+negate -2.5 in b
+display b \" \"
+negate a in b
+display a \" \" b \" \"
+store 0 in a
+negate a
+display a
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_A = 0 - (VAR_A);");
+    assert_emits!(cpp, "VAR_B = 0 - (-2.5);");
+    assert_emits!(cpp, "VAR_B = 0 - (VAR_A);");
+    assert_eq!("-5 5 2.5 5 -5 0", run!("negate", src));
+
+    let err = compile_err!(
+        "data:
+t is text
+procedure:
+negate 1 in t
+"
+    );
+    assert!(
+        err.contains("NEGATE can only store into a number"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_rounding_stmts() {
+    let src = "data:
+a is number
+b is number
+procedure:
+store 2.5 in a
+round a
+display a \" \"
+store -2.5 in a
+round a
+display a \" \"
+round 1.49 in b
+display b \" \"
+store 2.1 in a
+ceil a
+display a \" \"
+ceil -2.9 in b
+display b \" \"
+store 2.9 in a
+floor a
+display a \" \"
+floor -2.1 in b
+display a \" \" b
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_A = round(VAR_A);");
+    assert_emits!(cpp, "VAR_B = round(1.49);");
+    assert_emits!(cpp, "VAR_A = ceil(VAR_A);");
+    assert_emits!(cpp, "VAR_B = ceil(-2.9);");
+    assert_emits!(cpp, "VAR_A = floor(VAR_A);");
+    assert_emits!(cpp, "VAR_B = floor(-2.1);");
+    assert_eq!("3 -3 1 3 -2 2 2 -3", run!("rounding", src));
+
+    for stmt in ["round", "ceil", "floor"] {
+        let err = compile_err!(format!("data:\nt is text\nprocedure:\n{} 1 in t\n", stmt));
+        let want = format!("{} can only store into a number", stmt.to_uppercase());
+        assert!(err.contains(&want), "{}", err);
+
+        let err = compile_err!(format!("data:\nt is text\nprocedure:\n{} t\n", stmt));
+        let want = format!("{} expects a number, got t", stmt.to_uppercase());
+        assert!(err.contains(&want), "{}", err);
+    }
+}
+
+#[test]
+fn test_incr_decr_stmt() {
+    let src = "data:
+n is number
+step is number
+counts is number list
+procedure:
+increment n
+increment n
+display n \" \"
+store 10 in step
+increment n by step
+display n \" \"
+decrement n
+decrement n by 0.5
+display n \" \"
+push 0 to counts
+increment counts:0 by 3
+display counts:0
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_N += 1;");
+    assert_emits!(cpp, "VAR_N += VAR_STEP;");
+    assert_emits!(cpp, "VAR_N -= 1;");
+    assert_emits!(cpp, "VAR_N -= 0.5;");
+    assert_emits!(cpp, "VAR_COUNTS[0] += 3;");
+    assert_eq!("2 12 10.5 3", run!("incr_decr", src));
+
+    let err = compile_err!(
+        "data:
+t is text
+procedure:
+increment t
+"
+    );
+    assert!(
+        err.contains("INCREMENT expects a number variable"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_store_test_stmt() {
+    let src = "data:
+a is number
+b is number
+flag is number
+test is number
+procedure:
+store 1 in a
+store 2 in b
+store test a is less than b in flag
+display flag
+store test a is greater than b in flag
+display flag
+store test a is equal to 1 and (b is equal to 3 or not b is less than a) in flag
+display flag
+store test in flag
+display flag
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_FLAG = (VAR_A < VAR_B) ? 1 : 0;");
+    assert_emits!(cpp, "VAR_FLAG = (VAR_A > VAR_B) ? 1 : 0;");
+    assert_emits!(
+        cpp,
+        "VAR_FLAG = ((VAR_A == 1) && ((VAR_B == 3) || (!(VAR_B < VAR_A)))) ? 1 : 0;"
+    );
+    // a variable named `test` still works with plain STORE
+    assert_emits!(cpp, "VAR_FLAG = VAR_TEST;");
+    assert_eq!("1010", run!("store_test", src));
+}
+
+#[test]
+fn test_sub_exists_stmt() {
+    let src = "data:
+yes is number
+no is number
+later is number
+procedure:
+sub helper
+end sub
+store sub exists helper in yes
+store sub exists nothing in no
+store sub exists defined-later in later
+sub defined-later
+end sub
+display yes no later
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_YES = 1;");
+    assert_emits!(cpp, "VAR_NO = 0;");
+    assert_emits!(cpp, "VAR_LATER = 0;");
+    assert_eq!("100", run!("sub-exists", src));
+
+    let err = compile_err!("data:\nt is text\nprocedure:\nstore sub exists x in t\n");
+    assert!(
+        err.contains("STORE SUB EXISTS needs a number variable: t"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_toggle_stmt() {
+    let src = "data:
+flag is number
+procedure:
+toggle flag
+display flag
+toggle flag
+display flag
+store -7.5 in flag
+toggle flag
+display flag
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_FLAG = VAR_FLAG == 0 ? 1 : 0;");
+    assert_eq!("100", run!("toggle", src));
+
+    let err = compile_err!(
+        "data:
+t is text
+procedure:
+toggle t
+"
+    );
+    assert!(err.contains("TOGGLE expects a number variable"), "{}", err);
+}
+
+#[test]
+fn test_build_keep_cpp() {
+    let dir = env::temp_dir().join(format!("ldpl-test-{}-keep-cpp", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let bin = dir.join("hello").to_string_lossy().to_string();
+
+    let compiler = compiler::compile("procedure:\ndisplay \"hi\"\n").unwrap();
+    assert_eq!(
+        bin,
+        compiler
+            .build("hello.ldpl", Some(bin.clone()), true)
+            .unwrap()
+    );
+    let cpp = fs::read_to_string(format!("{}.cpp", bin)).unwrap();
+    assert_eq!(compiler.to_string(), cpp);
+    assert_eq!(b"hi", &Command::new(&bin).output().unwrap().stdout[..]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_get_sorted_keys_stmt() {
+    let src = "data:
+ages is number map
+names is text map
+keys is text list
+k is text
+procedure:
+store 1 in ages:10
+store 2 in ages:9
+store 3 in ages:100
+store 4 in ages:-1.5
+store \"x\" in names:\"pear\"
+store \"y\" in names:\"Apple\"
+store \"z\" in names:\"apple\"
+store \"w\" in names:2
+store \"v\" in names:10
+store \"u\" in names:9
+store \"t\" in names:\"b\"
+get sorted keys of ages in keys
+for each k in keys do
+    display k \" \"
+repeat
+display lf
+get sorted keys of names in keys
+for each k in keys do
+    display k \" \"
+repeat
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "get_sorted_indices(VAR_KEYS, VAR_AGES, true);");
+    assert_emits!(cpp, "get_sorted_indices(VAR_KEYS, VAR_NAMES, false);");
+    // number maps sort numerically, text maps lexicographically
+    assert_eq!(
+        "-1.5 9 10 100 \n10 2 9 Apple apple b pear ",
+        run!("sorted_keys", src)
+    );
+
+    let err = compile_err!(src.replace("sorted keys of ages", "sorted keys of keys"));
+    assert!(
+        err.contains("GET SORTED KEYS expects a map, but keys is a text list"),
+        "{}",
+        err
+    );
+    let err = compile_err!(src.replace("of ages in keys", "of ages in k"));
+    assert!(
+        err.contains("GET SORTED KEYS stores into a text list: k"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_build_with_missing_compiler() {
+    let mut compiler = compiler::compile("procedure:\ndisplay \"hi\"\n").unwrap();
+    compiler
+        .set_compiler("/no/such/c++-compiler".to_string())
+        .unwrap();
+    let dir = env::temp_dir().join(format!("ldpl-test-{}-no-cc", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let bin = dir.join("hello").to_string_lossy().to_string();
+
+    // without -k the source is still kept next to the binary
+    let err = match compiler.build("hello.ldpl", Some(bin.clone()), false) {
+        Ok(_) => panic!("expected a build error"),
+        Err(e) => e.to_string(),
+    };
+    assert!(
+        err.contains("Can't run C++ compiler `/no/such/c++-compiler`"),
+        "{}",
+        err
+    );
+    assert!(
+        err.contains(&format!("Kept C++ source as {}.cpp", bin)),
+        "{}",
+        err
+    );
+    assert!(fs::metadata(format!("{}.cpp", bin)).is_ok());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_collection_equality() {
+    let src = "data:
+a is number list
+b is number list
+m is text map
+n is text map
+procedure:
+push 1 to a
+push 2 to a
+push 1 to b
+if a equals b then
+    display \"same \"
+else
+    display \"different \"
+end if
+push 2 to b
+if a is equal to b then
+    display \"same \"
+end if
+store \"x\" in m:\"k\"
+store \"x\" in n:\"j\"
+if m is not equal to n then
+    display \"different\"
+end if
+";
+    let cpp = compile!(src);
+    assert_emits!(
+        cpp,
+        "if (VAR_A.inner_collection == VAR_B.inner_collection) {"
+    );
+    assert_emits!(
+        cpp,
+        "if (VAR_M.inner_collection != VAR_N.inner_collection) {"
+    );
+    assert_eq!("different same different", run!("collection_eq", src));
+
+    let err = compile_err!(
+        "data:
+a is number list
+b is text list
+procedure:
+if a is equal to b then
+end if
+"
+    );
+    assert!(err.contains("Can't compare a with b"), "{}", err);
+
+    let err = match compiler::compile(
+        "data:
+a is number list
+procedure:
+if a is less than a then
+end if
+",
+    ) {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert!(
+        err.to_string().contains("only be tested for equality"),
+        "{}",
+        err
+    );
+    assert_eq!((4, 4), (err.line, err.col));
+}
+
+#[test]
+fn test_opt_level() {
+    let mut compiler = compiler::compile("procedure:\ndisplay \"hi\"\n").unwrap();
+    for level in &["0", "1", "2", "3", "s"] {
+        assert!(compiler.set_opt_level(level).is_ok());
+    }
+    let err = compiler.set_opt_level("fast").unwrap_err().to_string();
+    assert!(err.contains("Invalid optimization level -Ofast"), "{}", err);
+    assert_eq!(Some("s".to_string()), compiler.opt_level);
+}
+
+#[test]
+fn test_indent() {
+    let src = "data:
+n is number
+procedure:
+if n is equal to 0 then
+    display \"zero\" lf
+end if
+";
+    // leading whitespace of the lines we generated for `src`
+    fn indents(cpp: &str) -> Vec<String> {
+        cpp.lines()
+            .filter(|l| l.contains("VAR_N == 0") || l.contains("\"zero\""))
+            .map(|l| l[..l.len() - l.trim_start().len()].to_string())
+            .collect()
+    }
+
+    let cpp = compile!(src);
+    for indent in indents(&cpp) {
+        assert!(indent.len() >= 4 && indent.len() % 4 == 0, "{:?}", indent);
+        assert!(indent.chars().all(|c| c == ' '), "{:?}", indent);
+    }
+
+    let mut compiler = compiler::new();
+    compiler.set_indent("\t");
+    compiler.compile(src).unwrap();
+    let tabs = indents(&compiler.to_string());
+    assert_eq!(2, tabs.len());
+    for indent in tabs {
+        assert!(!indent.is_empty(), "{:?}", indent);
+        assert!(indent.chars().all(|c| c == '\t'), "{:?}", indent);
+    }
+}
+
+#[test]
+fn test_else_if_chain() {
+    let src = "data:
+n is number
+procedure:
+for n from 0 to 5 step 1 do
+    if n is equal to 0 then
+        display \"zero\" lf
+    else if n is equal to 1 then
+        display \"one\" lf
+    else if n is equal to 2 then
+        if n is greater than 1 then
+            display \"two\" lf
+        else if n is equal to 9 then
+            display \"nine\" lf
+        end if
+    else if n is equal to 3 then
+        display \"three\" lf
+    else
+        display \"many\" lf
+    end if
+repeat
+";
+    let cpp = compile!(src);
+    let main = &cpp[cpp.find("int main(").unwrap()..];
+    let lines: Vec<_> = main
+        .lines()
+        .map(str::trim)
+        .filter(|l| l.starts_with("if") || l.starts_with('}'))
+        .collect();
+    assert_eq!(
+        vec![
+            "if (VAR_N == 0) {",
+            "} else if (VAR_N == 1) {",
+            "} else if (VAR_N == 2) {",
+            "if (VAR_N > 1) {",
+            "} else if (VAR_N == 9) {",
+            "}",
+            "} else if (VAR_N == 3) {",
+            "} else {",
+            "}",
+            "}",
+            "}",
+        ],
+        lines
+    );
+    assert_eq!("zero\none\ntwo\nthree\nmany\n", run!("else-if-chain", src));
+
+    for bad in ["else\nelse", "else\nelse if n is equal to 1 then"] {
+        let err = compile_err!(format!(
+            "data:\nn is number\nprocedure:\nif n is equal to 0 then\n{}\nend if\n",
+            bad
+        ));
+        assert!(
+            err.contains("Nothing can come after ELSE but END IF"),
+            "{}",
+            err
+        );
+    }
+}
+
+#[test]
+fn test_store_quote_escapes() {
+    let src = "data:
+t is text
+procedure:
+store quote in t
+C:\\path\\to \"file\"
+\ttabbed\\n
+end quote
+display t \"|\"
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, r#"VAR_T = "C:\\path\\to \"file\"\n\ttabbed\\n";"#);
+    assert_eq!(
+        "C:\\path\\to \"file\"\n\ttabbed\\n|",
+        run!("quote-escapes", src)
+    );
+
+    // sub names end up in C++ strings too
+    let mut compiler = compiler::new();
+    compiler.set_profile(true);
+    compiler
+        .compile("procedure:\nsub back\\slash\nend sub\n")
+        .unwrap();
+    assert_emits!(
+        compiler.to_string(),
+        r#"ldpl_profile_counts["back\\slash"]++;"#
+    );
+}
+
+#[test]
+fn test_profile() {
+    let src = "data:
+i is number
+procedure:
+sub ping
+end sub
+sub pong
+call ping
+end sub
+for i from 0 to 3 step 1 do
+    call pong
+repeat
+";
+    let cpp = compile!(src);
+    assert!(!cpp.contains("ldpl_profile_counts"), "{}", cpp);
+
+    let mut compiler = compiler::new();
+    compiler.set_profile(true);
+    compiler.compile(src).unwrap();
+    let cpp = compiler.to_string();
+    assert_emits!(cpp, "ldpl_profile_counts[\"ping\"]++;");
+    assert_emits!(cpp, "ldpl_profile_counts[\"pong\"]++;");
+    assert_eq!(2, cpp.matches("]++;").count());
+
+    let dir = env::temp_dir().join(format!("ldpl-test-{}-profile", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (cpp_path, bin) = (dir.join("main.cpp"), dir.join("main-bin"));
+    fs::write(&cpp_path, cpp).unwrap();
+    let status = Command::new("c++")
+        .arg(&cpp_path)
+        .args(["-std=gnu++11", "-w", "-o"])
+        .arg(&bin)
+        .status()
+        .unwrap();
+    assert!(status.success(), "C++ build failed");
+    let out = Command::new(&bin).output().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(
+        "-- sub-procedure calls --\n3\tping\n3\tpong\n",
+        String::from_utf8(out.stderr).unwrap()
+    );
+}
+
+#[test]
+fn test_debug_lines() {
+    let dir = env::temp_dir().join(format!("ldpl-test-{}-debug-lines", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("bad.ldpl");
+    fs::write(
+        &path,
+        "procedure:\ndisplay \"hi\"\ncall external no-such-thing with 1\n\
+         call external no-such-thing with \"x\"\n",
+    )
+    .unwrap();
+    let path = path.to_string_lossy().to_string();
+
+    let cpp = compiler::load_and_compile(&path).unwrap().to_string();
+    assert!(!cpp.contains("#line"), "{}", cpp);
+
+    let mut compiler = compiler::new();
+    compiler.set_debug_lines(true);
+    compiler.load_and_compile(&path).unwrap();
+    let cpp = compiler.to_string();
+    assert_emits!(cpp, format!("#line 2 \"{}\"", path));
+    assert_emits!(cpp, format!("#line 4 \"{}\"", path));
+
+    // the C++ compiler blames the LDPL file
+    let cpp_path = dir.join("main.cpp");
+    fs::write(&cpp_path, cpp).unwrap();
+    let out = Command::new("c++")
+        .arg(&cpp_path)
+        .args(["-std=gnu++11", "-w", "-fsyntax-only"])
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains(&format!("{}:4:", path)), "{}", stderr);
+}
+
+#[test]
+fn test_compile_with_base() {
+    let dir = env::temp_dir().join(format!("ldpl-test-{}-with-base", std::process::id()));
+    fs::create_dir_all(dir.join("lib")).unwrap();
+    fs::write(
+        dir.join("lib/greet.ldpl"),
+        "procedure:\nsub greet\ndisplay \"hi\"\nend sub\n",
+    )
+    .unwrap();
+    let src = "include \"lib/greet.ldpl\"\nextension \"ext.cpp\"\nprocedure:\ncall greet\n";
+
+    let mut compiler = compiler::new();
+    compiler.compile_with_base(src, &dir).unwrap();
+    assert_emits!(compiler.to_string(), "SUBPR_GREET();");
+    // extensions don't have to exist until build()
+    assert_eq!(
+        vec![dir.join("ext.cpp").to_string_lossy().to_string()],
+        compiler.exts
+    );
+
+    // without a base, the INCLUDE is relative to the working directory
+    let err = compile_err!(src);
+    assert!(err.contains("lib/greet.ldpl"), "{}", err);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_build_parallel_exts() {
+    let dir = env::temp_dir().join(format!("ldpl-test-{}-parallel-exts", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (hello, world, broken) = (
+        dir.join("hello.cpp"),
+        dir.join("world.cpp"),
+        dir.join("broken.cpp"),
+    );
+    fs::write(
+        &hello,
+        "#include <iostream>\nvoid HELLO() { std::cout << \"hello \"; }\n",
+    )
+    .unwrap();
+    fs::write(
+        &world,
+        "#include <iostream>\nvoid WORLD() { std::cout << \"world\"; }\n",
+    )
+    .unwrap();
+    fs::write(&broken, "void BROKEN() { oops }\n").unwrap();
+    let src = dir.join("main.ldpl");
+    let src = src.to_str().unwrap();
+    let bin = dir.join("main-bin").to_string_lossy().to_string();
+
+    for jobs in [0, 1, 2] {
+        let mut compiler = compiler::new();
+        compiler.set_jobs(jobs);
+        compiler
+            .add_extension(hello.to_string_lossy().to_string())
+            .unwrap();
+        compiler
+            .add_extension(world.to_string_lossy().to_string())
+            .unwrap();
+        compiler
+            .compile("procedure:\ncall external hello\ncall external world\n")
+            .unwrap();
+        compiler.build(src, Some(bin.clone()), true).unwrap();
+        let out = Command::new(&bin).output().unwrap();
+        assert_eq!("hello world", String::from_utf8(out.stdout).unwrap());
+        // only the program and its source are left behind
+        assert_eq!(5, fs::read_dir(&dir).unwrap().count());
+    }
+
+    let mut compiler = compiler::new();
+    compiler
+        .add_extension(hello.to_string_lossy().to_string())
+        .unwrap();
+    compiler
+        .add_extension(broken.to_string_lossy().to_string())
+        .unwrap();
+    compiler
+        .compile("procedure:\ncall external hello\n")
+        .unwrap();
+    let err = compiler
+        .build(src, Some(bin), true)
+        .unwrap_err()
+        .to_string();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(
+        err.contains("C++ Error compiling ") && err.contains("broken.cpp"),
+        "{}",
+        err
+    );
+    assert!(!err.contains("hello.cpp"), "{}", err);
+}
+
+#[test]
+fn test_extension_dir() {
+    let dir = env::temp_dir().join(format!("ldpl-test-{}-ext-dir", std::process::id()));
+    fs::create_dir_all(dir.join("more")).unwrap();
+    let cpp = |name: &str, text: &str| {
+        fs::write(
+            dir.join(name),
+            format!(
+                "#include <iostream>\nvoid {}() {{ std::cout << \"{}\"; }}\n",
+                name.trim_end_matches(".cpp")
+                    .replace("more/", "")
+                    .to_uppercase(),
+                text
+            ),
+        )
+        .unwrap();
+    };
+    cpp("world.cpp", "world");
+    cpp("hello.cpp", "hello ");
+    cpp("more/bang.cpp", "!");
+    fs::write(dir.join("notes.txt"), "not an extension").unwrap();
+    let path = |f: &str| dir.join(f).to_string_lossy().to_string();
+
+    let mut compiler = compiler::new();
+    compiler
+        .add_extension_dir(dir.to_str().unwrap(), false)
+        .unwrap();
+    assert_eq!(vec![path("hello.cpp"), path("world.cpp")], compiler.exts);
+
+    let mut compiler = compiler::new();
+    compiler
+        .add_extension_dir(dir.to_str().unwrap(), true)
+        .unwrap();
+    assert_eq!(
+        vec![path("hello.cpp"), path("more/bang.cpp"), path("world.cpp")],
+        compiler.exts
+    );
+    compiler
+        .compile("procedure:\ncall external hello\ncall external world\ncall external bang\n")
+        .unwrap();
+    let bin = path("main-bin");
+    compiler
+        .build(&path("main.ldpl"), Some(bin.clone()), true)
+        .unwrap();
+    let out = Command::new(&bin).output().unwrap();
+    assert_eq!("hello world!", String::from_utf8(out.stdout).unwrap());
+
+    let err = compiler::new()
+        .add_extension_dir(&path("missing"), false)
+        .unwrap_err()
+        .to_string();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(err.contains("Can't read extension directory"), "{}", err);
+}
+
+#[test]
+fn test_include_cycle() {
+    let dir = env::temp_dir().join(format!("ldpl-test-{}-include-cycle", std::process::id()));
+    fs::create_dir_all(dir.join("lib")).unwrap();
+    fs::write(dir.join("a.ldpl"), "include \"lib/b.ldpl\"\nprocedure:\n").unwrap();
+    fs::write(dir.join("lib/b.ldpl"), "\ninclude \"../a.ldpl\"\n").unwrap();
+    fs::write(dir.join("self.ldpl"), "include \"self.ldpl\"\n").unwrap();
+    let path = |f: &str| dir.join(f).to_string_lossy().to_string();
+
+    let err = match compiler::load_and_compile(&path("a.ldpl")) {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert_eq!(
+        format!(
+            "Error: Include cycle: {} -> {} -> {}",
+            path("a.ldpl"),
+            path("lib/b.ldpl"),
+            path("lib/../a.ldpl")
+        ),
+        err.to_string()
+    );
+    assert_eq!(Some(path("lib/b.ldpl")), err.file);
+    assert_eq!((2, 9), (err.line, err.col));
+
+    let err = match compiler::load_and_compile(&path("self.ldpl")) {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(
+        format!("Error: Include cycle: {0} -> {0}", path("self.ldpl")),
+        err.to_string()
+    );
+}
+
+#[test]
+fn test_compile_files() {
+    let files = "--- main.ldpl
+include \"lib/greet.ldpl\"
+data:
+name is text
+procedure:
+store \"virtual\" in name
+call greet with name
+--- lib/greet.ldpl
+include \"../shared.ldpl\"
+procedure:
+sub greet
+parameters:
+who is text
+procedure:
+display prefix who lf
+end sub
+--- shared.ldpl
+data:
+prefix is text
+procedure:
+store \"hello \" in prefix
+";
+    let mut compiler = compiler::new();
+    compiler.compile_files(files).unwrap();
+    assert_eq!(
+        vec!["lib/greet.ldpl", "lib/../shared.ldpl"],
+        compiler.includes
+    );
+    let dir = env::temp_dir().join(format!("ldpl-test-{}-compile-files", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let bin = dir.join("main-bin").to_string_lossy().to_string();
+    compiler
+        .build(
+            &dir.join("main.ldpl").to_string_lossy(),
+            Some(bin.clone()),
+            true,
+        )
+        .unwrap();
+    let out = Command::new(&bin).output().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!("hello virtual\n", String::from_utf8(out.stdout).unwrap());
+
+    // through -c, as an editor would
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ldpl-rs"))
+        .args(["-r", "-c"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    {
+        use std::io::Write;
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(files.as_bytes())
+            .unwrap();
+    }
+    let out = child.wait_with_output().unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let cpp = String::from_utf8(out.stdout).unwrap();
+    assert_emits!(cpp, "VAR_PREFIX = \"hello \";");
+    assert_emits!(cpp, "SUBPR_GREET(VAR_NAME);");
+
+    // errors point at the virtual file
+    let mut compiler = compiler::new();
+    let err = compiler
+        .compile_files("--- main.ldpl\ninclude \"b.ldpl\"\n--- b.ldpl\nprocedure:\nnope\n")
+        .unwrap_err();
+    assert_eq!(Some("b.ldpl"), err.file.as_deref());
+
+    let err = compiler::new()
+        .compile_files("--- a.ldpl\ninclude \"a.ldpl\"\n")
+        .unwrap_err();
+    assert!(err.to_string().contains("Include cycle"), "{}", err);
+    let err = compiler::new()
+        .compile_files(&format!(
+            "--- {}cyc/cyc.ldpl\nusing package cyc\n",
+            compiler::lpm_location()
+        ))
+        .unwrap_err();
+    assert!(err.to_string().contains("Include cycle"), "{}", err);
+    assert_eq!((1, 15), (err.line, err.col));
+    let err = compiler::new()
+        .compile_files("--- a.ldpl\n--- ./a.ldpl\n")
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("File given twice: ./a.ldpl"),
+        "{}",
+        err
+    );
+    let err = compiler::new()
+        .compile_files("procedure:\n--- a.ldpl\n")
+        .unwrap_err();
+    assert!(err.to_string().contains("Expected `--- <file>`"), "{}", err);
+}
+
+#[test]
+fn test_print_search_paths() {
+    let dir = env::temp_dir().join(format!("ldpl-test-{}-search-paths", std::process::id()));
+    fs::create_dir_all(dir.join("src")).unwrap();
+    let out = Command::new(env!("CARGO_BIN_EXE_ldpl-rs"))
+        .current_dir(&dir)
+        .env_remove("CXX")
+        .args(["--print-search-paths", "-C=my-c++", "-O2", "src/main.ldpl"])
+        .output()
+        .unwrap();
+    let root = fs::canonicalize(&dir).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let out = String::from_utf8(out.stdout).unwrap();
+    assert!(
+        out.contains(&format!("INCLUDE:      {}/src", root.display())),
+        "{}",
+        out
+    );
+    assert!(
+        out.contains(&format!("USING:        {}", compiler::lpm_location())),
+        "{}",
+        out
+    );
+    assert!(!out.contains('~'), "{}", out);
+    assert!(out.contains("C++ compiler: my-c++\n"), "{}", out);
+    assert!(
+        out.contains("C++ flags:    -std=gnu++11 -w -O2\n"),
+        "{}",
+        out
+    );
+
+    // no source file needed, and the default compiler is c++
+    let out = Command::new(env!("CARGO_BIN_EXE_ldpl-rs"))
+        .env_remove("CXX")
+        .arg("--print-search-paths")
+        .output()
+        .unwrap();
+    let out = String::from_utf8(out.stdout).unwrap();
+    assert!(out.contains("C++ compiler: c++\n"), "{}", out);
+}
+
+#[test]
+fn test_deps_command() {
+    let dir = env::temp_dir().join(format!("ldpl-test-{}-deps", std::process::id()));
+    fs::create_dir_all(dir.join("lib")).unwrap();
+    fs::write(
+        dir.join("main.ldpl"),
+        "include \"lib/a.ldpl\"\nprocedure:\ncall a\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("lib/a.ldpl"),
+        "include \"b.ldpl\"\nprocedure:\nsub a\ncall b\nend sub\n",
+    )
+    .unwrap();
+    fs::write(dir.join("lib/b.ldpl"), "procedure:\nsub b\nend sub\n").unwrap();
+    fs::write(dir.join("pre.ldpl"), "data:\nx is number\n").unwrap();
+
+    let out = Command::new(env!("CARGO_BIN_EXE_ldpl-rs"))
+        .current_dir(&dir)
+        .args(["deps", "-i=pre.ldpl", "main.ldpl"])
+        .output()
+        .unwrap();
+    let root = fs::canonicalize(&dir).unwrap();
+    let want: Vec<_> = ["pre.ldpl", "main.ldpl", "lib/a.ldpl", "lib/b.ldpl"]
+        .iter()
+        .map(|f| root.join(f).to_string_lossy().to_string())
+        .collect();
+    let files: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|f| f.unwrap().file_name())
+        .collect();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert_eq!(
+        want.join("\n") + "\n",
+        String::from_utf8(out.stdout).unwrap()
+    );
+    // nothing gets built
+    assert_eq!(3, files.len(), "{:?}", files);
+}
+
+#[test]
+fn test_manifest() {
+    let dir = env::temp_dir().join(format!("ldpl-test-{}-manifest", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("lib.ldpl"), "data:\nlib-count is number\n").unwrap();
+    let src = "include \"lib.ldpl\"
+extension \"ext.cpp\"
+data:
+names is text list
+procedure:
+sub greet
+parameters:
+name is text
+times is number
+procedure:
+end sub
+create statement \"GREET $ $ TIMES\" executing greet
+";
+    let mut compiler = compiler::new();
+    compiler.compile_with_base(src, &dir).unwrap();
+    let lib = dir.join("lib.ldpl").to_string_lossy().to_string();
+    let ext = dir.join("ext.cpp").to_string_lossy().to_string();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(
+        format!(
+            r#"{{
+  "globals": {{
+    "ARGV": "text list",
+    "ERRORCODE": "number",
+    "ERRORTEXT": "text",
+    "LIB-COUNT": "number",
+    "NAMES": "text list"
+  }},
+  "subs": {{
+    "GREET": ["text", "number"]
+  }},
+  "includes": [
+    "{}"
+  ],
+  "extensions": [
+    "{}"
+  ],
+  "statements": {{
+    "GREET $ $ TIMES": ["GREET"]
+  }}
+}}
+"#,
+            lib, ext
+        ),
+        compiler.manifest()
+    );
+
+    assert_eq!(
+        "{\n  \"globals\": {\n    \"ARGV\": \"text list\",\n    \"ERRORCODE\": \"number\",\n    \
+         \"ERRORTEXT\": \"text\"\n  },\n  \"subs\": {},\n  \"includes\": [],\n  \
+         \"extensions\": [],\n  \"statements\": {}\n}\n",
+        compiler::compile("procedure:\n").unwrap().manifest()
+    );
+}
+
+#[test]
+fn test_copy_is_deep() {
+    let src = "data:
+a is number list
+b is number list
+m is text map
+n is text map
+procedure:
+push 1 to a
+copy a to b
+store 2 in b:0
+push 3 to b
+store \"x\" in m:\"k\"
+copy m to n
+store \"y\" in n:\"k\"
+display a:0 \" \" b:0 \" \" m:\"k\" \" \" n:\"k\"
+";
+    assert_emits!(
+        compile!(src),
+        "VAR_B.inner_collection = VAR_A.inner_collection;"
+    );
+    assert_eq!("1 2 x y", run!("copy", src));
+
+    // LDPL can't declare nested collections yet, so check the runtime
+    // directly: copying a list of lists must not share the inner lists.
+    let dir = env::temp_dir().join(format!("ldpl-test-{}-deep-copy", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (cpp, bin) = (dir.join("main.cpp"), dir.join("main-bin"));
+    fs::write(
+        &cpp,
+        format!(
+            r#"#include "{}/lib/ldpl_header.cpp"
+int main() {{
+    ldpl_list<ldpl_list<ldpl_number>> a, b;
+    ldpl_map<ldpl_list<ldpl_number>> m, n;
+    a.inner_collection.push_back(ldpl_list<ldpl_number>());
+    a[0].inner_collection.push_back(1);
+    b.inner_collection = a.inner_collection;
+    b[0][0] = 2;
+    b[0].inner_collection.push_back(3);
+    m["k"].inner_collection.push_back(1);
+    n.inner_collection = m.inner_collection;
+    n["k"][0] = 2;
+    cout << a[0][0] << a[0].inner_collection.size() << m["k"][0];
+}}
+"#,
+            env!("CARGO_MANIFEST_DIR")
+        ),
+    )
+    .unwrap();
+    let status = Command::new("c++")
+        .arg(&cpp)
+        .args(["-std=gnu++11", "-w", "-o"])
+        .arg(&bin)
+        .status()
+        .unwrap();
+    assert!(status.success(), "C++ build failed");
+    let out = Command::new(&bin).output().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!("111", String::from_utf8(out.stdout).unwrap());
+}
+
+#[test]
+fn test_error_locations() {
+    let err = match compiler::compile("data:\nx is number\nx is text\nprocedure:\n") {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert_eq!(
+        "Error: Duplicate declaration for variable: x",
+        err.to_string()
+    );
+    assert_eq!(Some("<stdin>:3:1".to_string()), err.location());
+    assert_eq!(1, err.len);
+
+    let err = match compiler::compile("data:\nn is number\nprocedure:\nnegate n in nope\n") {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert_eq!("Error: No type found for nope", err.to_string());
+    assert_eq!((4, 13, 4), (err.line, err.col, err.len));
+
+    let err = match compiler::compile("procedure:\ndisplay 1\n   frobnicate the widget\n") {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert!(err.to_string().contains("Unknown statement"), "{}", err);
+    assert_eq!((3, 4), (err.line, err.col));
+
+    // errors from files know their path
+    let dir = env::temp_dir().join(format!("ldpl-test-{}-error-file", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("bad.ldpl").to_string_lossy().to_string();
+    fs::write(&path, "procedure:\nreturn\n").unwrap();
+    let err = match compiler::load_and_compile(&path) {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(Some(format!("{}:2:1", path)), err.location());
+}
+
+#[test]
+fn test_memoize_sub() {
+    let src = "memoize sub square
+MEMOIZE SUB-PROCEDURE fib
+data:
+runs is number
+x is number
+r is number
+procedure:
+sub square
+    parameters:
+    n is number
+    out is number
+    procedure:
+    in runs solve runs + 1
+    in out solve n * n
+end sub
+sub fib
+    parameters:
+    n is number
+    out is number
+    local data:
+    a is number
+    b is number
+    m is number
+    procedure:
+    if n is less than 2 then
+        store n in out
+        return
+    end if
+    in m solve n - 1
+    call fib with m a
+    in m solve n - 2
+    call fib with m b
+    in out solve a + b
+    in runs solve runs + 1
+end sub
+call square with 3 r
+display r \" \"
+store 0 in r
+call square with 4 r
+store 0 in r
+call square with 3 r
+display r \" \" runs \" \"
+# the incoming value of `out` is part of the key too
+call square with 3 r
+store 0 in runs
+call fib with 60 r
+display r \" \" runs
+";
+    let cpp = compile!(src);
+    assert_emits!(
+        cpp,
+        "void SUBPR_SQUARE_memo_body(ldpl_number& VAR_N, ldpl_number& VAR_OUT) {"
+    );
+    assert_emits!(
+        cpp,
+        "static unordered_map<string, tuple<ldpl_number, ldpl_number>> cache;"
+    );
+    assert_emits!(cpp, "tie(VAR_N, VAR_OUT) = hit->second;");
+    // 2 distinct argument lists for square, 59 non-trivial fibs
+    assert_eq!("9 9 2 1548008755920 59", run!("memoize", src));
+
+    let err = compile_err!(
+        "memoize sub total
+procedure:
+sub total
+    parameters:
+    nums is number list
+end sub
+"
+    );
+    assert!(
+        err.contains("MEMOIZE only works with number and text"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_declare_sub() {
+    let src = "declare sub add-to with number text list
+data:
+n is number
+name is text
+names is text list
+procedure:
+sub greet
+call add-to with 1 names
+end sub
+sub add-to
+parameters:
+x is number
+list is text list
+procedure:
+push \"hi\" to list
+end sub
+call greet
+call add-to with n names
+for each name in names do
+    display name \" \"
+repeat
+";
+    let cpp = compile!(src);
+    assert_emits!(
+        cpp,
+        "void SUBPR_ADDc45_TO(ldpl_number&, ldpl_list<chText>&);"
+    );
+    assert_eq!("hi hi ", run!("declare", src));
+
+    // forward calls are type checked against the declaration
+    let err = compile_err!(src.replace("with 1 names", "with names"));
+    assert!(
+        err.contains("SUB add-to expects 2 arguments, got 1"),
+        "{}",
+        err
+    );
+
+    let err = match compiler::compile(&src.replace("list is text list", "list is number list")) {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert_eq!(
+        "Error: SUB-PROCEDURE add-to doesn't match its DECLARE: \
+         declared with (number, text list), defined with (number, number list)",
+        err.to_string()
+    );
+    assert_eq!((10, 5), (err.line, err.col));
+
+    let err = match compiler::compile("declare sub nope with number\nprocedure:\n") {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert_eq!(
+        "Error: DECLAREd sub-procedure never defined: nope",
+        err.to_string()
+    );
+    assert_eq!((1, 13), (err.line, err.col));
+
+    // nested types, and the legacy VECTOR spelling
+    let cpp = compile!(
+        "declare sub tally with number list list text vector
+procedure:
+sub tally
+parameters:
+rows is number list list
+counts is text map
+end sub
+"
+    );
+    assert_emits!(
+        cpp,
+        "void SUBPR_TALLY(ldpl_list<ldpl_list<ldpl_number>>&, ldpl_map<chText>&);"
+    );
+
+    let err = match compiler::compile("declare sub nope with number lisst\nprocedure:\n") {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert!(
+        err.to_string().contains("Unknown type 'number lisst'"),
+        "{}",
+        err
+    );
+    assert_eq!((1, 23), (err.line, err.col));
+}
+
+#[test]
+fn test_external_sub_types() {
+    let sub = "external sub add-one
+parameters:
+x is number
+procedure:
+in x solve x + 1
+end sub
+";
+    let src = format!(
+        "data:\nn is number\nprocedure:\n{}call external add-one with n\ndisplay n\n",
+        sub
+    );
+    assert_emits!(compile!(src), "void ADD_ONE(ldpl_number& VAR_X) {");
+    let err = compile_err!(src.replace("with n", "with \"one\""));
+    assert!(
+        err.contains("argument 1 to add-one: expected number, got text"),
+        "{}",
+        err
+    );
+
+    // calls made before the definition are checked when it shows up
+    let err = match compiler::compile(&format!(
+        "data:\nn is number\nprocedure:\nsub first\ncall external add-one with n n\nend sub\n{}",
+        sub
+    )) {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert_eq!(
+        "Error: SUB add-one expects 1 arguments, got 2",
+        err.to_string()
+    );
+    assert_eq!((5, 15), (err.line, err.col));
+
+    // externals from C++ extensions can be declared
+    let src = "declare external sub ext-push with text text list
+data:
+names is text list
+procedure:
+call external ext-push with \"hi\" names
+call external unknown with names
+";
+    assert_emits!(compile!(src), "void EXT_PUSH(chText&, ldpl_list<chText>&);");
+    let err = compile_err!(src.replace("with \"hi\" names", "with names"));
+    assert!(
+        err.contains("SUB ext-push expects 2 arguments, got 1"),
+        "{}",
+        err
+    );
+    let err = compile_err!(src.replace(
+        "procedure:\n",
+        "procedure:\nexternal sub ext-push\nend sub\n"
+    ));
+    assert!(
+        err.contains(
+            "EXTERNAL SUB-PROCEDURE ext-push doesn't match its DECLARE: \
+             declared with (text, text list), defined with ()"
+        ),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_enum() {
+    let src = "enum color is red green blue
+ENUM size IS small large
+data:
+c is number
+procedure:
+store blue in c
+display c \" \" large
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "ldpl_number VAR_RED = 0;");
+    assert_emits!(cpp, "ldpl_number VAR_GREEN = 1;");
+    assert_emits!(cpp, "ldpl_number VAR_BLUE = 2;");
+    assert_emits!(cpp, "ldpl_number VAR_SMALL = 0;");
+    assert_emits!(cpp, "ldpl_number VAR_LARGE = 1;");
+    assert_eq!("2 1", run!("enum", src));
+
+    let err = compile_err!("enum color is red green red\nprocedure:\n");
+    assert!(
+        err.contains("Duplicate declaration for variable: red"),
+        "{}",
+        err
+    );
+    let err = compile_err!("enum color is red\ndata:\nred is text\nprocedure:\n");
+    assert!(
+        err.contains("Duplicate declaration for variable: red"),
+        "{}",
+        err
+    );
+    let err = compile_err!("enum color is argv\nprocedure:\n");
+    assert!(
+        err.contains("Duplicate declaration for variable: argv"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_user_stmt_candidates() {
+    let src = "data:
+n is number
+t is text
+procedure:
+sub add-nums
+parameters:
+a is number
+b is number
+procedure:
+end sub
+create statement \"ADD $ TO $\" executing add-nums
+sub add-texts
+parameters:
+a is text
+b is text
+procedure:
+end sub
+create statement \"ADD $ TO $\" executing add-texts
+sub show-one
+parameters:
+a is number
+procedure:
+end sub
+create statement \"SHOW $ NOW\" executing show-one
+sub show-two
+parameters:
+a is text
+b is number
+procedure:
+end sub
+create statement \"SHOW $ $\" executing show-two
+";
+
+    let err = match compiler::compile(&format!("{}add n to t\n", src)) {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert_eq!(
+        "Error: Statement arguments didn't match any sub-procedures: ADD N TO T; \
+         got (number, text); candidates: ADD-NUMS(number, number), ADD-TEXTS(text, text)",
+        err.to_string()
+    );
+    assert_eq!((32, 1), (err.line, err.col));
+
+    // a pattern that fits the words but not the types shouldn't stop
+    // us from trying the others
+    let cpp = compile!(format!("{}add t to t\nshow n now\nshow t n\n", src));
+    assert_emits!(cpp, "SUBPR_ADDc45_TEXTS(VAR_T, VAR_T);");
+    assert_emits!(cpp, "SUBPR_SHOWc45_ONE(VAR_N);");
+    assert_emits!(cpp, "SUBPR_SHOWc45_TWO(VAR_T, VAR_N);");
+
+    let err = compile_err!(format!("{}show t nope\n", src));
+    assert!(err.contains("nope"), "{}", err);
+
+    // only the overloads whose words fit are listed
+    let err = compile_err!(format!("{}show n n\n", src));
+    assert!(
+        err.ends_with("got (number, number); candidates: SHOW-TWO(text, number)"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_user_stmt_collection_args() {
+    let src = "data:
+ages is number map
+names is text list
+procedure:
+sub show-age
+parameters:
+age is number
+procedure:
+display age lf
+end sub
+create statement \"SHOW AGE $\" executing show-age
+sub show-all
+parameters:
+list is text list
+local data:
+name is text
+procedure:
+for each name in list do
+    display name lf
+repeat
+end sub
+create statement \"SHOW ALL $\" executing show-all
+store 41 in ages:\"Jo Ann\"
+push \"Bo\" to names
+show age ages:\"Jo Ann\"
+show all names
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "SUBPR_SHOWc45_AGE(VAR_AGES[\"Jo Ann\"]);");
+    assert_emits!(cpp, "SUBPR_SHOWc45_ALL(VAR_NAMES);");
+    assert_eq!("41\nBo\n", run!("user-stmt-args", src));
+}
+
+#[test]
+fn test_call_arg_checks() {
+    let sub = "sub foo
+parameters:
+a is number
+b is text
+c is number list
+procedure:
+end sub
+";
+    let data = "data:
+n is number
+t is text
+ns is number list
+procedure:
+";
+
+    let err = match compiler::compile(&format!("{}{}call foo with n t\n", data, sub)) {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert_eq!("Error: SUB foo expects 3 arguments, got 2", err.to_string());
+    assert_eq!((13, 6), (err.line, err.col));
+
+    let err = compile_err!(format!("{}{}call foo with t t ns\n", data, sub));
+    assert!(
+        err.contains("argument 1 to foo: expected number, got text"),
+        "{}",
+        err
+    );
+
+    // calls made before the SUB exists get checked when it's defined
+    let err = match compiler::compile(&format!("{}call foo with 1 \"a\" 2\n{}", data, sub)) {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert_eq!(
+        "Error: argument 3 to foo: expected number list, got number",
+        err.to_string()
+    );
+    assert_eq!((6, 6), (err.line, err.col));
+
+    compile!(format!(
+        "{}call foo with ns:0 \"a\" ns\n{}call foo with 1 t ns\n",
+        data, sub
+    ));
+}
+
+#[test]
+fn test_call_out_args() {
+    let src = "data:
+q is number
+r is number
+out is text
+procedure:
+sub divide
+parameters:
+a is number
+b is number
+quotient is number
+remainder is number
+procedure:
+in quotient solve a / b
+floor quotient
+modulo a by b in remainder
+end sub
+sub greet
+parameters:
+name is text
+procedure:
+display \"hi \" name \" \"
+end sub
+call divide with 17 5 out q r
+display q \" \" r \" \"
+call greet with out
+store \"there\" in out
+call sub-procedure greet out out
+display q \" \" r
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "SUBPR_DIVIDE(LPVAR_0, LPVAR_1, VAR_Q, VAR_R);");
+    assert_emits!(cpp, "SUBPR_GREET(VAR_OUT);");
+    assert_eq!("3 2 hi  hi there 3 2", run!("call-out", src));
+
+    let err = match compiler::compile(&format!(
+        "{}call divide with 1 2 out q 3\n",
+        src.replace("display q \" \" r\n", "")
+    )) {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert_eq!(
+        "Error: OUT arguments must be variables, but 3 is a literal",
+        err.to_string()
+    );
+    assert_eq!((28, 28), (err.line, err.col));
+
+    // OUT arguments are still checked against the PARAMETERS
+    let err = compile_err!(format!("{}call divide with 1 2 out q out\n", src));
+    assert!(
+        err.contains("argument 4 to divide: expected number, got text"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_unreachable_warnings() {
+    let src = "data:
+n is number
+procedure:
+sub early
+return
+display \"never\"
+display \"also never\"
+end sub
+sub jumpy
+goto done
+display \"skipped\"
+label done
+display \"reached\"
+if n is equal to 0 then
+    exit
+else
+    display \"fine\"
+end if
+end sub
+call early
+call jumpy
+";
+    let compiler = compiler::compile(src).unwrap();
+    let warnings: Vec<_> = compiler
+        .warnings
+        .iter()
+        .map(|w| (w.details.as_str(), w.line, w.col))
+        .collect();
+    assert_eq!(
+        vec![
+            ("Unreachable statement after RETURN", 6, 1),
+            ("Unreachable statement after GOTO", 11, 1),
+        ],
+        warnings
+    );
+
+    let mut compiler = compiler::new();
+    compiler.set_strict(true);
+    let err = match compiler.compile(src) {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert_eq!("Error: Unreachable statement after RETURN", err.to_string());
+    assert_eq!(Some("<stdin>:6:1".to_string()), err.location());
+}
+
+#[test]
+fn test_undefined_sub_calls() {
+    let result = compiler::compile(
+        "procedure:
+sub real
+end sub
+call later
+call real
+  call missing with 1
+call Missing
+sub later
+end sub
+call also-missing
+",
+    );
+    let err = match result {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert_eq!(
+        "Error: Sub-procedures called but never defined: \
+         missing (called at 6:8), also-missing (called at 10:6)",
+        err.to_string()
+    );
+    assert_eq!(Some("<stdin>:6:8".to_string()), err.location());
+
+    let err = match compiler::compile("memoize sub nope\nprocedure:\n") {
+        Ok(_) => panic!("expected a compile error"),
+        Err(e) => e,
+    };
+    assert_eq!(
+        "Error: MEMOIZE used with unknown sub-procedure: nope",
+        err.to_string()
+    );
+    assert_eq!(Some("<stdin>:1:13".to_string()), err.location());
+}
+
+#[test]
+fn test_program_dir_stmt() {
+    let src = "data:
+dir is text
+procedure:
+get program directory in dir
+display errorcode \" \" dir
+";
+    assert_emits!(compile!(src), "VAR_DIR = get_program_dir();");
+    let out = run!("program-dir", src);
+    assert!(out.starts_with("0 /"), "{}", out);
+    assert!(
+        out.ends_with(&format!("ldpl-test-{}-program-dir", std::process::id())),
+        "{}",
+        out
+    );
+
+    let err = compile_err!("data:\nn is number\nprocedure:\nget program directory in n\n");
+    assert!(err.contains("stores into text"), "{}", err);
+}
+
+#[test]
+fn test_execute_timeout_stmt() {
+    let src = "data:
+code is number
+procedure:
+execute \"exit 3\" with timeout 5000 and store exit code in code
+display code \" \"
+execute \"sleep 5\" with timeout 50 and store exit code in code
+display code
+";
+    assert_emits!(
+        compile!(src),
+        "VAR_CODE = exec_with_timeout(\"exit 3\", 5000);"
+    );
+    // fork() and kill() are Unix only
+    if cfg!(unix) {
+        assert_eq!("3 -1", run!("execute-timeout", src));
+    }
+
+    let err = compile_err!(src.replace("timeout 50 ", "timeout \"50\" "));
+    assert!(
+        err.contains("number of milliseconds for TIMEOUT"),
+        "{}",
+        err
+    );
+    let err = compile_err!(src.replace("code is number", "code is text"));
+    assert!(err.contains("EXIT CODE is stored in a number"), "{}", err);
+}
+
+#[test]
+fn test_forward_decl_types() {
+    let src = "data:
+n is number
+names is text list
+procedure:
+sub first
+call second with n names
+end sub
+sub second
+parameters:
+x is number
+list is text list
+procedure:
+in x solve x + 1
+push \"hi\" to list
+end sub
+call first
+call first
+display n \" \" names:1
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "void SUBPR_SECOND(ldpl_number&, ldpl_list<chText>&);");
+    assert_emits!(cpp, "void SUBPR_FIRST();");
+    assert!(!cpp.contains("void SUBPR_SECOND();"), "{}", cpp);
+    assert_eq!("2 hi", run!("forward-decl-types", src));
+
+    // externals with no known signature go by the call's arguments
+    let cpp = compile!("data:\nn is number\nprocedure:\ncall external ext-thing with n \"hi\"\n");
+    assert_emits!(cpp, "void EXT_THING(ldpl_number&, chText&);");
+}
+
+#[test]
+fn test_accept_default_stmt() {
+    let src = "data:
+name is text
+n is number
+procedure:
+accept name or default \"nobody\"
+accept n or default 10
+display name \" \" n
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_NAME = input_string_or(\"nobody\");");
+    assert_emits!(cpp, "VAR_N = input_number_or(10);");
+    assert_eq!("bob 3", run!("accept-default", src, "bob\n3\n"));
+    assert_eq!("nobody 10", run!("accept-default-empty", src, "\n\n"));
+    assert_eq!("nobody 10", run!("accept-default-eof", src));
+
+    let err = compile_err!(src.replace("default 10", "default \"10\""));
+    assert!(err.contains("ACCEPT n needs a number DEFAULT"), "{}", err);
+    let err = compile_err!("data:\nl is text list\nprocedure:\naccept l or default \"\"\n");
+    assert!(err.contains("needs a number or text variable"), "{}", err);
+}
+
+#[test]
+fn test_reset() {
+    let mut compiler = compiler::new();
+    compiler.set_indent("\t");
+    compiler.set_strict(true);
+    // fails partway through a SUB
+    assert!(compiler
+        .compile(
+            "extension \"a.cpp\"\ndata:\nx is number\nprocedure:\nsub s\nstore 1 in nope\nend sub\n"
+        )
+        .is_err());
+
+    compiler.reset();
+    assert!(compiler.exts.is_empty());
+    assert!(compiler.strict);
+    let src = "data:\nx is text\nprocedure:\nsub s\nstore \"hi\" in x\nend sub\ncall s\n";
+    compiler.compile(src).unwrap();
+    assert!(compiler.to_string().contains("\tVAR_X = \"hi\";\n"));
+
+    let lines = |cpp: String| {
+        cpp.lines()
+            .map(|l| l.trim().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(lines(compile!(src)), lines(compiler.to_string()));
+}
+
+#[test]
+fn test_system_info_stmts() {
+    let src = "data:
+mem is number
+cpus is number
+procedure:
+get total memory in mem
+display errorcode \" \"
+get cpu count in cpus
+display errorcode \" \"
+if mem is greater than 0 and cpus is greater than 0 then
+    display \"ok\"
+end if
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_MEM = get_total_memory();");
+    assert_emits!(cpp, "VAR_CPUS = get_cpu_count();");
+    assert_eq!("0 0 ok", run!("system-info", src));
+
+    let err = compile_err!("data:\nt is text\nprocedure:\nget total memory in t\n");
+    assert!(
+        err.contains("GET TOTAL MEMORY stores into a number: t"),
+        "{}",
+        err
+    );
+    let err = compile_err!("data:\nt is text list\nprocedure:\nget cpu count in t\n");
+    assert!(
+        err.contains("GET CPU COUNT stores into a number: t"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_indent_per_compiler() {
+    let src = "data:
+n is number
+procedure:
+sub count
+    while n is less than 3 do
+        if n is equal to 1 then
+            display n
+        end if
+        in n solve n + 1
+    repeat
+end sub
+call count
+";
+    let expected = compile!(src);
+    assert!(expected.contains("\n            cout << VAR_N << flush;\n"));
+    let handles: Vec<_> = (0..8)
+        .map(|_| std::thread::spawn(move || compile!(src)))
+        .collect();
+    for handle in handles {
+        assert_eq!(expected, handle.join().unwrap());
+    }
+}
+
+#[test]
+fn test_monotonic_time_stmt() {
+    let src = "data:
+start is number
+stop is number
+procedure:
+get monotonic time in start
+wait 20 milliseconds
+get monotonic time in stop
+in stop solve stop - start
+if stop is greater than 0.015 and stop is less than 5 then
+    display \"ok\"
+end if
+";
+    assert_emits!(compile!(src), "VAR_START = get_monotonic_time();");
+    assert_eq!("ok", run!("monotonic-time", src));
+
+    let err = compile_err!("data:\nt is text\nprocedure:\nget monotonic time in t\n");
+    assert!(
+        err.contains("GET MONOTONIC TIME stores into a number: t"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_join_list_stmt() {
+    let src = "data:
+names is text list
+nums is number list
+empty is text list
+out is text
+procedure:
+push \"a\" to names
+push \"b\" to names
+push \"c\" to names
+join names with \", \" in out
+display out \"|\"
+push 1 to nums
+join nums with \"-\" in out
+display out \"|\"
+push 2.5 to nums
+join nums with 0 in out
+display out \"|\"
+join empty with \",\" in out
+display out \"|\"
+join \"a\" and \"b\" in out
+display out
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_OUT = join_list(VAR_NAMES, \", \");");
+    assert_emits!(cpp, "VAR_OUT = join_list(VAR_NUMS, \"-\");");
+    assert_eq!("a, b, c|1|102.5||ab", run!("join-list", src));
+
+    let err = compile_err!(src.replace("join names with", "join out with"));
+    assert!(
+        err.contains("JOIN expects a list, but out is a text"),
+        "{}",
+        err
+    );
+    let err =
+        compile_err!("data:\nl is text list\nn is number\nprocedure:\njoin l with \",\" in n\n");
+    assert!(err.contains("JOIN stores into text: n"), "{}", err);
+    let err =
+        compile_err!("data:\nl is text list list\nt is text\nprocedure:\njoin l with \",\" in t\n");
+    assert!(
+        err.contains("JOIN expects a number or text list"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_split_keeps_empty_fields() {
+    let src = "data:
+parts is text list
+n is number
+x is text
+procedure:
+split \",a,,b,\" by \",\" in parts
+get length of parts in n
+display n \":\"
+for each x in parts do
+    display \"[\" x \"]\"
+repeat
+split \"\" by \",\" in parts
+get length of parts in n
+display \" \" n \":\" parts:0 \"|\"
+split \"a::b\" by \"::\" in parts
+display parts:0 parts:1
+";
+    assert_emits!(
+        compile!(src),
+        "VAR_PARTS = utf8_split_list(\",a,,b,\", \",\");"
+    );
+    assert_eq!("5:[][a][][b][] 1:|ab", run!("split-empty", src));
+}
+
+#[test]
+fn test_library_stmts() {
+    let src = "data:
+lib is number
+n is number
+name is text
+procedure:
+load library \"LIBPATH\" as lib
+display errorcode \" \"
+store 20 in n
+store \"bob\" in name
+call library function \"add_length\" from lib with n name 1
+display n \" \"
+call library function \"nope\" from lib
+display errorcode \" \"
+unload library lib
+call library function \"add_length\" from lib with n
+display errorcode \" \"
+load library \"/no/such/lib.so\" as n
+display errorcode \" \" n
+";
+    let cpp = compile!(src);
+    assert_emits!(cpp, "VAR_LIB = ldpl_load_library(\"LIBPATH\");");
+    assert_emits!(
+        cpp,
+        "ldpl_call_library(VAR_LIB, \"add_length\", VAR_N, VAR_NAME, LPVAR_0);"
+    );
+    assert_emits!(cpp, "ldpl_call_library(VAR_LIB, \"nope\");");
+    assert_emits!(cpp, "ldpl_unload_library(VAR_LIB);");
+
+    // dlopen() is Unix only
+    if cfg!(unix) {
+        let dir = env::temp_dir().join(format!("ldpl-test-{}-library", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (lib_src, lib) = (dir.join("lib.cpp"), dir.join("libadd.so"));
+        fs::write(
+            &lib_src,
+            "#include <string.h>
+extern \"C\" {
+typedef struct { int is_text; double number; const char* text; } ldpl_ffi_arg;
+void add_length(ldpl_ffi_arg* args, int count) {
+    args[0].number += strlen(args[1].text) * args[2].number;
+}
+}
+",
+        )
+        .unwrap();
+        let status = Command::new("c++")
+            .args(["-shared", "-fPIC", "-o"])
+            .arg(&lib)
+            .arg(&lib_src)
+            .status()
+            .unwrap();
+        assert!(status.success(), "C++ build failed");
+
+        let src = src.replace("LIBPATH", &lib.to_string_lossy());
+        let compiler = compiler::compile(&src).unwrap();
+        let bin = dir.join("main-bin").to_string_lossy().to_string();
+        compiler.build(&bin, Some(bin.clone()), true).unwrap();
+        let out = Command::new(&bin).output().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!("0 23 1 1 1 0", String::from_utf8(out.stdout).unwrap());
+    }
+
+    let err = compile_err!(src.replace("from lib with n name 1", "from name"));
+    assert!(err.contains("needs a number handle"), "{}", err);
+    let err = compile_err!(
+        "data:\nl is text list\nprocedure:\ncall library function \"f\" from 1 with l\n"
+    );
+    assert!(err.contains("only passes numbers and text"), "{}", err);
+    let err = compile_err!("data:\nt is text\nprocedure:\nload library \"x.so\" as t\n");
+    assert!(err.contains("stores a handle into a number"), "{}", err);
+}
@@ -18,7 +18,7 @@ macro_rules! parse_one {
 // parse a single expression
 macro_rules! parse_expr {
     ($e:expr) => {
-        LDPLParser::parse(Rule::expr, &$e).unwrap().nth(0).unwrap()
+        LDPLParser::parse(Rule::expr, &$e).unwrap().next().unwrap()
     };
 }
 
@@ -75,11 +75,30 @@ fn test_newline() {
 
     let crlf = iter.next().unwrap();
     assert_eq!("crlf", crlf.as_str());
-    assert_eq!(Rule::linefeed, crlf.as_rule());
+    assert_eq!(Rule::crlf, crlf.as_rule());
 
     let var = iter.next().unwrap();
     assert_eq!("goodbye", var.as_str());
     assert_eq!(Rule::var, var.as_rule());
+
+    // names that start with lf or crlf are still names
+    let node = parse_one!("display lf lfs crlf-count");
+    let parts: Vec<_> = node.into_inner().map(|p| p.as_rule()).collect();
+    assert_eq!(vec![Rule::linefeed, Rule::var, Rule::var], parts);
+}
+
+#[test]
+fn test_display_error() {
+    let node = parse_one!("display error \"oops: \" errortext crlf");
+    assert_eq!(Rule::display_error_stmt, node.as_rule());
+    let parts: Vec<_> = node.into_inner().map(|p| p.as_rule()).collect();
+    assert_eq!(vec![Rule::text, Rule::var, Rule::crlf], parts);
+
+    // names that start with error are still names
+    let node = parse_one!("display errorcode errortext");
+    assert_eq!(Rule::display_stmt, node.as_rule());
+    let node = parse_one!("display error");
+    assert_eq!(Rule::display_stmt, node.as_rule());
 }
 
 #[test]
@@ -137,25 +156,25 @@ fn test_var() {
 fn test_store_stmt() {
     let node = parse_one!("store 3.14 in x");
     assert_eq!(Rule::store_stmt, node.as_rule());
-    let node = node.into_inner().nth(0).unwrap();
+    let node = node.into_inner().next().unwrap();
     assert_eq!(Rule::number, node.as_rule());
     assert_eq!("3.14", node.as_str());
 
     let node = parse_one!("store 314 in x");
     assert_eq!(Rule::store_stmt, node.as_rule());
-    let node = node.into_inner().nth(0).unwrap();
+    let node = node.into_inner().next().unwrap();
     assert_eq!(Rule::number, node.as_rule());
     assert_eq!("314", node.as_str());
 
     let node = parse_one!("store +10213 in x");
     assert_eq!(Rule::store_stmt, node.as_rule());
-    let node = node.into_inner().nth(0).unwrap();
+    let node = node.into_inner().next().unwrap();
     assert_eq!(Rule::number, node.as_rule());
     assert_eq!("+10213", node.as_str());
 
     let node = parse_one!("store -12051205.0325035 in x");
     assert_eq!(Rule::store_stmt, node.as_rule());
-    let node = node.into_inner().nth(0).unwrap();
+    let node = node.into_inner().next().unwrap();
     assert_eq!(Rule::number, node.as_rule());
     assert_eq!("-12051205.0325035", node.as_str());
 }
@@ -183,3 +202,406 @@ fn test_user_stmt() {
     assert_eq!(Rule::var, node.as_rule());
     assert_eq!("to", node.as_str());
 }
+
+#[test]
+fn test_line_continuation() {
+    let node = parse_one!("display \"one \" \\\n    \"two \" \\\n    three");
+    assert_eq!(Rule::display_stmt, node.as_rule());
+    let parts: Vec<_> = node.into_inner().map(|p| p.as_str()).collect();
+    assert_eq!(vec![r#""one ""#, r#""two ""#, "three"], parts);
+
+    let node = parse_one!("in greeting join \"hello\" \\\r\n    \", \" name");
+    assert_eq!(Rule::join_stmt, node.as_rule());
+    let mut iter = node.into_inner();
+    assert_eq!("greeting", iter.next().unwrap().as_str());
+    let parts: Vec<_> = iter.map(|p| p.as_str()).collect();
+    assert_eq!(vec![r#""hello""#, r#"", ""#, "name"], parts);
+
+    // continuation ends an ident, too
+    let node = parse_one!("display name\\\n    crlf");
+    let parts: Vec<_> = node.into_inner().map(|p| p.as_rule()).collect();
+    assert_eq!(vec![Rule::var, Rule::crlf], parts);
+
+    // errors still point at the physical line
+    let err = LDPLParser::parse(
+        Rule::program,
+        "procedure:\ndisplay 1 \\\n    2\ndisplay \"oops\n",
+    )
+    .unwrap_err();
+    match err.line_col {
+        pest::error::LineColLocation::Pos((line, _)) => assert_eq!(4, line),
+        pest::error::LineColLocation::Span((line, _), _) => assert_eq!(4, line),
+    }
+}
+
+#[test]
+fn test_semicolon_separator() {
+    let node = LDPLParser::parse(
+        Rule::procedure_section,
+        "procedure:\nstore 1 in x; display x crlf\n",
+    )
+    .unwrap()
+    .next()
+    .unwrap();
+    let stmts: Vec<_> = node.into_inner().map(|p| p.as_rule()).collect();
+    assert_eq!(vec![Rule::store_stmt, Rule::display_stmt], stmts);
+
+    // trailing ; and blocks
+    let node = parse_one!("if x is equal to 1 then; display \"one\"; else; display \"?\"; end if");
+    assert_eq!(Rule::if_stmt, node.as_rule());
+    let stmts: Vec<_> = node.into_inner().skip(1).map(|p| p.as_rule()).collect();
+    assert_eq!(
+        vec![Rule::display_stmt, Rule::else_stmt, Rule::display_stmt],
+        stmts
+    );
+
+    // errors point at the right column
+    let err =
+        LDPLParser::parse(Rule::program, "procedure:\ndisplay 1; display \"oops\n").unwrap_err();
+    match err.line_col {
+        pest::error::LineColLocation::Pos((line, col)) => assert_eq!((2, 20), (line, col)),
+        pest::error::LineColLocation::Span((line, col), _) => assert_eq!((2, 20), (line, col)),
+    }
+}
+
+#[test]
+fn test_type_name_case() {
+    for src in &[
+        "x is number list",
+        "x is NUMBER LIST",
+        "x is Number List",
+        "x is nUmBeR   lIsT",
+    ] {
+        let node = LDPLParser::parse(Rule::type_def, src)
+            .unwrap()
+            .next()
+            .unwrap();
+        let typename = node.into_inner().nth(1).unwrap();
+        assert_eq!(Rule::type_name, typename.as_rule());
+        assert_eq!(&src[5..], typename.as_str());
+    }
+}
+
+#[test]
+fn test_messy_sub_sections() {
+    let node = parse_one!(
+        "sub greet
+    # takes two params
+
+    parameters:
+    # who to greet
+
+    name is text    # their name
+
+    # how many times
+    times is number
+
+    local data:
+    # scratch
+
+    i is number
+    procedure:
+    display name crlf
+end sub"
+    );
+    assert_eq!(Rule::sub_def_stmt, node.as_rule());
+    let mut iter = node.into_inner();
+    assert_eq!("greet", iter.next().unwrap().as_str());
+
+    let params = iter.next().unwrap();
+    assert_eq!(Rule::sub_param_section, params.as_rule());
+    let names: Vec<_> = params
+        .into_inner()
+        .map(|def| def.into_inner().next().unwrap().as_str())
+        .collect();
+    assert_eq!(vec!["name", "times"], names);
+
+    let data = iter.next().unwrap();
+    assert_eq!(Rule::sub_data_section, data.as_rule());
+    assert_eq!(1, data.into_inner().count());
+
+    assert_eq!(Rule::display_stmt, iter.next().unwrap().as_rule());
+    assert!(iter.next().is_none());
+
+    // empty sections
+    let node =
+        parse_one!("sub nothing\nparameters:\n# none yet\n\nlocal data:\nprocedure:\nend sub");
+    let rules: Vec<_> = node.into_inner().map(|p| p.as_rule()).collect();
+    assert_eq!(
+        vec![Rule::ident, Rule::sub_param_section, Rule::sub_data_section],
+        rules
+    );
+}
+
+#[test]
+fn test_equals_ignoring_case() {
+    let node = parse_one!("if name equals ignoring case \"BOB\" then\n    display name\nend if");
+    let test = node.into_inner().next().unwrap();
+    let one = test.into_inner().next().unwrap();
+    assert_eq!(Rule::one_test_expr, one.as_rule());
+    let rules: Vec<_> = one.into_inner().map(|p| p.as_rule()).collect();
+    assert_eq!(
+        vec![Rule::var, Rule::equal_ignore_case_expr, Rule::text],
+        rules
+    );
+
+    // plain EQUALS is unchanged
+    let node = parse_one!("if name equals \"bob\" then\nend if");
+    let one = node
+        .into_inner()
+        .next()
+        .unwrap()
+        .into_inner()
+        .next()
+        .unwrap();
+    let rules: Vec<_> = one.into_inner().map(|p| p.as_rule()).collect();
+    assert_eq!(vec![Rule::var, Rule::equal_expr, Rule::text], rules);
+}
+
+#[test]
+fn test_delete_key_stmt() {
+    let node = parse_one!("delete key \"bob\" from ages");
+    assert_eq!(Rule::delete_key_stmt, node.as_rule());
+    let rules: Vec<_> = node.into_inner().map(|p| p.as_rule()).collect();
+    assert_eq!(vec![Rule::text, Rule::var], rules);
+
+    // DELETE LAST ELEMENT and DELETE INDEX are still their own thing
+    assert_eq!(
+        Rule::delete_stmt,
+        parse_one!("delete last element of l").as_rule()
+    );
+    assert_eq!(
+        Rule::delete_at_stmt,
+        parse_one!("delete index 0 from l").as_rule()
+    );
+}
+
+#[test]
+fn test_has_key() {
+    let node = parse_one!("if ages has key \"bob\" and not ages has key name then\nend if");
+    let test = node.into_inner().next().unwrap();
+    let and = test.into_inner().next().unwrap();
+    assert_eq!(Rule::and_test_expr, and.as_rule());
+    let mut iter = and.into_inner();
+    let has = iter.next().unwrap();
+    assert_eq!(Rule::has_key_test_expr, has.as_rule());
+    let rules: Vec<_> = has.into_inner().map(|p| p.as_rule()).collect();
+    assert_eq!(vec![Rule::var, Rule::text], rules);
+}
+
+#[test]
+fn test_chained_comparison() {
+    let node = parse_one!(
+        "if 1 is less than x is less than or equal to 10 then
+    display x
+end if"
+    );
+    let test = node.into_inner().next().unwrap();
+    assert_eq!(Rule::test_expr, test.as_rule());
+    let one = test.into_inner().next().unwrap();
+    assert_eq!(Rule::one_test_expr, one.as_rule());
+    let rules: Vec<_> = one.into_inner().map(|p| p.as_rule()).collect();
+    assert_eq!(
+        vec![
+            Rule::number,
+            Rule::lt_expr,
+            Rule::var,
+            Rule::lte_expr,
+            Rule::number
+        ],
+        rules
+    );
+
+    // chains still combine with OR
+    let node = parse_one!("while a is less than b is less than c or d is equal to 1 do\nrepeat");
+    let test = node.into_inner().next().unwrap();
+    assert_eq!(
+        Rule::or_test_expr,
+        test.into_inner().next().unwrap().as_rule()
+    );
+}
+
+#[test]
+fn test_short_comparisons() {
+    let cmp = |test: &str| {
+        let src = format!("while {} do\nrepeat", test);
+        let node = parse_one!(src);
+        assert_eq!(Rule::while_stmt, node.as_rule());
+        let test = node.into_inner().next().unwrap();
+        let one = test.into_inner().next().unwrap();
+        assert_eq!(Rule::one_test_expr, one.as_rule());
+        one.into_inner().map(|p| p.as_rule()).collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        vec![Rule::var, Rule::equal_expr, Rule::number],
+        cmp("x is 0")
+    );
+    assert_eq!(
+        vec![Rule::var, Rule::not_equal_expr, Rule::text],
+        cmp("x is not \"\"")
+    );
+    // a name starting with `not` isn't IS NOT
+    assert_eq!(
+        vec![Rule::var, Rule::equal_expr, Rule::var],
+        cmp("x is nothing")
+    );
+    assert_eq!(
+        vec![Rule::var, Rule::gt_expr, Rule::var],
+        cmp("x is greater than y")
+    );
+    assert_eq!(
+        vec![Rule::var, Rule::lt_expr, Rule::var],
+        cmp("x is less than y")
+    );
+    assert_eq!(
+        vec![Rule::var, Rule::gte_expr, Rule::var],
+        cmp("x is greater than or equal to y")
+    );
+    assert_eq!(
+        vec![Rule::var, Rule::lte_expr, Rule::var],
+        cmp("x is less than or equal to y")
+    );
+
+    // the long forms still work
+    assert_eq!(
+        vec![Rule::var, Rule::equal_expr, Rule::var],
+        cmp("x is equal to y")
+    );
+    assert_eq!(
+        vec![Rule::var, Rule::not_equal_expr, Rule::var],
+        cmp("x is not equal to y")
+    );
+    assert_eq!(
+        vec![Rule::var, Rule::equal_expr, Rule::var],
+        cmp("x equals y")
+    );
+}
+
+#[test]
+fn test_not_test() {
+    let node = parse_one!("if not (a is equal to b) then\nend if");
+    let test = node.into_inner().next().unwrap();
+    let not = test.into_inner().next().unwrap();
+    assert_eq!(Rule::not_test_expr, not.as_rule());
+    let inner = not.into_inner().next().unwrap();
+    assert_eq!(Rule::test_expr, inner.as_rule());
+    assert_eq!(
+        Rule::one_test_expr,
+        inner.into_inner().next().unwrap().as_rule()
+    );
+
+    // NOT binds tighter than AND
+    let node = parse_one!("if not a is equal to b and c is equal to d then\nend if");
+    let test = node.into_inner().next().unwrap();
+    let and = test.into_inner().next().unwrap();
+    assert_eq!(Rule::and_test_expr, and.as_rule());
+    assert_eq!(
+        Rule::not_test_expr,
+        and.into_inner().next().unwrap().as_rule()
+    );
+
+    // variables can still start with "not"
+    let node = parse_one!("if nothing is equal to 0 then\nend if");
+    let test = node.into_inner().next().unwrap();
+    let one = test.into_inner().next().unwrap();
+    assert_eq!(Rule::one_test_expr, one.as_rule());
+    assert_eq!("nothing", one.into_inner().next().unwrap().as_str());
+}
+
+#[test]
+fn test_else_if_chain() {
+    let node = parse_one!(
+        "if n is equal to 0 then
+    display \"zero\"
+else if n is equal to 1 then
+    display \"one\"
+else if n is equal to 2 then
+    display \"two\"
+else if n is equal to 3 then
+    display \"three\"
+else
+    display \"many\"
+end if"
+    );
+    assert_eq!(Rule::if_stmt, node.as_rule());
+    let stmts: Vec<_> = node.into_inner().skip(1).collect();
+    let rules: Vec<_> = stmts.iter().map(|p| p.as_rule()).collect();
+    assert_eq!(
+        vec![
+            Rule::display_stmt,
+            Rule::else_stmt,
+            Rule::display_stmt,
+            Rule::else_stmt,
+            Rule::display_stmt,
+            Rule::else_stmt,
+            Rule::display_stmt,
+            Rule::else_stmt,
+            Rule::display_stmt,
+        ],
+        rules
+    );
+    // ELSE IFs carry their test, the final ELSE doesn't
+    let tests: Vec<_> = stmts
+        .iter()
+        .filter(|p| p.as_rule() == Rule::else_stmt)
+        .map(|p| p.clone().into_inner().count())
+        .collect();
+    assert_eq!(vec![1, 1, 1, 0], tests);
+}
+
+#[test]
+fn test_get_length_stmt() {
+    for src in [
+        "get length of ages in n",
+        "GET LENGTH OF ages:\"bob\" IN n",
+        "store length of ages in n",
+        "Store Length Of names:0 In n",
+    ] {
+        let node = parse_one!(src);
+        assert_eq!(Rule::get_length_stmt, node.as_rule(), "{}", src);
+        let parts: Vec<_> = node.into_inner().map(|p| p.as_rule()).collect();
+        assert_eq!(vec![Rule::var, Rule::var], parts, "{}", src);
+    }
+
+    // a variable named length is still a plain STORE
+    let node = parse_one!("store length in n");
+    assert_eq!(Rule::store_stmt, node.as_rule());
+}
+
+#[test]
+fn test_solve_call() {
+    let node = parse_one!("in r solve sqrt of x + abs(y - 1)");
+    let expr = node.into_inner().nth(1).unwrap();
+    let parts: Vec<_> = expr.into_inner().map(|p| p.as_rule()).collect();
+    assert_eq!(
+        vec![Rule::solve_call, Rule::add_op, Rule::solve_call],
+        parts
+    );
+
+    // plain variables are still variables
+    let node = parse_one!("in r solve sqrt + of");
+    let expr = node.into_inner().nth(1).unwrap();
+    let parts: Vec<_> = expr.into_inner().map(|p| p.as_rule()).collect();
+    assert_eq!(vec![Rule::var, Rule::add_op, Rule::var], parts);
+}
+
+#[test]
+fn test_library_stmts() {
+    let node = parse_one!("call library function \"f\" from lib with 1 \"two\" x");
+    assert_eq!(Rule::call_library_stmt, node.as_rule());
+    let rules: Vec<_> = node.into_inner().map(|p| p.as_rule()).collect();
+    assert_eq!(
+        vec![Rule::text, Rule::var, Rule::number, Rule::text, Rule::var],
+        rules
+    );
+
+    // a sub named `library` is still a plain CALL
+    let node = parse_one!("call library with 1");
+    assert_eq!(Rule::call_stmt, node.as_rule());
+
+    let node = parse_one!("load library \"libm.so\" as m");
+    assert_eq!(Rule::load_library_stmt, node.as_rule());
+    let node = parse_one!("unload library m");
+    assert_eq!(Rule::unload_library_stmt, node.as_rule());
+}
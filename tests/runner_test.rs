@@ -0,0 +1,74 @@
+use ldpl::runner::Runner;
+use std::{env, fs, os::unix::fs::PermissionsExt, path::PathBuf, process::Command};
+
+// write an executable shell script and return its path
+fn script(name: &str, body: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("ldpl-runner-{}-{}", std::process::id(), name));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("prog");
+    fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+// is there a process with this pid?
+fn alive(pid: u32) -> bool {
+    Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .status()
+        .unwrap()
+        .success()
+}
+
+#[test]
+fn test_runner_lifecycle() {
+    let bin = script("lifecycle", "sleep 30");
+    let mut runner = Runner::new(bin.to_str().unwrap());
+    assert!(!runner.is_running());
+    assert_eq!(None, runner.id());
+
+    runner.start().unwrap();
+    assert!(runner.is_running());
+    let first = runner.id().unwrap();
+
+    // start() leaves a running program alone
+    runner.start().unwrap();
+    assert_eq!(Some(first), runner.id());
+
+    runner.restart().unwrap();
+    let second = runner.id().unwrap();
+    assert_ne!(first, second);
+    assert!(!alive(first));
+    assert!(runner.is_running());
+
+    runner.stop().unwrap();
+    assert!(!runner.is_running());
+    assert!(!alive(second));
+
+    // stopping twice is fine
+    runner.stop().unwrap();
+
+    // dropping the runner kills the program
+    runner.start().unwrap();
+    let third = runner.id().unwrap();
+    drop(runner);
+    assert!(!alive(third));
+
+    fs::remove_dir_all(bin.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn test_runner_program_exits() {
+    let bin = script("exits", "exit 0");
+    let mut runner = Runner::new(bin.to_str().unwrap());
+    runner.start().unwrap();
+    runner.wait().unwrap();
+    assert!(!runner.is_running());
+
+    // restart after the program finished on its own
+    runner.restart().unwrap();
+    runner.wait().unwrap();
+    assert!(!runner.is_running());
+
+    fs::remove_dir_all(bin.parent().unwrap()).unwrap();
+}